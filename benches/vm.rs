@@ -0,0 +1,67 @@
+//! VM benchmarks, mostly for comparing value representations. To compare
+//! NaN-boxing with the default enum:
+//!
+//! ```text
+//! cargo bench --bench vm -- --save-baseline enum
+//! cargo bench --bench vm --features nan-boxing -- --baseline enum
+//! ```
+
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+
+use first_interpreter::chunk::Chunk;
+use first_interpreter::compiler;
+use first_interpreter::expr::Ast;
+use first_interpreter::parser::Parser;
+use first_interpreter::scanner::Scanner;
+use first_interpreter::vm::Vm;
+
+// Nothing is printed, so only the VM is measured.
+const PROGRAMS: [(&str, &str); 3] = [
+    (
+        "arithmetic",
+        "var total = 0;
+        for (var i = 0; i < 10000; i = i + 1) {
+          total = total + i * 2 - i / 2;
+        }",
+    ),
+    (
+        "locals",
+        "{
+          var a = 0;
+          var b = 1;
+          for (var i = 0; i < 10000; i = i + 1) {
+            var next = a + b;
+            a = b;
+            b = next > 1000000 ? 0 : next;
+          }
+        }",
+    ),
+    (
+        "strings",
+        "var s = \"\";
+        for (var i = 0; i < 1000; i = i + 1) {
+          s = s == \"xxxxxxxxxx\" ? \"\" : s + \"x\";
+        }",
+    ),
+];
+
+fn compile(source: &str) -> Chunk {
+    let mut scanner = Scanner::new(source);
+    let mut ast = Ast::new();
+    let statements = Parser::new(scanner.scan_tokens(), &mut ast)
+        .parse_program()
+        .unwrap();
+    compiler::compile(&ast, &statements).unwrap()
+}
+
+fn vm(c: &mut Criterion) {
+    for (name, source) in PROGRAMS {
+        let chunk = compile(source);
+        c.bench_function(name, |b| b.iter(|| Vm::new().run(&chunk).unwrap()));
+    }
+}
+
+criterion_group!(benches, vm);
+criterion_main!(benches);
@@ -1,55 +1,239 @@
 use crate::expr;
 use crate::scanner;
+use crate::stmt;
+use crate::stmt::Stmt;
 
 pub struct AstPrinter;
 
 impl expr::Visitor for AstPrinter {
     type Result = String;
 
-    fn visit_expr(&mut self, expr: &expr::Expr) -> Self::Result {
+    fn visit_expr(&mut self, ast: &expr::Ast, expr: expr::NodeId) -> Self::Result {
         use expr::Expr::*;
-        match expr {
+        match &ast[expr] {
+            Assign { name, value, .. } => {
+                format!("(= {} {})", name, self.visit_expr(ast, *value))
+            }
             Binary {
                 left,
                 operator,
                 right,
+                ..
             } => {
                 format!(
                     "({} {} {})",
-                    token_to_string(operator),
-                    self.visit_expr(left),
-                    self.visit_expr(right)
+                    operator,
+                    self.visit_expr(ast, *left),
+                    self.visit_expr(ast, *right)
+                )
+            }
+            Call {
+                callee, arguments, ..
+            } => {
+                let mut printed = format!("(call {}", self.visit_expr(ast, *callee));
+                for argument in arguments {
+                    printed.push(' ');
+                    printed.push_str(&self.visit_expr(ast, *argument));
+                }
+                printed.push(')');
+                printed
+            }
+            Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                format!(
+                    "(?: {} {} {})",
+                    self.visit_expr(ast, *condition),
+                    self.visit_expr(ast, *then_branch),
+                    self.visit_expr(ast, *else_branch)
                 )
             }
+            // The body's statements follow the parameters, e.g.
+            // `(fun (a b) (return (+ a b)))`.
+            Function { params, body, .. } => {
+                use stmt::Visitor;
+                let params: Vec<_> = params.iter().map(|param| param.to_string()).collect();
+                let mut printed = format!("(fun ({})", params.join(" "));
+                if let Stmt::Block(statements) = &ast[*body] {
+                    for stmt in statements {
+                        printed.push(' ');
+                        printed.push_str(&self.visit_stmt(ast, *stmt));
+                    }
+                }
+                printed.push(')');
+                printed
+            }
+            Get { object, name, .. } => format!("(. {} {})", self.visit_expr(ast, *object), name),
             Grouping(expr) => {
-                format!("({})", self.visit_expr(expr))
+                format!("({})", self.visit_expr(ast, *expr))
             }
-            Unary { operator, right } => {
-                format!("({} {})", token_to_string(operator), self.visit_expr(right))
-            },
-            LiteralNumber(number) => {
-                format!("{}", number)
+            Index { object, index, .. } => format!(
+                "(index {} {})",
+                self.visit_expr(ast, *object),
+                self.visit_expr(ast, *index)
+            ),
+            List(elements) => {
+                let elements: Vec<_> = elements
+                    .iter()
+                    .map(|element| self.visit_expr(ast, *element))
+                    .collect();
+                format!("[{}]", elements.join(" "))
             }
-            LiteralString(ss) => {
-                format!("{}", ss)
+            Map(entries) => {
+                let entries: Vec<_> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        format!(
+                            "{} {}",
+                            self.visit_expr(ast, *key),
+                            self.visit_expr(ast, *value)
+                        )
+                    })
+                    .collect();
+                format!("{{{}}}", entries.join(" "))
             }
-            LiteralBool(bb) => {
+            Logical {
+                left,
+                operator,
+                right,
+            } => {
+                format!(
+                    "({} {} {})",
+                    operator,
+                    self.visit_expr(ast, *left),
+                    self.visit_expr(ast, *right)
+                )
+            }
+            SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => format!(
+                "(= (index {} {}) {})",
+                self.visit_expr(ast, *object),
+                self.visit_expr(ast, *index),
+                self.visit_expr(ast, *value)
+            ),
+            Unary {
+                operator, right, ..
+            } => {
+                format!("({} {})", operator, self.visit_expr(ast, *right))
+            }
+            Variable { name, .. } => name.to_string(),
+            LiteralNumber { value: number, .. } => {
+                format!("{}", number)
+            }
+            LiteralString { value: ss, .. } => format!("\"{}\"", ss),
+            LiteralBool { value: bb, .. } => {
                 format!("{}", bb)
             }
-            Nil => {
+            Nil { .. } => {
                 "nil".to_owned()
             }
         }
     }
 }
 
-pub fn token_to_string(annotated: &scanner::AnnotatedToken) -> String {
+/// Statements print like expressions, headed by their keyword, e.g.
+/// `(while (< i 3) (block (print i) (; (= i (+ i 1)))))`. Missing parts of a
+/// `for` loop print as `_`.
+impl stmt::Visitor for AstPrinter {
+    type Result = String;
+
+    fn visit_stmt(&mut self, ast: &expr::Ast, stmt: stmt::StmtId) -> Self::Result {
+        use expr::Visitor;
+        match &ast[stmt] {
+            Stmt::Block(statements) => {
+                let mut printed = "(block".to_owned();
+                for stmt in statements {
+                    printed.push(' ');
+                    printed.push_str(&self.visit_stmt(ast, *stmt));
+                }
+                printed.push(')');
+                printed
+            }
+            Stmt::Break { .. } => "(break)".to_owned(),
+            Stmt::Continue { .. } => "(continue)".to_owned(),
+            Stmt::Expression { expr, .. } => format!("(; {})", self.visit_expr(ast, *expr)),
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                let initializer = match initializer {
+                    Some(initializer) => self.visit_stmt(ast, *initializer),
+                    None => "_".to_owned(),
+                };
+                let mut optional_expr = |expr: &Option<expr::NodeId>| match expr {
+                    Some(expr) => self.visit_expr(ast, *expr),
+                    None => "_".to_owned(),
+                };
+                let condition = optional_expr(condition);
+                let increment = optional_expr(increment);
+                format!(
+                    "(for {} {} {} {})",
+                    initializer,
+                    condition,
+                    increment,
+                    self.visit_stmt(ast, *body)
+                )
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let condition = self.visit_expr(ast, *condition);
+                let then_branch = self.visit_stmt(ast, *then_branch);
+                match else_branch {
+                    Some(else_branch) => format!(
+                        "(if {} {} {})",
+                        condition,
+                        then_branch,
+                        self.visit_stmt(ast, *else_branch)
+                    ),
+                    None => format!("(if {} {})", condition, then_branch),
+                }
+            }
+            Stmt::Print { expr, .. } => format!("(print {})", self.visit_expr(ast, *expr)),
+            Stmt::Return { value, .. } => match value {
+                Some(value) => format!("(return {})", self.visit_expr(ast, *value)),
+                None => "(return)".to_owned(),
+            },
+            Stmt::Var {
+                name, initializer, ..
+            } => match initializer {
+                Some(initializer) => {
+                    format!("(var {} {})", name, self.visit_expr(ast, *initializer))
+                }
+                None => format!("(var {})", name),
+            },
+            Stmt::While {
+                condition, body, ..
+            } => format!(
+                "(while {} {})",
+                self.visit_expr(ast, *condition),
+                self.visit_stmt(ast, *body)
+            ),
+        }
+    }
+}
+
+/// How a token is written in Lox source. Also used to show operators and
+/// constants in bytecode disassembly.
+pub fn token_to_string(token: &scanner::Token) -> String {
     use scanner::Token::*;
-    match annotated.token {
+    match token {
         LeftParen => "(".to_owned(),
         RightParen => ")".to_owned(),
         LeftBrace => "{".to_owned(),
         RightBrace => "}".to_owned(),
+        LeftBracket => "[".to_owned(),
+        RightBracket => "]".to_owned(),
         Comma => ",".to_owned(),
         Dot => ".".to_owned(),
         Minus => "-".to_owned(),
@@ -57,6 +241,8 @@ pub fn token_to_string(annotated: &scanner::AnnotatedToken) -> String {
         Semicolon => ";".to_owned(),
         Slash => "/".to_owned(),
         Star => "*".to_owned(),
+        Question => "?".to_owned(),
+        Colon => ":".to_owned(),
 
         Bang => "!".to_owned(),
         BangEqual => "!=".to_owned(),
@@ -68,7 +254,9 @@ pub fn token_to_string(annotated: &scanner::AnnotatedToken) -> String {
         LessEqual => "<=".to_owned(),
 
         And => "and".to_owned(),
+        Break => "break".to_owned(),
         Class => "class".to_owned(),
+        Continue => "continue".to_owned(),
         Else => "else".to_owned(),
         False => "false".to_owned(),
         Fun => "fun".to_owned(),
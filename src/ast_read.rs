@@ -0,0 +1,552 @@
+use crate::expr::Ast;
+use crate::expr::BinaryOp;
+use crate::expr::Expr;
+use crate::expr::LogicalOp;
+use crate::expr::NodeId;
+use crate::expr::UnaryOp;
+use crate::symbol::Symbol;
+
+/// Reads the S-expressions that `AstPrinter` produces back into an `Ast`.
+///
+/// Nodes are allocated in the same order the parser allocates them, so reading
+/// the printed form of a parsed expression gives an identical arena (as long
+/// as everything was on one line, since line numbers are taken from the
+/// S-expression text).
+///
+/// Functions can't be read back, since their bodies are statements.
+pub fn read_expr(input: &str, ast: &mut Ast) -> anyhow::Result<NodeId> {
+    let mut reader = AstReader {
+        input,
+        byte_offset: 0,
+        line_number: 1,
+        ast,
+    };
+    let expr = reader.expr()?;
+    reader.skip_whitespace();
+    if reader.byte_offset != input.len() {
+        anyhow::bail!(
+            "[line {}] Unexpected input after expression.",
+            reader.line_number
+        );
+    }
+    Ok(expr)
+}
+
+// What `AstPrinter` puts at the head of a conditional expression.
+const CONDITIONAL_HEAD: &str = "?:";
+// ... and of an assignment.
+const ASSIGN_HEAD: &str = "=";
+// ... a call.
+const CALL_HEAD: &str = "call";
+// ... a property access.
+const GET_HEAD: &str = ".";
+// ... and indexing.
+const INDEX_HEAD: &str = "index";
+
+struct AstReader<'a, 'ast> {
+    input: &'a str,
+    byte_offset: usize,
+    line_number: usize,
+    ast: &'ast mut Ast,
+}
+
+impl<'a, 'ast> AstReader<'a, 'ast> {
+    fn expr(&mut self) -> anyhow::Result<NodeId> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('(') => self.list(),
+            Some('[') => self.list_literal(),
+            Some('{') => self.map_literal(),
+            Some('"') => self.string(),
+            Some(ch @ (')' | ']' | '}')) => {
+                anyhow::bail!("[line {}] Unexpected '{}'.", self.line_number, ch)
+            }
+            Some(_) => {
+                let line_number = self.line_number;
+                let atom = self.atom();
+                let expr = match atom {
+                    "nil" => Expr::Nil { line_number },
+                    "true" => Expr::LiteralBool {
+                        value: true,
+                        line_number,
+                    },
+                    "false" => Expr::LiteralBool {
+                        value: false,
+                        line_number,
+                    },
+                    _ => match atom.parse() {
+                        Ok(value) => Expr::LiteralNumber { value, line_number },
+                        Err(_) if is_identifier(atom) => Expr::Variable {
+                            name: Symbol::intern(atom),
+                            line_number,
+                        },
+                        Err(_) => {
+                            anyhow::bail!("[line {}] Unexpected atom '{}'.", self.line_number, atom)
+                        }
+                    },
+                };
+                Ok(self.ast.add(expr))
+            }
+            None => anyhow::bail!("[line {}] Expected an expression.", self.line_number),
+        }
+    }
+
+    // Either an operator application like `(+ 1 2)`, or a grouping like `(1)`.
+    fn list(&mut self) -> anyhow::Result<NodeId> {
+        self.advance();
+        self.skip_whitespace();
+
+        let line_number = self.line_number;
+        let head = match self.peek() {
+            Some('(' | '"') | None => None,
+            Some(_) => {
+                let start = self.byte_offset;
+                let atom = self.atom();
+                if atom == CONDITIONAL_HEAD
+                    || atom == ASSIGN_HEAD
+                    || atom == GET_HEAD
+                    || BinaryOp::from_lexeme(atom).is_some()
+                    || LogicalOp::from_lexeme(atom).is_some()
+                {
+                    Some(atom)
+                } else if (atom == CALL_HEAD || atom == INDEX_HEAD) && !self.at_close_paren() {
+                    // `(call)` on its own is a grouped variable named `call`.
+                    Some(atom)
+                } else {
+                    // Not an operator, so it's the start of a grouped
+                    // expression.
+                    self.byte_offset = start;
+                    None
+                }
+            }
+        };
+
+        let expr = match head {
+            None => {
+                let inner = self.expr()?;
+                Expr::Grouping(inner)
+            }
+            Some(CONDITIONAL_HEAD) => {
+                let condition = self.expr()?;
+                let then_branch = self.expr()?;
+                let else_branch = self.expr()?;
+                Expr::Conditional {
+                    condition,
+                    then_branch,
+                    else_branch,
+                }
+            }
+            Some(ASSIGN_HEAD) if self.at_open_paren() => {
+                let target = self.expr()?;
+                let Expr::Index {
+                    object,
+                    index,
+                    line_number,
+                } = self.ast[target]
+                else {
+                    anyhow::bail!("[line {}] Invalid assignment target.", line_number);
+                };
+                let value = self.expr()?;
+                Expr::SetIndex {
+                    object,
+                    index,
+                    value,
+                    line_number,
+                }
+            }
+            Some(ASSIGN_HEAD) => {
+                self.skip_whitespace();
+                let name = self.atom();
+                if !is_identifier(name) {
+                    anyhow::bail!("[line {}] Can't assign to '{}'.", self.line_number, name);
+                }
+                // The parser allocates the target as a variable before it
+                // sees the `=`, so the arena has it too.
+                self.ast.add(Expr::Variable {
+                    name: Symbol::intern(name),
+                    line_number,
+                });
+                let value = self.expr()?;
+                Expr::Assign {
+                    name: Symbol::intern(name),
+                    line_number,
+                    value,
+                }
+            }
+            Some(CALL_HEAD) => {
+                let callee = self.expr()?;
+                let mut arguments = Vec::new();
+                while !self.at_close_paren() {
+                    arguments.push(self.expr()?);
+                }
+                Expr::Call {
+                    callee,
+                    line_number,
+                    arguments,
+                }
+            }
+            Some(GET_HEAD) => {
+                let object = self.expr()?;
+                self.skip_whitespace();
+                let name = self.atom();
+                if !is_identifier(name) {
+                    anyhow::bail!("[line {}] Expected a property name.", self.line_number);
+                }
+                Expr::Get {
+                    object,
+                    name: Symbol::intern(name),
+                    line_number,
+                }
+            }
+            Some(INDEX_HEAD) => {
+                let object = self.expr()?;
+                let index = self.expr()?;
+                Expr::Index {
+                    object,
+                    index,
+                    line_number,
+                }
+            }
+            Some(operator) if LogicalOp::from_lexeme(operator).is_some() => {
+                let left = self.expr()?;
+                let right = self.expr()?;
+                Expr::Logical {
+                    left,
+                    // Checked above.
+                    operator: LogicalOp::from_lexeme(operator).unwrap(),
+                    right,
+                }
+            }
+            Some(operator) => {
+                let first = self.expr()?;
+                self.skip_whitespace();
+                if self.peek() == Some(')') {
+                    let Some(operator) = UnaryOp::from_lexeme(operator) else {
+                        anyhow::bail!(
+                            "[line {}] '{}' isn't a unary operator.",
+                            line_number,
+                            operator
+                        );
+                    };
+                    Expr::Unary {
+                        operator,
+                        line_number,
+                        right: first,
+                    }
+                } else {
+                    let second = self.expr()?;
+                    Expr::Binary {
+                        left: first,
+                        // Checked above.
+                        operator: BinaryOp::from_lexeme(operator).unwrap(),
+                        line_number,
+                        right: second,
+                    }
+                }
+            }
+        };
+
+        self.skip_whitespace();
+        if self.peek() != Some(')') {
+            anyhow::bail!("[line {}] Expected ')'.", self.line_number);
+        }
+        self.advance();
+        Ok(self.ast.add(expr))
+    }
+
+    // A list like `[1 2 3]`.
+    fn list_literal(&mut self) -> anyhow::Result<NodeId> {
+        self.advance();
+        let mut elements = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(']') => break,
+                None => anyhow::bail!("[line {}] Expected ']'.", self.line_number),
+                Some(_) => elements.push(self.expr()?),
+            }
+        }
+        self.advance();
+        Ok(self.ast.add(Expr::List(elements)))
+    }
+
+    // A map like `{"a" 1 "b" 2}`, with keys and values alternating.
+    fn map_literal(&mut self) -> anyhow::Result<NodeId> {
+        self.advance();
+        let mut entries = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('}') => break,
+                None => anyhow::bail!("[line {}] Expected '}}'.", self.line_number),
+                Some(_) => {
+                    let key = self.expr()?;
+                    self.skip_whitespace();
+                    if self.peek() == Some('}') {
+                        anyhow::bail!("[line {}] Expected a value for the key.", self.line_number);
+                    }
+                    entries.push((key, self.expr()?));
+                }
+            }
+        }
+        self.advance();
+        Ok(self.ast.add(Expr::Map(entries)))
+    }
+
+    fn string(&mut self) -> anyhow::Result<NodeId> {
+        // Opening quote.
+        self.advance();
+        let start = self.byte_offset;
+        while let Some(ch) = self.peek() {
+            if ch == '"' {
+                break;
+            }
+            self.advance();
+        }
+        if self.peek().is_none() {
+            anyhow::bail!("[line {}] Unterminated string.", self.line_number);
+        }
+        let contents = self.input[start..self.byte_offset].to_owned();
+        // Closing quote.
+        self.advance();
+        Ok(self.ast.add(Expr::LiteralString {
+            value: contents,
+            line_number: self.line_number,
+        }))
+    }
+
+    fn atom(&mut self) -> &'a str {
+        let start = self.byte_offset;
+        while let Some(ch) = self.peek() {
+            if ch.is_whitespace() || matches!(ch, '(' | ')' | '[' | ']' | '{' | '}') {
+                break;
+            }
+            self.advance();
+        }
+        &self.input[start..self.byte_offset]
+    }
+
+    // Also stops at the end of the input, where the caller will complain about
+    // the missing ')'.
+    fn at_close_paren(&mut self) -> bool {
+        self.skip_whitespace();
+        matches!(self.peek(), Some(')') | None)
+    }
+
+    fn at_open_paren(&mut self) -> bool {
+        self.skip_whitespace();
+        self.peek() == Some('(')
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.advance();
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.byte_offset..].chars().next()
+    }
+
+    fn advance(&mut self) {
+        if let Some(ch) = self.peek() {
+            if ch == '\n' {
+                self.line_number += 1;
+            }
+            self.byte_offset += ch.len_utf8();
+        }
+    }
+}
+
+// Same rules as the scanner's identifiers.
+fn is_identifier(atom: &str) -> bool {
+    let mut chars = atom.chars();
+    chars
+        .next()
+        .is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_print::AstPrinter;
+    use crate::expr::Visitor;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn parse(source: &str) -> (Ast, NodeId) {
+        let mut scanner = Scanner::new(source);
+        let mut ast = Ast::new();
+        let expr = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse()
+            .unwrap();
+        (ast, expr)
+    }
+
+    fn read(input: &str) -> (Ast, NodeId) {
+        let mut ast = Ast::new();
+        let expr = read_expr(input, &mut ast).unwrap();
+        (ast, expr)
+    }
+
+    #[test]
+    fn read_matches_parse() {
+        for source in [
+            "1 == 2",
+            "+1 * -2 == -1 / 4;",
+            "-+-+1",
+            "(12 + 23) * (3 - 4)",
+            "\"str\" + \"other str\"",
+            "(((nil))) != true",
+            "123.5 >= false",
+            "1 ? 2, 3 : 4 ? 5 : 6",
+            "a = b = c or d and nil",
+            "f(1, (2, 3))(call)",
+            "-Math.sqrt(x).y",
+            "(a)",
+            "[]",
+            "[1, [2, \"three\"], xs[0]][-1]",
+            "xs[i] = ys[j] = index",
+            "(index)[0]",
+            "{}",
+            "{\"a\": {1: [nil]}, b: c = d}[\"a\"]",
+        ] {
+            let (parsed_ast, parsed) = parse(source);
+            let printed = AstPrinter.visit_expr(&parsed_ast, parsed);
+            let (read_ast, read) = read(&printed);
+            assert_eq!(read_ast, parsed_ast, "reading {}", printed);
+            assert_eq!(read, parsed);
+        }
+    }
+
+    #[test]
+    fn read_golden() {
+        let (ast, expr) = read("(== (* (+ 1) (- 2))\n  (/ (- 1) 4))");
+        assert_eq!(
+            AstPrinter.visit_expr(&ast, expr),
+            "(== (* (+ 1) (- 2)) (/ (- 1) 4))"
+        );
+        let Expr::Binary { right, .. } = ast[expr] else {
+            panic!("expected binary expression");
+        };
+        assert!(matches!(ast[right], Expr::Binary { line_number: 2, .. }));
+    }
+
+    #[test]
+    fn read_errors() {
+        for input in [
+            "",
+            "(",
+            "(+ 1 2",
+            "(* 1)",
+            "(+ 1 2 3)",
+            "1foo",
+            "(= 1 2)",
+            "(. a 1)",
+            "(= (+ 1 2) 3)",
+            "[1 2",
+            "]",
+            "{1 2",
+            "{1}",
+            "\"open",
+            "1 2",
+            ")",
+        ] {
+            assert!(
+                read_expr(input, &mut Ast::new()).is_err(),
+                "reading {:?}",
+                input
+            );
+        }
+    }
+
+    // Tiny xorshift generator, so the property test is deterministic and
+    // doesn't need extra dependencies.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % bound
+        }
+    }
+
+    fn random_expr(rng: &mut Rng, ast: &mut Ast, depth: u32) -> NodeId {
+        const BINARY_OPS: [BinaryOp; 11] = [
+            BinaryOp::Comma,
+            BinaryOp::Equal,
+            BinaryOp::NotEqual,
+            BinaryOp::Greater,
+            BinaryOp::GreaterEqual,
+            BinaryOp::Less,
+            BinaryOp::LessEqual,
+            BinaryOp::Add,
+            BinaryOp::Subtract,
+            BinaryOp::Multiply,
+            BinaryOp::Divide,
+        ];
+        let choice = if depth == 0 { rng.next(4) } else { rng.next(8) };
+        let expr = match choice {
+            0 => Expr::Nil { line_number: 1 },
+            1 => Expr::LiteralBool {
+                value: rng.next(2) == 0,
+                line_number: 1,
+            },
+            2 => Expr::LiteralNumber {
+                value: rng.next(10_000) as f64 / 8.0 - 100.0,
+                line_number: 1,
+            },
+            3 => Expr::LiteralString {
+                value: format!("s {}", rng.next(100)),
+                line_number: 1,
+            },
+            4 => Expr::Grouping(random_expr(rng, ast, depth - 1)),
+            6 => {
+                let condition = random_expr(rng, ast, depth - 1);
+                let then_branch = random_expr(rng, ast, depth - 1);
+                let else_branch = random_expr(rng, ast, depth - 1);
+                Expr::Conditional {
+                    condition,
+                    then_branch,
+                    else_branch,
+                }
+            }
+            5 => Expr::Unary {
+                operator: if rng.next(2) == 0 {
+                    UnaryOp::Plus
+                } else {
+                    UnaryOp::Minus
+                },
+                line_number: 1,
+                right: random_expr(rng, ast, depth - 1),
+            },
+            _ => {
+                let left = random_expr(rng, ast, depth - 1);
+                let right = random_expr(rng, ast, depth - 1);
+                Expr::Binary {
+                    left,
+                    operator: BINARY_OPS[rng.next(BINARY_OPS.len() as u64) as usize],
+                    line_number: 1,
+                    right,
+                }
+            }
+        };
+        ast.add(expr)
+    }
+
+    #[test]
+    fn read_print_round_trip() {
+        let mut rng = Rng(0x5eed);
+        for _ in 0..500 {
+            let mut ast = Ast::new();
+            let expr = random_expr(&mut rng, &mut ast, 5);
+            let printed = AstPrinter.visit_expr(&ast, expr);
+            let (read_ast, read) = read(&printed);
+            assert_eq!(read_ast, ast, "reading {}", printed);
+            assert_eq!(read, expr);
+        }
+    }
+}
@@ -2,17 +2,31 @@ use first_interpreter::ast_print;
 use first_interpreter::expr::Visitor;
 
 fn main() -> anyhow::Result<()> {
+    use first_interpreter::expr::Ast;
+    use first_interpreter::expr::BinaryOp;
     use first_interpreter::expr::Expr::*;
-    use first_interpreter::scanner::Token::*;
-    use first_interpreter::scanner::AnnotatedToken;
-    let test = Binary {
-        left: Box::new(Unary {
-            operator: AnnotatedToken { token: Minus, line_number: 1 },
-            right: Box::new(LiteralNumber(123.0)),
-        }),
-        operator: AnnotatedToken { token: Star, line_number: 1 },
-        right: Box::new(Grouping(Box::new(LiteralNumber(45.67)))),
-    };
-    println!("{}", ast_print::AstPrinter.visit_expr(&test));
+    use first_interpreter::expr::UnaryOp;
+    let mut ast = Ast::new();
+    let number = ast.add(LiteralNumber {
+        value: 123.0,
+        line_number: 1,
+    });
+    let left = ast.add(Unary {
+        operator: UnaryOp::Minus,
+        line_number: 1,
+        right: number,
+    });
+    let number = ast.add(LiteralNumber {
+        value: 45.67,
+        line_number: 1,
+    });
+    let right = ast.add(Grouping(number));
+    let test = ast.add(Binary {
+        left,
+        operator: BinaryOp::Multiply,
+        line_number: 1,
+        right,
+    });
+    println!("{}", ast_print::AstPrinter.visit_expr(&ast, test));
     Ok(())
 }
@@ -15,7 +15,7 @@ fn main() -> anyhow::Result<()> {
     define_ast(
         &args.output_dir,
         "generated_expr",
-        &vec![
+        &[
             "Binary   : Expr left, Token operator, Expr right",
             "Grouping : Expr expression",
             "Literal  : Object value",
@@ -24,7 +24,7 @@ fn main() -> anyhow::Result<()> {
     )
 }
 
-fn define_ast(output_dir: &Path, basename: &str, types: &Vec<&str>) -> anyhow::Result<()> {
-    let path = output_dir.join(format!("{}.rs", basename));
+fn define_ast(output_dir: &Path, basename: &str, _types: &[&str]) -> anyhow::Result<()> {
+    let _path = output_dir.join(format!("{}.rs", basename));
     Ok(())
 }
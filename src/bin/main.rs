@@ -1,89 +1,414 @@
 use anyhow::bail;
 use clap::Parser;
+use clap::Subcommand;
+use clap::ValueEnum;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
 
+use first_interpreter::ast_print::AstPrinter;
+use first_interpreter::chunk::Prototype;
+use first_interpreter::chunk::SCRIPT_NAME;
+use first_interpreter::compiler;
+use first_interpreter::disassembler;
+use first_interpreter::dot_print::DotPrinter;
+use first_interpreter::expr::Ast;
+use first_interpreter::expr::Visitor as _;
+use first_interpreter::formatter;
+use first_interpreter::interpreter::Interpreter;
+use first_interpreter::json;
+use first_interpreter::limits::Limits;
+use first_interpreter::limits::DEFAULT_MAX_CALL_DEPTH;
+use first_interpreter::loxc;
+use first_interpreter::native::NativeRegistry;
+use first_interpreter::parser::Parser as LoxParser;
+use first_interpreter::rpn_print::RPNPrinter;
 use first_interpreter::scanner::Scanner;
+use first_interpreter::stdlib;
+use first_interpreter::stdlib::Capabilities;
+use first_interpreter::stmt::Stmt;
+use first_interpreter::stmt::Visitor as _;
+use first_interpreter::vm::Vm;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
-/// Lox tree-walk interpreter
+#[command(args_conflicts_with_subcommands = true)]
+/// Lox interpreter
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// A Lox source file, or a `.loxc` file from `compile`.
     script: Option<PathBuf>,
+
+    /// How to run the script (or REPL).
+    #[arg(long, value_enum, default_value_t = Backend::TreeWalk)]
+    backend: Backend,
+
+    /// Print the VM's stack and each instruction to stderr as it runs. Only
+    /// affects the VM backend.
+    #[arg(long)]
+    trace: bool,
+
+    /// Collect garbage on every allocation, to shake out GC bugs. Only
+    /// affects the VM backend.
+    #[arg(long)]
+    gc_stress: bool,
+
+    /// Print garbage collector statistics to stderr on exit. Only affects the
+    /// VM backend.
+    #[arg(long)]
+    gc_stats: bool,
+
+    /// Stop with an error after this many steps: statements for the
+    /// tree-walker, instructions for the VM.
+    #[arg(long)]
+    max_steps: Option<u64>,
+
+    /// How deeply calls can nest before it's a stack overflow.
+    #[arg(long, default_value_t = DEFAULT_MAX_CALL_DEPTH)]
+    max_call_depth: usize,
+
+    /// Stop with an error if the heap grows past this many bytes. Requires
+    /// `--backend vm`, since the tree-walker has no heap to measure.
+    #[arg(long)]
+    max_heap_bytes: Option<usize>,
+
+    /// Deny the script access to files, the environment, stdin, the process
+    /// and the time.
+    #[arg(long)]
+    sandbox: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Backend {
+    /// Walks the syntax tree directly.
+    TreeWalk,
+    /// Compiles to bytecode for a stack VM.
+    Vm,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Rewrites Lox files in canonical formatting.
+    Fmt {
+        files: Vec<PathBuf>,
+        /// Don't write anything, just fail if some file isn't formatted.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Prints the syntax tree of a Lox file.
+    Parse {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = TreeFormat::Sexpr)]
+        format: TreeFormat,
+    },
+    /// Compiles a Lox file to bytecode, which can then be run directly.
+    Compile {
+        file: PathBuf,
+        /// Defaults to the input file with a `.loxc` extension.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Prints the bytecode a Lox file compiles to.
+    Disassemble { file: PathBuf },
+    /// Prints the tokens of a Lox file as JSON.
+    Tokens { file: PathBuf },
+    /// Runs a program in the JSON format printed by `parse --format json`.
+    RunAst { file: PathBuf },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum TreeFormat {
+    /// Lisp-style S-expressions.
+    Sexpr,
+    /// Reverse-polish notation, for programs of expression statements.
+    Rpn,
+    /// Graphviz DOT.
+    Dot,
+    /// JSON, see the `json` module for the schema.
+    Json,
 }
 
-#[derive(Debug)]
 struct Lox {
     // NOTE: might be better if Lox initialized with some strategy for error
     // handling.
     has_error: bool,
+    // Shared by every call to `run`, so that nodes parsed from an earlier REPL
+    // line stay alive after the line itself is dropped.
+    ast: Ast,
+    backend: Backend,
+    // Only the one for `backend` is used, but both are cheap to create.
+    interpreter: Interpreter,
+    vm: Vm,
 }
 
 impl Lox {
-    pub fn run_file(script: &Path) -> anyhow::Result<()> {
+    pub fn run_file(&mut self, script: &Path) -> anyhow::Result<()> {
         // Note: we don't need a bufreader for now since we're just reading all
         // at once.
         let mut f = File::open(script)?;
-        let mut contents = String::new();
-        f.read_to_string(&mut contents)?;
-        let mut lox = Lox::new();
-        lox.run(&contents);
+        let mut contents = Vec::new();
+        f.read_to_end(&mut contents)?;
+        if loxc::is_loxc(&contents) {
+            // Precompiled, so it can only run on the VM.
+            let prototypes = loxc::read(&contents)?;
+            let result = self.vm.run(&prototypes[0].chunk);
+            self.report_runtime_error(result);
+        } else {
+            self.run(&String::from_utf8(contents)?);
+        }
 
-        if lox.has_error() {
+        if self.has_error() {
             bail!("TODO: some error msg");
         }
 
         Ok(())
     }
 
-    pub fn run_repl() -> anyhow::Result<()> {
-        let mut lox = Lox::new();
+    pub fn run_repl(&mut self) -> anyhow::Result<()> {
         let stdin = std::io::stdin();
         for line in stdin.lock().lines() {
             let line = line?;
-            lox.run(&line);
+            self.run(&line);
         }
 
-        if lox.has_error() {
+        if self.has_error() {
             bail!("TODO: some error msg");
         }
 
         Ok(())
     }
 
-    pub fn new() -> Self {
-        Self { has_error: false }
+    pub fn new(backend: Backend, natives: NativeRegistry) -> Self {
+        Self {
+            has_error: false,
+            ast: Ast::new(),
+            backend,
+            interpreter: Interpreter::with_natives(natives.clone()),
+            vm: Vm::with_natives(natives),
+        }
     }
 
     pub fn run(&mut self, code: &str) {
         let mut scanner = Scanner::new(code);
         let tokens = scanner.scan_tokens();
-        println!("Tokens:");
-        println!("{:#?}", tokens);
-        // TODO:
+        let parsed = LoxParser::new(tokens, &mut self.ast).parse_program();
+        if scanner.has_error() {
+            for error in scanner.errors() {
+                eprintln!("{}", error);
+            }
+            self.has_error = true;
+            return;
+        }
+
+        let statements = match parsed {
+            Ok(statements) => statements,
+            Err(err) => {
+                eprintln!("{}", err);
+                self.has_error = true;
+                return;
+            }
+        };
+
+        let result = match self.backend {
+            Backend::TreeWalk => self.interpreter.interpret(&self.ast, &statements),
+            Backend::Vm => match compiler::compile(&self.ast, &statements) {
+                Ok(chunk) => self.vm.run(&chunk),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    self.has_error = true;
+                    return;
+                }
+            },
+        };
+        self.report_runtime_error(result);
+    }
+
+    fn report_runtime_error(&mut self, result: anyhow::Result<()>) {
+        if let Err(err) = result {
+            eprintln!("Runtime error: {}", err);
+            self.has_error = true;
+        }
     }
 
     pub fn has_error(&self) -> bool {
         self.has_error
     }
 
+    // Not wired up until the parser reports errors through `Lox`.
+    #[allow(dead_code)]
     fn report_error(&mut self, line: u64, message: &str) {
         eprintln!("[line {}] Error (TODO where): {}", line, message);
         self.has_error = true;
     }
 }
 
+fn format_files(files: &[PathBuf], check: bool) -> anyhow::Result<()> {
+    let mut unformatted = 0;
+    for file in files {
+        let source = std::fs::read_to_string(file)?;
+        let formatted = formatter::format_source(&source)
+            .map_err(|err| err.context(format!("Couldn't format {}", file.display())))?;
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            println!("Would reformat {}", file.display());
+            unformatted += 1;
+        } else {
+            std::fs::write(file, formatted)?;
+        }
+    }
+
+    if unformatted > 0 {
+        bail!("{} file(s) aren't formatted", unformatted);
+    }
+    Ok(())
+}
+
+fn print_tree(file: &Path, format: TreeFormat) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(file)?;
+    let mut scanner = Scanner::new(&source);
+    let mut ast = Ast::new();
+    let statements = LoxParser::new(scanner.scan_tokens(), &mut ast).parse_program()?;
+    if scanner.has_error() {
+        bail!(
+            "Couldn't scan {}:\n{}",
+            file.display(),
+            scanner.errors().join("\n")
+        );
+    }
+
+    match format {
+        TreeFormat::Sexpr => {
+            for stmt in statements {
+                println!("{}", AstPrinter.visit_stmt(&ast, stmt));
+            }
+        }
+        TreeFormat::Rpn => {
+            for stmt in statements {
+                let Stmt::Expression { expr, .. } = ast[stmt] else {
+                    bail!("Only expression statements can be printed as RPN.");
+                };
+                println!("{}", RPNPrinter.visit_expr(&ast, expr));
+            }
+        }
+        TreeFormat::Dot => print!("{}", DotPrinter.print_program(&ast, &statements)),
+        TreeFormat::Json => println!("{}", json::program_to_json(&ast, &statements)?),
+    }
+    Ok(())
+}
+
+fn compile_file(file: &Path, output: Option<PathBuf>) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(file)?;
+    let mut scanner = Scanner::new(&source);
+    let mut ast = Ast::new();
+    let statements = LoxParser::new(scanner.scan_tokens(), &mut ast).parse_program()?;
+    if scanner.has_error() {
+        bail!(
+            "Couldn't scan {}:\n{}",
+            file.display(),
+            scanner.errors().join("\n")
+        );
+    }
+
+    let script = Prototype {
+        name: SCRIPT_NAME.to_owned(),
+        arity: 0,
+        upvalues: Vec::new(),
+        chunk: compiler::compile(&ast, &statements)?,
+    };
+    let output = output.unwrap_or_else(|| file.with_extension("loxc"));
+    std::fs::write(output, loxc::write(&[script]))?;
+    Ok(())
+}
+
+fn disassemble(file: &Path) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(file)?;
+    let mut scanner = Scanner::new(&source);
+    let mut ast = Ast::new();
+    let statements = LoxParser::new(scanner.scan_tokens(), &mut ast).parse_program()?;
+    if scanner.has_error() {
+        bail!(
+            "Couldn't scan {}:\n{}",
+            file.display(),
+            scanner.errors().join("\n")
+        );
+    }
+
+    let chunk = compiler::compile(&ast, &statements)?;
+    print!(
+        "{}",
+        disassembler::disassemble_chunk(&chunk, &file.display().to_string())
+    );
+    Ok(())
+}
+
+fn print_tokens(file: &Path) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(file)?;
+    let mut scanner = Scanner::new(&source);
+    println!("{}", json::tokens_to_json(scanner.scan_tokens())?);
+    Ok(())
+}
+
+fn run_ast(file: &Path) -> anyhow::Result<()> {
+    let (ast, statements) = json::program_from_json(&std::fs::read_to_string(file)?)?;
+    Interpreter::new().interpret(&ast, &statements)
+}
+
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-    match args.script {
-        Some(script) => {
-            Lox::run_file(&script)?;
+    match (args.command, args.script) {
+        (Some(Command::Fmt { files, check }), _) => {
+            format_files(&files, check)?;
+        }
+        (Some(Command::Parse { file, format }), _) => {
+            print_tree(&file, format)?;
+        }
+        (Some(Command::Compile { file, output }), _) => {
+            compile_file(&file, output)?;
+        }
+        (Some(Command::Disassemble { file }), _) => {
+            disassemble(&file)?;
+        }
+        (Some(Command::Tokens { file }), _) => {
+            print_tokens(&file)?;
+        }
+        (Some(Command::RunAst { file }), _) => {
+            run_ast(&file)?;
         }
-        None => {
-            Lox::run_repl()?;
+        (None, script) => {
+            if args.max_heap_bytes.is_some() && args.backend == Backend::TreeWalk {
+                bail!("--max-heap-bytes only works with --backend vm.");
+            }
+            let limits = Limits {
+                max_steps: args.max_steps,
+                max_call_depth: Some(args.max_call_depth),
+                max_heap_bytes: args.max_heap_bytes,
+            };
+            let capabilities = if args.sandbox {
+                Capabilities::none()
+            } else {
+                Capabilities::all()
+            };
+            let mut lox = Lox::new(args.backend, stdlib::with_capabilities(capabilities));
+            lox.interpreter.set_limits(limits);
+            lox.vm.set_limits(limits);
+            lox.vm.set_trace(args.trace);
+            lox.vm.set_gc_stress(args.gc_stress);
+            let result = match script {
+                Some(script) => lox.run_file(&script),
+                None => lox.run_repl(),
+            };
+            // Even if the script failed, the collector still did its work.
+            if args.gc_stats {
+                eprintln!("{}", lox.vm.gc_stats());
+            }
+            result?;
         }
     };
 
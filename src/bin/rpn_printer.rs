@@ -1,51 +1,73 @@
+use expr::Ast;
+use expr::BinaryOp;
 use expr::Expr::*;
+use expr::UnaryOp;
 use first_interpreter::expr;
 use first_interpreter::expr::Visitor;
 use first_interpreter::rpn_print::RPNPrinter;
-use first_interpreter::scanner;
-use scanner::AnnotatedToken;
-use scanner::Token::*;
 
 fn main() -> anyhow::Result<()> {
+    let mut ast = Ast::new();
+
     // (-123) * (45.67)
-    let test = Binary {
-        left: Box::new(Unary {
-            operator: AnnotatedToken {
-                token: Minus,
-                line_number: 1,
-            },
-            right: Box::new(LiteralNumber(123.0)),
-        }),
-        operator: AnnotatedToken {
-            token: Star,
-            line_number: 1,
-        },
-        right: Box::new(Grouping(Box::new(LiteralNumber(45.67)))),
-    };
+    let number = ast.add(LiteralNumber {
+        value: 123.0,
+        line_number: 1,
+    });
+    let left = ast.add(Unary {
+        operator: UnaryOp::Minus,
+        line_number: 1,
+        right: number,
+    });
+    let number = ast.add(LiteralNumber {
+        value: 45.67,
+        line_number: 1,
+    });
+    let right = ast.add(Grouping(number));
+    let test = ast.add(Binary {
+        left,
+        operator: BinaryOp::Multiply,
+        line_number: 1,
+        right,
+    });
+
     // (1 + 2) * (4 - 3)
-    let test2 = Binary {
-        left: Box::new(Binary {
-            left: Box::new(LiteralNumber(1.0)),
-            operator: AnnotatedToken {
-                token: Plus,
-                line_number: 1,
-            },
-            right: Box::new(LiteralNumber(2.0)),
-        }),
-        operator: AnnotatedToken {
-            token: Star,
-            line_number: 1,
-        },
-        right: Box::new(Binary {
-            left: Box::new(LiteralNumber(4.0)),
-            operator: AnnotatedToken {
-                token: Minus,
-                line_number: 1,
-            },
-            right: Box::new(LiteralNumber(3.0)),
-        }),
-    };
-    println!("{}", RPNPrinter.visit_expr(&test));
-    println!("{}", RPNPrinter.visit_expr(&test2));
+    let one = ast.add(LiteralNumber {
+        value: 1.0,
+        line_number: 1,
+    });
+    let two = ast.add(LiteralNumber {
+        value: 2.0,
+        line_number: 1,
+    });
+    let left = ast.add(Binary {
+        left: one,
+        operator: BinaryOp::Add,
+        line_number: 1,
+        right: two,
+    });
+    let four = ast.add(LiteralNumber {
+        value: 4.0,
+        line_number: 1,
+    });
+    let three = ast.add(LiteralNumber {
+        value: 3.0,
+        line_number: 1,
+    });
+    let right = ast.add(Binary {
+        left: four,
+        operator: BinaryOp::Subtract,
+        line_number: 1,
+        right: three,
+    });
+    let test2 = ast.add(Binary {
+        left,
+        operator: BinaryOp::Multiply,
+        line_number: 1,
+        right,
+    });
+
+    println!("{}", RPNPrinter.visit_expr(&ast, test));
+    println!("{}", RPNPrinter.visit_expr(&ast, test2));
     Ok(())
 }
@@ -0,0 +1,378 @@
+use crate::value::Value;
+use std::rc::Rc;
+
+/// A bytecode instruction. Operands, if any, follow the opcode in the code
+/// stream.
+#[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OpCode {
+    /// Operand: constant index (u8).
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    /// Operand: stack slot (u8).
+    GetLocal,
+    /// Operand: stack slot (u8). Leaves the value on the stack.
+    SetLocal,
+    /// Operand: constant index of the name (u8).
+    GetGlobal,
+    /// Operand: constant index of the name (u8).
+    DefineGlobal,
+    /// Operand: constant index of the name (u8). Leaves the value on the
+    /// stack.
+    SetGlobal,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Negate,
+    /// Unary `+`, which only checks that its operand is a number.
+    Positive,
+    Print,
+    /// Operand: forward offset (u16).
+    Jump,
+    /// Operand: forward offset (u16). Leaves the condition on the stack.
+    JumpIfFalse,
+    /// Operand: backward offset (u16).
+    Loop,
+    Return,
+    // Added after `.loxc` files existed, so they're after `Return` to keep the
+    // opcodes of older files the same.
+    /// Operand: argument count (u8). The callee is below the arguments, and
+    /// all of them are replaced by the result.
+    Call,
+    /// Operand: constant index of the name (u8). Replaces the object with
+    /// the property's value.
+    GetProperty,
+    /// Operand: element count (u8). The elements are replaced by a list of
+    /// them.
+    BuildList,
+    /// Replaces the list (or map) and index with the element.
+    GetIndex,
+    /// Replaces the list (or map), index and value with the value.
+    SetIndex,
+    /// Operand: entry count (u8). The keys and values, alternating, are
+    /// replaced by a map of them.
+    BuildMap,
+    /// Operand: index into the chunk's functions (u8). Pushes a closure of
+    /// the function, capturing the variables its `upvalues` say to.
+    Closure,
+    /// Operand: upvalue index (u8).
+    GetUpvalue,
+    /// Operand: upvalue index (u8). Leaves the value on the stack.
+    SetUpvalue,
+    /// Pops a local that a closure captured, moving its value off the stack
+    /// so the closure still sees it.
+    CloseUpvalue,
+}
+
+impl OpCode {
+    /// The name shown in disassembly.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::Constant => "CONSTANT",
+            OpCode::Nil => "NIL",
+            OpCode::True => "TRUE",
+            OpCode::False => "FALSE",
+            OpCode::Pop => "POP",
+            OpCode::GetLocal => "GET_LOCAL",
+            OpCode::SetLocal => "SET_LOCAL",
+            OpCode::GetGlobal => "GET_GLOBAL",
+            OpCode::DefineGlobal => "DEFINE_GLOBAL",
+            OpCode::SetGlobal => "SET_GLOBAL",
+            OpCode::Equal => "EQUAL",
+            OpCode::NotEqual => "NOT_EQUAL",
+            OpCode::Greater => "GREATER",
+            OpCode::GreaterEqual => "GREATER_EQUAL",
+            OpCode::Less => "LESS",
+            OpCode::LessEqual => "LESS_EQUAL",
+            OpCode::Add => "ADD",
+            OpCode::Subtract => "SUBTRACT",
+            OpCode::Multiply => "MULTIPLY",
+            OpCode::Divide => "DIVIDE",
+            OpCode::Negate => "NEGATE",
+            OpCode::Positive => "POSITIVE",
+            OpCode::Print => "PRINT",
+            OpCode::Jump => "JUMP",
+            OpCode::JumpIfFalse => "JUMP_IF_FALSE",
+            OpCode::Loop => "LOOP",
+            OpCode::Return => "RETURN",
+            OpCode::Call => "CALL",
+            OpCode::GetProperty => "GET_PROPERTY",
+            OpCode::BuildList => "BUILD_LIST",
+            OpCode::GetIndex => "GET_INDEX",
+            OpCode::SetIndex => "SET_INDEX",
+            OpCode::BuildMap => "BUILD_MAP",
+            OpCode::Closure => "CLOSURE",
+            OpCode::GetUpvalue => "GET_UPVALUE",
+            OpCode::SetUpvalue => "SET_UPVALUE",
+            OpCode::CloseUpvalue => "CLOSE_UPVALUE",
+        }
+    }
+
+    /// How many bytes of operands follow the opcode.
+    pub fn operand_len(&self) -> usize {
+        match self {
+            OpCode::Constant
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::Call
+            | OpCode::GetProperty
+            | OpCode::BuildList
+            | OpCode::BuildMap
+            | OpCode::Closure
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue => 1,
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => 2,
+            _ => 0,
+        }
+    }
+
+    /// How many values the instruction pops, and then how many it pushes,
+    /// given its first operand byte. Instructions that leave a value where it
+    /// is, like `SetLocal`, count as popping and pushing it.
+    pub fn stack_effect(&self, operand: u8) -> (usize, usize) {
+        let operand = operand as usize;
+        match self {
+            OpCode::Constant
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::GetLocal
+            | OpCode::GetGlobal
+            | OpCode::Closure
+            | OpCode::GetUpvalue => (0, 1),
+            OpCode::Pop | OpCode::DefineGlobal | OpCode::Print | OpCode::CloseUpvalue => (1, 0),
+            OpCode::SetLocal
+            | OpCode::SetGlobal
+            | OpCode::SetUpvalue
+            | OpCode::Negate
+            | OpCode::Positive
+            | OpCode::JumpIfFalse
+            | OpCode::GetProperty => (1, 1),
+            OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::GetIndex => (2, 1),
+            OpCode::SetIndex => (3, 1),
+            // The result is optional, the script might not leave one.
+            OpCode::Jump | OpCode::Loop | OpCode::Return => (0, 0),
+            OpCode::Call => (operand + 1, 1),
+            OpCode::BuildList => (operand, 1),
+            OpCode::BuildMap => (operand * 2, 1),
+        }
+    }
+
+    // In discriminant order, for decoding.
+    const ALL: [OpCode; 37] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Negate,
+        OpCode::Positive,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Return,
+        OpCode::Call,
+        OpCode::GetProperty,
+        OpCode::BuildList,
+        OpCode::GetIndex,
+        OpCode::SetIndex,
+        OpCode::BuildMap,
+        OpCode::Closure,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::CloseUpvalue,
+    ];
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = anyhow::Error;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        OpCode::ALL
+            .get(byte as usize)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("Unknown opcode {}.", byte))
+    }
+}
+
+/// Compiled bytecode, along with the constants it refers to.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    /// Source lines of the bytes in `code`, run-length encoded since most
+    /// lines compile to several bytes.
+    pub lines: Vec<LineRun>,
+    /// The functions declared directly in this chunk, for `Closure`.
+    pub functions: Vec<Rc<Prototype>>,
+}
+
+/// A compiled function: its bytecode and what's needed to call it. The
+/// top-level script is a function too, named `SCRIPT_NAME`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Prototype {
+    pub name: String,
+    pub arity: u8,
+    /// The enclosing function's variables this one uses, in the order of
+    /// its upvalue indices.
+    pub upvalues: Vec<Capture>,
+    pub chunk: Chunk,
+}
+
+pub const SCRIPT_NAME: &str = "<script>";
+/// Functions are anonymous, so they all get this name.
+pub const FUNCTION_NAME: &str = "<fn>";
+
+/// Where a closure gets one of its upvalues from when it's created.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Capture {
+    /// A local of the enclosing function if true, otherwise one of the
+    /// enclosing function's own upvalues.
+    pub is_local: bool,
+    pub index: u8,
+}
+
+/// `count` consecutive bytes of code that came from the same line.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LineRun {
+    pub line_number: usize,
+    pub count: usize,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, byte: u8, line_number: usize) {
+        self.code.push(byte);
+        match self.lines.last_mut() {
+            Some(run) if run.line_number == line_number => run.count += 1,
+            _ => self.lines.push(LineRun {
+                line_number,
+                count: 1,
+            }),
+        }
+    }
+
+    pub fn write_op(&mut self, op: OpCode, line_number: usize) {
+        self.write(op as u8, line_number);
+    }
+
+    /// The source line of the byte at `offset`.
+    pub fn line_number(&self, offset: usize) -> usize {
+        let mut end = 0;
+        for run in &self.lines {
+            end += run.count;
+            if offset < end {
+                return run.line_number;
+            }
+        }
+        panic!("offset {} is past the end of the chunk", offset);
+    }
+
+    /// Adds a value to the constant pool, returning its index.
+    pub fn add_constant(&mut self, value: Value) -> anyhow::Result<u8> {
+        let index = u8::try_from(self.constants.len())
+            .map_err(|_| anyhow::anyhow!("Too many constants in one chunk."))?;
+        self.constants.push(value);
+        Ok(index)
+    }
+
+    /// Adds a function for `Closure` to refer to, returning its index.
+    pub fn add_function(&mut self, function: Prototype) -> anyhow::Result<u8> {
+        let index = u8::try_from(self.functions.len())
+            .map_err(|_| anyhow::anyhow!("Too many functions in one chunk."))?;
+        self.functions.push(Rc::new(function));
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opcodes_round_trip() {
+        for op in OpCode::ALL {
+            assert_eq!(OpCode::try_from(op as u8).unwrap(), op);
+        }
+        assert!(OpCode::try_from(OpCode::ALL.len() as u8).is_err());
+    }
+
+    #[test]
+    fn line_runs() {
+        let mut chunk = Chunk::new();
+        chunk.write_op(OpCode::Nil, 1);
+        chunk.write_op(OpCode::Print, 1);
+        chunk.write_op(OpCode::Nil, 3);
+        chunk.write_op(OpCode::Return, 1);
+        assert_eq!(
+            chunk.lines,
+            [
+                LineRun {
+                    line_number: 1,
+                    count: 2
+                },
+                LineRun {
+                    line_number: 3,
+                    count: 1
+                },
+                LineRun {
+                    line_number: 1,
+                    count: 1
+                },
+            ]
+        );
+        let lines: Vec<_> = (0..4).map(|offset| chunk.line_number(offset)).collect();
+        assert_eq!(lines, [1, 1, 3, 1]);
+    }
+
+    #[test]
+    fn constant_pool_limit() {
+        let mut chunk = Chunk::new();
+        for number in 0..256 {
+            chunk.add_constant(Value::Number(number as f64)).unwrap();
+        }
+        assert!(chunk.add_constant(Value::Nil).is_err());
+    }
+}
@@ -0,0 +1,758 @@
+use crate::chunk::Capture;
+use crate::chunk::Chunk;
+use crate::chunk::OpCode;
+use crate::chunk::Prototype;
+use crate::chunk::FUNCTION_NAME;
+use crate::expr;
+use crate::expr::Ast;
+use crate::expr::BinaryOp;
+use crate::expr::Expr;
+use crate::expr::LogicalOp;
+use crate::expr::NodeId;
+use crate::expr::UnaryOp;
+use crate::expr::Visitor as _;
+use crate::parser::MAX_ARGUMENTS;
+use crate::parser::MAX_LIST_ELEMENTS;
+use crate::parser::MAX_MAP_ENTRIES;
+use crate::parser::MAX_PARAMETERS;
+use crate::stmt;
+use crate::stmt::Stmt;
+use crate::stmt::StmtId;
+use crate::stmt::Visitor as _;
+use crate::symbol::Symbol;
+use crate::value::Value;
+
+/// Compiles a program into bytecode for the `Vm`.
+pub fn compile(ast: &Ast, statements: &[StmtId]) -> anyhow::Result<Chunk> {
+    let mut compiler = Compiler::new();
+    for stmt in statements {
+        compiler.visit_stmt(ast, *stmt)?;
+    }
+    Ok(compiler.finish())
+}
+
+/// Compiles a single expression, in the global scope, whose value is left on
+/// the stack for `Vm::evaluate` to return.
+pub fn compile_expression(ast: &Ast, expr: NodeId) -> anyhow::Result<Chunk> {
+    let mut compiler = Compiler::new();
+    compiler.visit_expr(ast, expr)?;
+    Ok(compiler.finish())
+}
+
+// Top-level variables are globals, looked up by name at runtime. Everything
+// else lives on the stack, and is resolved to a slot here, or to an upvalue
+// if it belongs to an enclosing function.
+struct Compiler {
+    // The function being compiled, which is the script at the top level.
+    function: FunctionState,
+    // The functions it's nested in, outermost first.
+    enclosing: Vec<FunctionState>,
+    // The latest line we know of, for instructions whose node doesn't have one.
+    line_number: usize,
+}
+
+#[derive(Default)]
+struct FunctionState {
+    chunk: Chunk,
+    locals: Vec<Local>,
+    upvalues: Vec<Capture>,
+    scope_depth: usize,
+    // The loops we're inside of, innermost last.
+    loops: Vec<Loop>,
+}
+
+struct Local {
+    name: Symbol,
+    // `None` until the initializer has run. Until then, the name refers to
+    // whatever it did before, like in the tree-walker.
+    depth: Option<usize>,
+    // Whether a closure captured it, so it has to be closed rather than just
+    // popped when it goes out of scope.
+    captured: bool,
+}
+
+// Jumps out of a loop's body that can only be patched once the whole loop has
+// been compiled.
+struct Loop {
+    break_jumps: Vec<usize>,
+    continue_jumps: Vec<usize>,
+    // Locals declared deeper than this are popped before jumping.
+    scope_depth: usize,
+}
+
+impl expr::Visitor for Compiler {
+    type Result = anyhow::Result<()>;
+
+    fn visit_expr(&mut self, ast: &Ast, expr: NodeId) -> Self::Result {
+        match &ast[expr] {
+            Expr::Assign {
+                name,
+                line_number,
+                value,
+            } => {
+                self.line_number = *line_number;
+                self.visit_expr(ast, *value)?;
+                if let Some(slot) = self.resolve_local(*name) {
+                    self.emit_op_with_operand(OpCode::SetLocal, slot, *line_number);
+                } else if let Some(index) = self.resolve_upvalue(self.enclosing.len(), *name)? {
+                    self.emit_op_with_operand(OpCode::SetUpvalue, index, *line_number);
+                } else {
+                    let constant = self.identifier_constant(*name)?;
+                    self.emit_op_with_operand(OpCode::SetGlobal, constant, *line_number);
+                }
+            }
+            Expr::Binary {
+                left,
+                operator: BinaryOp::Comma,
+                right,
+                line_number,
+            } => {
+                self.visit_expr(ast, *left)?;
+                self.emit_op(OpCode::Pop, *line_number);
+                self.visit_expr(ast, *right)?;
+            }
+            Expr::Binary {
+                left,
+                operator,
+                line_number,
+                right,
+            } => {
+                self.visit_expr(ast, *left)?;
+                self.visit_expr(ast, *right)?;
+                self.line_number = *line_number;
+                self.emit_op(binary_op_code(*operator), *line_number);
+            }
+            Expr::Call {
+                callee,
+                line_number,
+                arguments,
+            } => {
+                self.visit_expr(ast, *callee)?;
+                for argument in arguments {
+                    self.visit_expr(ast, *argument)?;
+                }
+                self.line_number = *line_number;
+                let count = u8::try_from(arguments.len()).map_err(|_| {
+                    anyhow::anyhow!(
+                        "[line {}] Error: Can't have more than {} arguments.",
+                        line_number,
+                        MAX_ARGUMENTS
+                    )
+                })?;
+                self.emit_op_with_operand(OpCode::Call, count, *line_number);
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.visit_expr(ast, *condition)?;
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop, self.line_number);
+                self.visit_expr(ast, *then_branch)?;
+                let end_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(else_jump)?;
+                self.emit_op(OpCode::Pop, self.line_number);
+                self.visit_expr(ast, *else_branch)?;
+                self.patch_jump(end_jump)?;
+            }
+            Expr::Get {
+                object,
+                name,
+                line_number,
+            } => {
+                self.visit_expr(ast, *object)?;
+                self.line_number = *line_number;
+                let constant = self.identifier_constant(*name)?;
+                self.emit_op_with_operand(OpCode::GetProperty, constant, *line_number);
+            }
+            Expr::Function {
+                params,
+                body,
+                line_number,
+            } => {
+                self.line_number = *line_number;
+                self.compile_function(ast, params, *body, *line_number)?;
+            }
+            Expr::Grouping(inner) => self.visit_expr(ast, *inner)?,
+            Expr::Index {
+                object,
+                index,
+                line_number,
+            } => {
+                self.visit_expr(ast, *object)?;
+                self.visit_expr(ast, *index)?;
+                self.line_number = *line_number;
+                self.emit_op(OpCode::GetIndex, *line_number);
+            }
+            Expr::List(elements) => {
+                for element in elements {
+                    self.visit_expr(ast, *element)?;
+                }
+                let count = u8::try_from(elements.len()).map_err(|_| {
+                    anyhow::anyhow!(
+                        "[line {}] Error: Can't have more than {} elements in a list.",
+                        self.line_number,
+                        MAX_LIST_ELEMENTS
+                    )
+                })?;
+                self.emit_op_with_operand(OpCode::BuildList, count, self.line_number);
+            }
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.visit_expr(ast, *key)?;
+                    self.visit_expr(ast, *value)?;
+                }
+                let count = u8::try_from(entries.len()).map_err(|_| {
+                    anyhow::anyhow!(
+                        "[line {}] Error: Can't have more than {} entries in a map.",
+                        self.line_number,
+                        MAX_MAP_ENTRIES
+                    )
+                })?;
+                self.emit_op_with_operand(OpCode::BuildMap, count, self.line_number);
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                // Either way, the left operand is the result if we jump.
+                self.visit_expr(ast, *left)?;
+                let end_jump = match operator {
+                    LogicalOp::And => self.emit_jump(OpCode::JumpIfFalse),
+                    LogicalOp::Or => {
+                        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                        let end_jump = self.emit_jump(OpCode::Jump);
+                        self.patch_jump(else_jump)?;
+                        end_jump
+                    }
+                };
+                self.emit_op(OpCode::Pop, self.line_number);
+                self.visit_expr(ast, *right)?;
+                self.patch_jump(end_jump)?;
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                line_number,
+            } => {
+                self.visit_expr(ast, *object)?;
+                self.visit_expr(ast, *index)?;
+                self.visit_expr(ast, *value)?;
+                self.line_number = *line_number;
+                self.emit_op(OpCode::SetIndex, *line_number);
+            }
+            Expr::Unary {
+                operator,
+                line_number,
+                right,
+            } => {
+                self.visit_expr(ast, *right)?;
+                self.line_number = *line_number;
+                let op = match operator {
+                    UnaryOp::Minus => OpCode::Negate,
+                    UnaryOp::Plus => OpCode::Positive,
+                };
+                self.emit_op(op, *line_number);
+            }
+            Expr::LiteralNumber { value, line_number } => {
+                self.line_number = *line_number;
+                self.emit_constant(Value::Number(*value))?;
+            }
+            Expr::LiteralString { value, line_number } => {
+                self.line_number = *line_number;
+                self.emit_constant(Value::String(value.as_str().into()))?;
+            }
+            Expr::LiteralBool { value, line_number } => {
+                self.line_number = *line_number;
+                let op = if *value { OpCode::True } else { OpCode::False };
+                self.emit_op(op, *line_number);
+            }
+            Expr::Nil { line_number } => {
+                self.line_number = *line_number;
+                self.emit_op(OpCode::Nil, *line_number);
+            }
+            Expr::Variable { name, line_number } => {
+                self.line_number = *line_number;
+                if let Some(slot) = self.resolve_local(*name) {
+                    self.emit_op_with_operand(OpCode::GetLocal, slot, *line_number);
+                } else if let Some(index) = self.resolve_upvalue(self.enclosing.len(), *name)? {
+                    self.emit_op_with_operand(OpCode::GetUpvalue, index, *line_number);
+                } else {
+                    let constant = self.identifier_constant(*name)?;
+                    self.emit_op_with_operand(OpCode::GetGlobal, constant, *line_number);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl stmt::Visitor for Compiler {
+    type Result = anyhow::Result<()>;
+
+    fn visit_stmt(&mut self, ast: &Ast, stmt: StmtId) -> Self::Result {
+        match &ast[stmt] {
+            Stmt::Block(statements) => {
+                self.begin_scope();
+                for stmt in statements {
+                    self.visit_stmt(ast, *stmt)?;
+                }
+                self.end_scope();
+            }
+            Stmt::Break { line_number } => {
+                self.line_number = *line_number;
+                let jump = self.emit_loop_jump("break")?;
+                self.function
+                    .loops
+                    .last_mut()
+                    .unwrap()
+                    .break_jumps
+                    .push(jump);
+            }
+            Stmt::Continue { line_number } => {
+                self.line_number = *line_number;
+                let jump = self.emit_loop_jump("continue")?;
+                self.function
+                    .loops
+                    .last_mut()
+                    .unwrap()
+                    .continue_jumps
+                    .push(jump);
+            }
+            Stmt::Expression { expr, line_number } => {
+                self.line_number = *line_number;
+                self.visit_expr(ast, *expr)?;
+                self.emit_op(OpCode::Pop, self.line_number);
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                // The initializer's variable is scoped to the loop.
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.visit_stmt(ast, *initializer)?;
+                }
+                let loop_start = self.function.chunk.code.len();
+                let exit_jump = match condition {
+                    Some(condition) => {
+                        self.visit_expr(ast, *condition)?;
+                        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                        self.emit_op(OpCode::Pop, self.line_number);
+                        Some(exit_jump)
+                    }
+                    None => None,
+                };
+                let jumps = self.loop_body(ast, *body)?;
+                // `continue` still runs the increment.
+                for jump in jumps.continue_jumps {
+                    self.patch_jump(jump)?;
+                }
+                if let Some(increment) = increment {
+                    self.visit_expr(ast, *increment)?;
+                    self.emit_op(OpCode::Pop, self.line_number);
+                }
+                self.emit_loop(loop_start)?;
+                if let Some(exit_jump) = exit_jump {
+                    self.patch_jump(exit_jump)?;
+                    self.emit_op(OpCode::Pop, self.line_number);
+                }
+                // Past the condition's `Pop`, since `break` jumps from the
+                // body, where the condition is already gone.
+                for jump in jumps.break_jumps {
+                    self.patch_jump(jump)?;
+                }
+                self.end_scope();
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.visit_expr(ast, *condition)?;
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop, self.line_number);
+                self.visit_stmt(ast, *then_branch)?;
+                let end_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(else_jump)?;
+                self.emit_op(OpCode::Pop, self.line_number);
+                if let Some(else_branch) = else_branch {
+                    self.visit_stmt(ast, *else_branch)?;
+                }
+                self.patch_jump(end_jump)?;
+            }
+            Stmt::Print { expr, line_number } => {
+                self.line_number = *line_number;
+                self.visit_expr(ast, *expr)?;
+                self.emit_op(OpCode::Print, self.line_number);
+            }
+            Stmt::Return { value, line_number } => {
+                self.line_number = *line_number;
+                if self.enclosing.is_empty() {
+                    anyhow::bail!(
+                        "[line {}] Error at 'return': Can't return from top-level code.",
+                        line_number
+                    );
+                }
+                match value {
+                    Some(value) => self.visit_expr(ast, *value)?,
+                    None => self.emit_op(OpCode::Nil, *line_number),
+                }
+                self.emit_op(OpCode::Return, self.line_number);
+            }
+            Stmt::Var {
+                name,
+                line_number,
+                initializer,
+            } => {
+                self.line_number = *line_number;
+                if self.function.scope_depth > 0 {
+                    if self.function.locals.len() > u8::MAX as usize {
+                        anyhow::bail!(
+                            "[line {}] Error at '{}': Too many local variables in one chunk.",
+                            line_number,
+                            name
+                        );
+                    }
+                    // Declared before the initializer runs, so that its
+                    // value ends up in the new slot.
+                    self.function.locals.push(Local {
+                        name: *name,
+                        depth: None,
+                        captured: false,
+                    });
+                }
+                match initializer {
+                    Some(initializer) => self.visit_expr(ast, *initializer)?,
+                    None => self.emit_op(OpCode::Nil, *line_number),
+                }
+                if self.function.scope_depth > 0 {
+                    self.function.locals.last_mut().unwrap().depth =
+                        Some(self.function.scope_depth);
+                } else {
+                    let constant = self.identifier_constant(*name)?;
+                    self.emit_op_with_operand(OpCode::DefineGlobal, constant, *line_number);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                line_number,
+            } => {
+                self.line_number = *line_number;
+                let loop_start = self.function.chunk.code.len();
+                self.visit_expr(ast, *condition)?;
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop, self.line_number);
+                let jumps = self.loop_body(ast, *body)?;
+                for jump in jumps.continue_jumps {
+                    self.patch_jump(jump)?;
+                }
+                self.line_number = *line_number;
+                self.emit_loop(loop_start)?;
+                self.patch_jump(exit_jump)?;
+                self.emit_op(OpCode::Pop, self.line_number);
+                for jump in jumps.break_jumps {
+                    self.patch_jump(jump)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Compiler {
+    fn new() -> Self {
+        Compiler {
+            function: FunctionState::default(),
+            enclosing: Vec::new(),
+            line_number: 1,
+        }
+    }
+
+    fn finish(mut self) -> Chunk {
+        let line_number = self.line_number;
+        self.emit_op(OpCode::Return, line_number);
+        self.function.chunk
+    }
+
+    // Compiles the function into a prototype of its own, and emits a
+    // `Closure` for it.
+    fn compile_function(
+        &mut self,
+        ast: &Ast,
+        params: &[Symbol],
+        body: StmtId,
+        line_number: usize,
+    ) -> anyhow::Result<()> {
+        let arity = u8::try_from(params.len()).map_err(|_| {
+            anyhow::anyhow!(
+                "[line {}] Error: Can't have more than {} parameters.",
+                line_number,
+                MAX_PARAMETERS
+            )
+        })?;
+        let enclosing = std::mem::take(&mut self.function);
+        self.enclosing.push(enclosing);
+
+        // Slot 0 holds the closure being called, which has no name. The
+        // parameters and the body share the function's scope.
+        self.function.scope_depth = 1;
+        let slot_zero = std::iter::once((Symbol::intern(""), 0));
+        for (name, depth) in slot_zero.chain(params.iter().map(|param| (*param, 1))) {
+            self.function.locals.push(Local {
+                name,
+                depth: Some(depth),
+                captured: false,
+            });
+        }
+        let Stmt::Block(statements) = &ast[body] else {
+            unreachable!("function bodies are blocks");
+        };
+        for stmt in statements {
+            self.visit_stmt(ast, *stmt)?;
+        }
+        // Falling off the end returns nil.
+        self.emit_op(OpCode::Nil, self.line_number);
+        self.emit_op(OpCode::Return, self.line_number);
+
+        let enclosing = self.enclosing.pop().unwrap();
+        let function = std::mem::replace(&mut self.function, enclosing);
+        let prototype = Prototype {
+            name: FUNCTION_NAME.to_string(),
+            arity,
+            upvalues: function.upvalues,
+            chunk: function.chunk,
+        };
+        let index = self
+            .function
+            .chunk
+            .add_function(prototype)
+            .map_err(|err| anyhow::anyhow!("[line {}] Error: {}", line_number, err))?;
+        self.emit_op_with_operand(OpCode::Closure, index, line_number);
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.function.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.function.scope_depth -= 1;
+        while self.function.locals.last().is_some_and(|local| {
+            local
+                .depth
+                .is_some_and(|depth| depth > self.function.scope_depth)
+        }) {
+            let local = self.function.locals.pop().unwrap();
+            self.emit_pop(&local);
+        }
+    }
+
+    // Returns the `break` and `continue` jumps out of the body, for the
+    // caller to patch.
+    fn loop_body(&mut self, ast: &Ast, body: StmtId) -> anyhow::Result<Loop> {
+        self.function.loops.push(Loop {
+            break_jumps: Vec::new(),
+            continue_jumps: Vec::new(),
+            scope_depth: self.function.scope_depth,
+        });
+        let result = self.visit_stmt(ast, body);
+        let jumps = self.function.loops.pop().unwrap();
+        result.map(|_| jumps)
+    }
+
+    // Pops the locals of the scopes being jumped out of, then emits a jump
+    // for the loop to patch. The locals stay declared, since the code after
+    // the jump is still in their scope.
+    fn emit_loop_jump(&mut self, keyword: &str) -> anyhow::Result<usize> {
+        let Some(innermost) = self.function.loops.last() else {
+            anyhow::bail!(
+                "[line {}] Error: Can't use '{}' outside of a loop.",
+                self.line_number,
+                keyword
+            );
+        };
+        let depth = innermost.scope_depth;
+        let count = self
+            .function
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_some_and(|local_depth| local_depth > depth))
+            .count();
+        for index in (self.function.locals.len() - count..self.function.locals.len()).rev() {
+            let op = self.pop_op(&self.function.locals[index]);
+            self.emit_op(op, self.line_number);
+        }
+        Ok(self.emit_jump(OpCode::Jump))
+    }
+
+    fn emit_pop(&mut self, local: &Local) {
+        let op = self.pop_op(local);
+        self.emit_op(op, self.line_number);
+    }
+
+    fn pop_op(&self, local: &Local) -> OpCode {
+        if local.captured {
+            OpCode::CloseUpvalue
+        } else {
+            OpCode::Pop
+        }
+    }
+
+    // The stack slot of a local variable, or `None` if it's a global.
+    fn resolve_local(&self, name: Symbol) -> Option<u8> {
+        self.function
+            .locals
+            .iter()
+            .rposition(|local| local.name == name && local.depth.is_some())
+            // There are at most 256 locals.
+            .map(|slot| slot as u8)
+    }
+
+    // The index of the upvalue for a variable of an enclosing function, in
+    // the function `level` deep, adding it to that function and every one in
+    // between if it's new. `None` if it's a global.
+    fn resolve_upvalue(&mut self, level: usize, name: Symbol) -> anyhow::Result<Option<u8>> {
+        // The script doesn't have an enclosing function.
+        let Some(parent) = level.checked_sub(1) else {
+            return Ok(None);
+        };
+        let slot = self
+            .state(parent)
+            .locals
+            .iter()
+            .rposition(|local| local.name == name && local.depth.is_some());
+        let capture = match slot {
+            Some(slot) => {
+                self.state(parent).locals[slot].captured = true;
+                Capture {
+                    is_local: true,
+                    index: slot as u8,
+                }
+            }
+            None => match self.resolve_upvalue(parent, name)? {
+                Some(index) => Capture {
+                    is_local: false,
+                    index,
+                },
+                None => return Ok(None),
+            },
+        };
+
+        let line_number = self.line_number;
+        let upvalues = &mut self.state(level).upvalues;
+        if let Some(index) = upvalues.iter().position(|upvalue| *upvalue == capture) {
+            return Ok(Some(index as u8));
+        }
+        let index = u8::try_from(upvalues.len()).map_err(|_| {
+            anyhow::anyhow!(
+                "[line {}] Error: Too many closure variables in function.",
+                line_number
+            )
+        })?;
+        upvalues.push(capture);
+        Ok(Some(index))
+    }
+
+    // The function `level` deep, where the current one is the deepest.
+    fn state(&mut self, level: usize) -> &mut FunctionState {
+        match self.enclosing.get_mut(level) {
+            Some(state) => state,
+            None => &mut self.function,
+        }
+    }
+
+    // Names of globals are stored as string constants. Each name is only
+    // added once, since the pool is small.
+    fn identifier_constant(&mut self, name: Symbol) -> anyhow::Result<u8> {
+        let existing =
+            self.function.chunk.constants.iter().position(
+                |constant| matches!(constant, Value::String(ss) if **ss == *name.as_str()),
+            );
+        match existing {
+            Some(index) => Ok(index as u8),
+            None => self.add_constant(Value::String(name.as_str().into())),
+        }
+    }
+
+    fn add_constant(&mut self, value: Value) -> anyhow::Result<u8> {
+        self.function
+            .chunk
+            .add_constant(value)
+            .map_err(|err| anyhow::anyhow!("[line {}] Error: {}", self.line_number, err))
+    }
+
+    fn emit_constant(&mut self, value: Value) -> anyhow::Result<()> {
+        let constant = self.add_constant(value)?;
+        self.emit_op_with_operand(OpCode::Constant, constant, self.line_number);
+        Ok(())
+    }
+
+    fn emit_op(&mut self, op: OpCode, line_number: usize) {
+        self.function.chunk.write_op(op, line_number);
+    }
+
+    fn emit_op_with_operand(&mut self, op: OpCode, operand: u8, line_number: usize) {
+        self.function.chunk.write_op(op, line_number);
+        self.function.chunk.write(operand, line_number);
+    }
+
+    // Emits a jump with a placeholder offset, returning where the offset is
+    // so that `patch_jump` can fill it in.
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op, self.line_number);
+        self.function.chunk.write(0xff, self.line_number);
+        self.function.chunk.write(0xff, self.line_number);
+        self.function.chunk.code.len() - 2
+    }
+
+    // Points the jump at `offset` to the next instruction.
+    fn patch_jump(&mut self, offset: usize) -> anyhow::Result<()> {
+        // The jump is relative to the end of its operand.
+        let jump = u16::try_from(self.function.chunk.code.len() - offset - 2).map_err(|_| {
+            anyhow::anyhow!(
+                "[line {}] Error: Too much code to jump over.",
+                self.line_number
+            )
+        })?;
+        self.function.chunk.code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) -> anyhow::Result<()> {
+        self.emit_op(OpCode::Loop, self.line_number);
+        // Jumping back over the operand too.
+        let offset =
+            u16::try_from(self.function.chunk.code.len() - loop_start + 2).map_err(|_| {
+                anyhow::anyhow!("[line {}] Error: Loop body too large.", self.line_number)
+            })?;
+        for byte in offset.to_be_bytes() {
+            self.function.chunk.write(byte, self.line_number);
+        }
+        Ok(())
+    }
+}
+
+fn binary_op_code(operator: BinaryOp) -> OpCode {
+    match operator {
+        // Compiled to a `Pop` between the operands instead.
+        BinaryOp::Comma => unreachable!("comma has no opcode"),
+        BinaryOp::Equal => OpCode::Equal,
+        BinaryOp::NotEqual => OpCode::NotEqual,
+        BinaryOp::Greater => OpCode::Greater,
+        BinaryOp::GreaterEqual => OpCode::GreaterEqual,
+        BinaryOp::Less => OpCode::Less,
+        BinaryOp::LessEqual => OpCode::LessEqual,
+        BinaryOp::Add => OpCode::Add,
+        BinaryOp::Subtract => OpCode::Subtract,
+        BinaryOp::Multiply => OpCode::Multiply,
+        BinaryOp::Divide => OpCode::Divide,
+    }
+}
@@ -0,0 +1,246 @@
+use crate::ast_print::token_to_string;
+use crate::chunk::Chunk;
+use crate::chunk::OpCode;
+use crate::scanner::Token;
+use crate::value::Value;
+
+/// Prints every instruction in a chunk, one per line:
+///
+/// ```text
+/// == script ==
+/// 0000    1 CONSTANT           0 1
+/// 0002    | CONSTANT           1 "two"
+/// 0004    | ADD              +
+/// 0005    2 DEFINE_GLOBAL      2 a
+/// ```
+///
+/// Columns are the byte offset, the source line (`|` when it's the same as the
+/// previous instruction's), the opcode and its operands.
+///
+/// The chunk's functions follow, named after their index: the first function
+/// of `script` is `script/0`.
+pub fn disassemble_chunk(chunk: &Chunk, name: &str) -> String {
+    let mut output = format!("== {} ==\n", name);
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let (instruction, next_offset) = disassemble_instruction(chunk, offset);
+        output.push_str(&instruction);
+        output.push('\n');
+        offset = next_offset;
+    }
+    for (index, function) in chunk.functions.iter().enumerate() {
+        output.push_str(&disassemble_chunk(
+            &function.chunk,
+            &format!("{}/{}", name, index),
+        ));
+    }
+    output
+}
+
+/// Prints the instruction at `offset`, returning it along with the offset of
+/// the next instruction.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
+    let line_number = chunk.line_number(offset);
+    let line = if offset > 0 && chunk.line_number(offset - 1) == line_number {
+        "   |".to_owned()
+    } else {
+        format!("{:4}", line_number)
+    };
+    let prefix = format!("{:04} {}", offset, line);
+
+    let op = match OpCode::try_from(chunk.code[offset]) {
+        Ok(op) => op,
+        Err(_) => {
+            return (
+                format!("{} UNKNOWN {}", prefix, chunk.code[offset]),
+                offset + 1,
+            )
+        }
+    };
+    let (operands, length) = match op {
+        OpCode::Constant
+        | OpCode::DefineGlobal
+        | OpCode::GetGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty => {
+            let constant = chunk.code[offset + 1];
+            let value = &chunk.constants[constant as usize];
+            let shown = match (op, value) {
+                // Names are shown as the identifiers they came from.
+                (OpCode::Constant, _) => value_to_string(value),
+                (_, Value::String(name)) => {
+                    token_to_string(&Token::Identifier { identifier: name })
+                }
+                (_, _) => value_to_string(value),
+            };
+            (format!("{:3} {}", constant, shown), 2)
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::Call
+        | OpCode::BuildList
+        | OpCode::BuildMap
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue => (format!("{:3}", chunk.code[offset + 1]), 2),
+        OpCode::Closure => {
+            let index = chunk.code[offset + 1];
+            let function = &chunk.functions[index as usize];
+            let captures: Vec<_> = function
+                .upvalues
+                .iter()
+                .map(|capture| {
+                    let kind = if capture.is_local { "local" } else { "upvalue" };
+                    format!("{} {}", kind, capture.index)
+                })
+                .collect();
+            let shown = if captures.is_empty() {
+                function.name.clone()
+            } else {
+                format!("{} ({})", function.name, captures.join(", "))
+            };
+            (format!("{:3} {}", index, shown), 2)
+        }
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+            let jump = u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+            let next = offset + 3;
+            let target = if op == OpCode::Loop {
+                next - jump as usize
+            } else {
+                next + jump as usize
+            };
+            (format!("{:3} -> {:04}", jump, target), 3)
+        }
+        _ => match operator_token(op) {
+            Some(token) => (token_to_string(&token), 1),
+            None => (String::new(), 1),
+        },
+    };
+
+    let instruction = format!("{} {:<16} {}", prefix, op.name(), operands);
+    (instruction.trim_end().to_owned(), offset + length)
+}
+
+/// Shows a value like it would be written as a literal, so strings are
+/// quoted.
+pub fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(ss) => token_to_string(&Token::String {
+            quoted_str: &format!("\"{}\"", ss),
+        }),
+        Value::Number(number) => token_to_string(&Token::Number { number: *number }),
+        Value::Bool(true) => token_to_string(&Token::True),
+        Value::Bool(false) => token_to_string(&Token::False),
+        Value::Nil => token_to_string(&Token::Nil),
+        // Never constants, but the VM's stack can have them when tracing.
+        Value::List(_)
+        | Value::Map(_)
+        | Value::Native(_)
+        | Value::Namespace(_)
+        | Value::NativeClass(_)
+        | Value::NativeInstance(_)
+        | Value::Function(_)
+        | Value::Closure(_) => value.to_string(),
+    }
+}
+
+/// The value stack, bottom first, as printed by `Vm` when tracing.
+pub fn stack_to_string(stack: &[Value]) -> String {
+    stack
+        .iter()
+        .map(|value| format!("[ {} ]", value_to_string(value)))
+        .collect()
+}
+
+// The token an operator instruction was compiled from.
+fn operator_token(op: OpCode) -> Option<Token<'static>> {
+    let token = match op {
+        OpCode::Equal => Token::EqualEqual,
+        OpCode::NotEqual => Token::BangEqual,
+        OpCode::Greater => Token::Greater,
+        OpCode::GreaterEqual => Token::GreaterEqual,
+        OpCode::Less => Token::Less,
+        OpCode::LessEqual => Token::LessEqual,
+        OpCode::Add | OpCode::Positive => Token::Plus,
+        OpCode::Subtract | OpCode::Negate => Token::Minus,
+        OpCode::Multiply => Token::Star,
+        OpCode::Divide => Token::Slash,
+        _ => return None,
+    };
+    Some(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+    use crate::expr::Ast;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn compile(source: &str) -> Chunk {
+        let mut scanner = Scanner::new(source);
+        let mut ast = Ast::new();
+        let statements = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse_program()
+            .unwrap();
+        compiler::compile(&ast, &statements).unwrap()
+    }
+
+    #[test]
+    fn chunk() {
+        let chunk = compile("var a = 1 + \"two\";\nwhile (a) { var b = -a; }");
+        assert_eq!(
+            disassemble_chunk(&chunk, "script"),
+            "== script ==
+0000    1 CONSTANT           0 1
+0002    | CONSTANT           1 \"two\"
+0004    | ADD              +
+0005    | DEFINE_GLOBAL      2 a
+0007    2 GET_GLOBAL         2 a
+0009    | JUMP_IF_FALSE      8 -> 0020
+0012    | POP
+0013    | GET_GLOBAL         2 a
+0015    | NEGATE           -
+0016    | POP
+0017    | LOOP              13 -> 0007
+0020    | POP
+0021    | RETURN
+"
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let chunk = compile("{ var a; fun (b) { return fun () { return a + b; }; }; }");
+        assert_eq!(
+            disassemble_chunk(&chunk, "script"),
+            "== script ==
+0000    1 NIL
+0001    | CLOSURE            0 <fn> (local 0)
+0003    | POP
+0004    | CLOSE_UPVALUE
+0005    | RETURN
+== script/0 ==
+0000    1 CLOSURE            0 <fn> (upvalue 0, local 1)
+0002    | RETURN
+0003    | NIL
+0004    | RETURN
+== script/0/0 ==
+0000    1 GET_UPVALUE        0
+0002    | GET_UPVALUE        1
+0004    | ADD              +
+0005    | RETURN
+0006    | NIL
+0007    | RETURN
+"
+        );
+    }
+
+    #[test]
+    fn stack() {
+        assert_eq!(
+            stack_to_string(&[Value::Nil, Value::Number(1.5), Value::String("s".into())]),
+            "[ nil ][ 1.5 ][ \"s\" ]"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use crate::expr;
+use crate::expr::NodeId;
+use crate::expr::Visitor;
+use crate::stmt;
+use crate::stmt::Stmt;
+use crate::stmt::StmtId;
+
+/// Renders a syntax tree as a Graphviz `digraph`, with one graph node per AST
+/// node. Graph nodes are named after their `NodeId`s (`n0`) or `StmtId`s
+/// (`s0`).
+pub struct DotPrinter;
+
+impl DotPrinter {
+    pub fn print(&mut self, ast: &expr::Ast, root: NodeId) -> String {
+        format!(
+            "digraph ast {{\n  node [shape=box];\n{}}}\n",
+            self.visit_expr(ast, root)
+        )
+    }
+
+    /// Prints a whole program, with each top-level statement as a root.
+    pub fn print_program(&mut self, ast: &expr::Ast, statements: &[StmtId]) -> String {
+        use stmt::Visitor;
+        let mut lines = String::new();
+        for stmt in statements {
+            lines.push_str(&self.visit_stmt(ast, *stmt));
+        }
+        format!("digraph ast {{\n  node [shape=box];\n{}}}\n", lines)
+    }
+}
+
+impl expr::Visitor for DotPrinter {
+    // Node and edge statements for the subtree, one per line.
+    type Result = String;
+
+    fn visit_expr(&mut self, ast: &expr::Ast, expr: NodeId) -> Self::Result {
+        use expr::Expr::*;
+        let (label, children) = match &ast[expr] {
+            Assign { name, value, .. } => (format!("= {}", name), vec![*value]),
+            Binary {
+                left,
+                operator,
+                right,
+                ..
+            } => (operator.to_string(), vec![*left, *right]),
+            Call {
+                callee, arguments, ..
+            } => (
+                "call".to_owned(),
+                [*callee].into_iter().chain(arguments.iter().copied()).collect(),
+            ),
+            Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => ("?:".to_owned(), vec![*condition, *then_branch, *else_branch]),
+            // The only expression with a statement for a child.
+            Function { params, body, .. } => {
+                use stmt::Visitor;
+                let params: Vec<_> = params.iter().map(|param| param.to_string()).collect();
+                let label = format!("fun ({})", params.join(", "));
+                return format!(
+                    "  {} [label=\"{}\"];\n  {} -> {};\n{}",
+                    node_name(expr),
+                    escape(&label),
+                    node_name(expr),
+                    stmt_name(*body),
+                    self.visit_stmt(ast, *body)
+                );
+            }
+            Get { object, name, .. } => (format!(".{}", name), vec![*object]),
+            Grouping(expr) => ("group".to_owned(), vec![*expr]),
+            Index { object, index, .. } => ("[]".to_owned(), vec![*object, *index]),
+            List(elements) => ("list".to_owned(), elements.clone()),
+            Map(entries) => (
+                "map".to_owned(),
+                entries
+                    .iter()
+                    .flat_map(|(key, value)| [*key, *value])
+                    .collect(),
+            ),
+            Logical {
+                left,
+                operator,
+                right,
+            } => (operator.to_string(), vec![*left, *right]),
+            SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => ("[]=".to_owned(), vec![*object, *index, *value]),
+            Unary {
+                operator, right, ..
+            } => (operator.to_string(), vec![*right]),
+            Variable { name, .. } => (name.to_string(), vec![]),
+            LiteralNumber { value: number, .. } => (format!("{}", number), vec![]),
+            LiteralString { value: ss, .. } => (format!("\"{}\"", ss), vec![]),
+            LiteralBool { value: bb, .. } => (format!("{}", bb), vec![]),
+            Nil { .. } => ("nil".to_owned(), vec![]),
+        };
+
+        let mut lines = format!("  {} [label=\"{}\"];\n", node_name(expr), escape(&label));
+        for child in children {
+            lines.push_str(&format!("  {} -> {};\n", node_name(expr), node_name(child)));
+            lines.push_str(&self.visit_expr(ast, child));
+        }
+        lines
+    }
+}
+
+impl stmt::Visitor for DotPrinter {
+    type Result = String;
+
+    fn visit_stmt(&mut self, ast: &expr::Ast, stmt: StmtId) -> Self::Result {
+        let (label, children) = match &ast[stmt] {
+            Stmt::Block(statements) => (
+                "block".to_owned(),
+                statements.iter().copied().map(Child::Stmt).collect(),
+            ),
+            Stmt::Break { .. } => ("break".to_owned(), vec![]),
+            Stmt::Continue { .. } => ("continue".to_owned(), vec![]),
+            Stmt::Expression { expr, .. } => ("expr;".to_owned(), vec![Child::Expr(*expr)]),
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                // Missing clauses just don't get an edge.
+                let children = initializer
+                    .map(Child::Stmt)
+                    .into_iter()
+                    .chain(condition.map(Child::Expr))
+                    .chain(increment.map(Child::Expr))
+                    .chain([Child::Stmt(*body)])
+                    .collect();
+                ("for".to_owned(), children)
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let children = [Child::Expr(*condition), Child::Stmt(*then_branch)]
+                    .into_iter()
+                    .chain(else_branch.map(Child::Stmt))
+                    .collect();
+                ("if".to_owned(), children)
+            }
+            Stmt::Print { expr, .. } => ("print".to_owned(), vec![Child::Expr(*expr)]),
+            Stmt::Return { value, .. } => (
+                "return".to_owned(),
+                value.map(Child::Expr).into_iter().collect(),
+            ),
+            Stmt::Var {
+                name, initializer, ..
+            } => (
+                format!("var {}", name),
+                initializer.map(Child::Expr).into_iter().collect(),
+            ),
+            Stmt::While {
+                condition, body, ..
+            } => (
+                "while".to_owned(),
+                vec![Child::Expr(*condition), Child::Stmt(*body)],
+            ),
+        };
+
+        let name = stmt_name(stmt);
+        let mut lines = format!("  {} [label=\"{}\"];\n", name, escape(&label));
+        for child in children {
+            let (child_name, child_lines) = match child {
+                Child::Expr(expr) => (node_name(expr), self.visit_expr(ast, expr)),
+                Child::Stmt(stmt) => (stmt_name(stmt), self.visit_stmt(ast, stmt)),
+            };
+            lines.push_str(&format!("  {} -> {};\n", name, child_name));
+            lines.push_str(&child_lines);
+        }
+        lines
+    }
+}
+
+// Statements can have both kinds of children.
+enum Child {
+    Expr(NodeId),
+    Stmt(StmtId),
+}
+
+fn stmt_name(stmt: StmtId) -> String {
+    format!("s{}", stmt.index())
+}
+
+fn node_name(expr: NodeId) -> String {
+    format!("n{}", expr.index())
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::Ast;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn digraph() {
+        let mut scanner = Scanner::new("-(1 + \"two\")");
+        let mut ast = Ast::new();
+        let root = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse()
+            .unwrap();
+        assert_eq!(
+            DotPrinter.print(&ast, root),
+            r#"digraph ast {
+  node [shape=box];
+  n4 [label="-"];
+  n4 -> n3;
+  n3 [label="group"];
+  n3 -> n2;
+  n2 [label="+"];
+  n2 -> n0;
+  n0 [label="1"];
+  n2 -> n1;
+  n1 [label="\"two\""];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn program() {
+        let mut scanner = Scanner::new("var a = 1; while (a) print a;");
+        let mut ast = Ast::new();
+        let statements = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse_program()
+            .unwrap();
+        assert_eq!(
+            DotPrinter.print_program(&ast, &statements),
+            r#"digraph ast {
+  node [shape=box];
+  s0 [label="var a"];
+  s0 -> n0;
+  n0 [label="1"];
+  s2 [label="while"];
+  s2 -> n1;
+  n1 [label="a"];
+  s2 -> s1;
+  s1 [label="print"];
+  s1 -> n2;
+  n2 [label="a"];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn function() {
+        let mut scanner = Scanner::new("fun (a, b) { return a; }");
+        let mut ast = Ast::new();
+        let root = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse()
+            .unwrap();
+        assert_eq!(
+            DotPrinter.print(&ast, root),
+            r#"digraph ast {
+  node [shape=box];
+  n1 [label="fun (a, b)"];
+  n1 -> s1;
+  s1 [label="block"];
+  s1 -> s0;
+  s0 [label="return"];
+  s0 -> n0;
+  n0 [label="a"];
+}
+"#
+        );
+    }
+}
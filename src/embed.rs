@@ -0,0 +1,529 @@
+//! Running Lox from a Rust program.
+//!
+//! ```
+//! use first_interpreter::embed::Engine;
+//!
+//! let mut engine = Engine::new();
+//! engine.set_global("width", 3.0);
+//! let area: f64 = engine.eval("var height = 2; width * height;")?.try_into()?;
+//! assert_eq!(area, 6.0);
+//! # anyhow::Ok(())
+//! ```
+//!
+//! Values cross over as `value::Value`, which converts to and from the
+//! matching Rust types with `From`/`TryFrom`. The VM keeps its own lists and
+//! maps, so with it they're copied when they cross, and changes on one side
+//! aren't seen on the other. Rust functions are made
+//! callable from Lox by registering them in a `NativeRegistry`, and Rust types
+//! can be registered as classes, see `NativeClass::builder`.
+
+use crate::compiler;
+use crate::expr::Ast;
+use crate::expr::NodeId;
+use crate::interpreter::Interpreter;
+use crate::limits::Limits;
+use crate::native::NativeRegistry;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::stdlib;
+use crate::stdlib::Capabilities;
+use crate::stmt::Stmt;
+use crate::stmt::StmtId;
+use crate::symbol::Symbol;
+use crate::value;
+use crate::value::Value;
+use crate::vm::Vm;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Which interpreter an `Engine` runs code with. They behave the same, but
+/// the VM is faster.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Backend {
+    #[default]
+    TreeWalk,
+    Vm,
+}
+
+/// A Lox interpreter for a host program. Globals are kept between calls, so
+/// a script can be loaded once and its functions called many times.
+///
+/// NOTE: every call to `eval` adds to the same syntax tree, which is never
+/// shrunk. Fine for loading scripts, but not for evaluating an unbounded
+/// stream of code.
+pub struct Engine {
+    ast: Ast,
+    runtime: Runtime,
+}
+
+enum Runtime {
+    TreeWalk(Interpreter),
+    // Boxed since it's much bigger than the interpreter.
+    Vm(Box<Vm>),
+}
+
+impl Engine {
+    /// A tree-walking engine with the standard library.
+    pub fn new() -> Self {
+        Self::with_natives(Backend::default(), stdlib::core())
+    }
+
+    /// An engine with the parts of the standard library `capabilities`
+    /// allows, e.g. `Capabilities::none()` for untrusted scripts.
+    pub fn with_capabilities(backend: Backend, capabilities: Capabilities) -> Self {
+        Self::with_natives(backend, stdlib::with_capabilities(capabilities))
+    }
+
+    pub fn with_natives(backend: Backend, natives: NativeRegistry) -> Self {
+        let runtime = match backend {
+            Backend::TreeWalk => Runtime::TreeWalk(Interpreter::with_natives(natives)),
+            Backend::Vm => Runtime::Vm(Box::new(Vm::with_natives(natives))),
+        };
+        Engine {
+            ast: Ast::new(),
+            runtime,
+        }
+    }
+
+    /// Sends the output of `print` somewhere other than stdout.
+    pub fn set_stdout(&mut self, stdout: impl Write + 'static) {
+        match &mut self.runtime {
+            Runtime::TreeWalk(interpreter) => interpreter.set_stdout(stdout),
+            Runtime::Vm(vm) => vm.set_stdout(stdout),
+        }
+    }
+
+    /// Sends diagnostics somewhere other than stderr. Errors are returned
+    /// rather than printed, so this is only VM tracing for now.
+    pub fn set_stderr(&mut self, stderr: impl Write + 'static) {
+        match &mut self.runtime {
+            Runtime::TreeWalk(_) => {}
+            Runtime::Vm(vm) => vm.set_stderr(stderr),
+        }
+    }
+
+    /// Limits each call to `eval`, see `Limits`. Going over one is an error
+    /// that downcasts to `limits::LimitExceeded`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        match &mut self.runtime {
+            Runtime::TreeWalk(interpreter) => interpreter.set_limits(limits),
+            Runtime::Vm(vm) => vm.set_limits(limits),
+        }
+    }
+
+    /// Runs a program. If it ends with an expression statement, the value of
+    /// that expression is returned (the semicolon is optional), otherwise
+    /// `nil`.
+    ///
+    /// Scan and parse errors are reported before anything runs. A runtime
+    /// error stops the program, but globals it already defined are kept.
+    pub fn eval(&mut self, source: &str) -> anyhow::Result<Value> {
+        let (statements, last) = self.parse(source)?;
+        match &mut self.runtime {
+            Runtime::TreeWalk(interpreter) => {
+                interpreter.interpret(&self.ast, &statements)?;
+                match last {
+                    Some(expr) => interpreter.evaluate(&self.ast, expr),
+                    None => Ok(Value::Nil),
+                }
+            }
+            Runtime::Vm(vm) => {
+                vm.run(&compiler::compile(&self.ast, &statements)?)?;
+                match last {
+                    Some(expr) => vm.evaluate(&compiler::compile_expression(&self.ast, expr)?),
+                    None => Ok(Value::Nil),
+                }
+            }
+        }
+    }
+
+    // Splits off the final expression statement, if there is one.
+    fn parse(&mut self, source: &str) -> anyhow::Result<(Vec<StmtId>, Option<NodeId>)> {
+        let mut scanner = Scanner::new(source);
+        // Cloned so that the scanner can be asked about errors.
+        let tokens = scanner.scan_tokens().clone();
+        if scanner.has_error() {
+            anyhow::bail!("{}", scanner.errors().join("\n"));
+        }
+
+        let mut statements = match Parser::new(&tokens, &mut self.ast).parse_program() {
+            Ok(statements) => statements,
+            Err(err) => {
+                // Maybe it's a lone expression without its semicolon. If not,
+                // the program's error is the more useful one.
+                let mut parser = Parser::new(&tokens, &mut self.ast);
+                return match parser.parse() {
+                    Ok(expr) if parser.position() + 1 == tokens.len() => {
+                        Ok((Vec::new(), Some(expr)))
+                    }
+                    _ => Err(err),
+                };
+            }
+        };
+        match statements.last().map(|stmt| &self.ast[*stmt]) {
+            Some(Stmt::Expression { expr, .. }) => {
+                let expr = *expr;
+                statements.pop();
+                Ok((statements, Some(expr)))
+            }
+            _ => Ok((statements, None)),
+        }
+    }
+
+    /// Calls the global function `name`.
+    pub fn call(&mut self, name: &str, arguments: &[Value]) -> anyhow::Result<Value> {
+        let function = self
+            .get_global(name)
+            .ok_or_else(|| anyhow::anyhow!("Undefined variable '{}'.", name))?;
+        match &mut self.runtime {
+            // Natives don't need the interpreter, and their errors don't
+            // get a line number, since there's no call in the source.
+            Runtime::TreeWalk(interpreter) if matches!(function, Value::Function(_)) => {
+                interpreter.call(&self.ast, &function, arguments)
+            }
+            Runtime::TreeWalk(_) => value::call(&function, arguments),
+            Runtime::Vm(vm) => vm.call(&function, arguments),
+        }
+    }
+
+    /// A global the program defined, or else a native.
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        let name = Symbol::intern(name);
+        match &self.runtime {
+            Runtime::TreeWalk(interpreter) => interpreter.get_global(name),
+            Runtime::Vm(vm) => vm.get_global(name),
+        }
+    }
+
+    /// Defines a global, or replaces it if it already exists.
+    pub fn set_global(&mut self, name: &str, value: impl Into<Value>) {
+        let name = Symbol::intern(name);
+        match &mut self.runtime {
+            Runtime::TreeWalk(interpreter) => interpreter.set_global(name, value.into()),
+            Runtime::Vm(vm) => vm.set_global(name, value.into()),
+        }
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An in-memory `Write` whose clones share the same buffer, so output can be
+/// read back after handing a clone to `Engine::set_stdout`.
+#[derive(Default, Clone)]
+pub struct OutputBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written so far. Invalid UTF-8 is replaced.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::LimitExceeded;
+    use crate::limits::LimitKind;
+    use crate::native::NativeClass;
+
+    fn engines() -> [Engine; 2] {
+        [
+            Engine::with_natives(Backend::TreeWalk, stdlib::core()),
+            Engine::with_natives(Backend::Vm, stdlib::core()),
+        ]
+    }
+
+    #[test]
+    fn eval() {
+        for mut engine in engines() {
+            assert_eq!(engine.eval("1 + 2").unwrap(), Value::Number(3.0));
+            assert_eq!(engine.eval("var a = \"x\";").unwrap(), Value::Nil);
+            // Globals are kept between calls.
+            assert_eq!(engine.eval("a + a;").unwrap(), Value::from("xx"));
+            assert_eq!(engine.eval("").unwrap(), Value::Nil);
+            assert_eq!(
+                engine.eval("a +").unwrap_err().to_string(),
+                "[line 1] Error at end: Unexpected token."
+            );
+            assert_eq!(
+                engine.eval("@").unwrap_err().to_string(),
+                "[line 1] Error (TODO where): Unexpected character."
+            );
+        }
+    }
+
+    #[test]
+    fn globals() {
+        for mut engine in engines() {
+            engine.set_global("n", 2);
+            engine.set_global("name", "lox");
+            engine.set_global("missing", None::<f64>);
+            assert_eq!(
+                engine.eval("missing == nil and name == \"lox\"").unwrap(),
+                Value::Bool(true)
+            );
+            engine.eval("n = n * 3;").unwrap();
+            assert_eq!(f64::try_from(engine.get_global("n").unwrap()).unwrap(), 6.0);
+            assert_eq!(
+                String::try_from(engine.get_global("n").unwrap())
+                    .unwrap_err()
+                    .to_string(),
+                "Expected a string but got a number."
+            );
+            assert!(engine.get_global("clock").is_some());
+            assert_eq!(engine.get_global("nope"), None);
+        }
+    }
+
+    #[test]
+    fn call() {
+        let mut natives = NativeRegistry::new();
+        natives.register("greet", 1, |arguments| {
+            let name = String::try_from(arguments[0].clone())?;
+            Ok(Value::from(format!("hello, {}", name)))
+        });
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let mut engine = Engine::with_natives(backend, natives.clone());
+            assert_eq!(
+                engine.call("greet", &["you".into()]).unwrap(),
+                Value::from("hello, you")
+            );
+            assert_eq!(
+                engine.eval("greet(1)").unwrap_err().to_string(),
+                "[line 1] Expected a string but got a number."
+            );
+            assert_eq!(
+                engine.call("nope", &[]).unwrap_err().to_string(),
+                "Undefined variable 'nope'."
+            );
+        }
+    }
+
+    #[test]
+    fn lox_functions() {
+        for mut engine in engines() {
+            engine
+                .eval("var base = 10; var add = fun (x) { return base + x; };")
+                .unwrap();
+            assert_eq!(
+                engine.call("add", &[Value::Number(1.0)]).unwrap(),
+                Value::Number(11.0)
+            );
+            // The function still sees the global, not a copy of it.
+            engine.set_global("base", 20);
+            assert_eq!(
+                engine.call("add", &[Value::Number(1.0)]).unwrap(),
+                Value::Number(21.0)
+            );
+            assert_eq!(
+                engine.call("add", &[]).unwrap_err().to_string(),
+                "Expected 1 arguments but got 0."
+            );
+            assert_eq!(
+                engine.call("add", &["x".into()]).unwrap_err().to_string(),
+                "[line 1] Operands must be two numbers or two strings."
+            );
+            let add = engine.get_global("add").unwrap();
+            assert_eq!(add.to_string(), "<fn>");
+        }
+
+        // Each backend can only call the functions it made.
+        let [mut tree_walk, mut vm] = engines();
+        vm.set_global("f", tree_walk.eval("fun () {}").unwrap());
+        tree_walk.set_global("f", vm.eval("fun () {}").unwrap());
+        for mut engine in [tree_walk, vm] {
+            assert_eq!(
+                engine.eval("f()").unwrap_err().to_string(),
+                "[line 1] Can't call a function made by another interpreter."
+            );
+        }
+    }
+
+    #[test]
+    fn native_classes() {
+        // Every instance reports to the same host-side total.
+        let total = Rc::new(RefCell::new(0.0));
+        let class_total = total.clone();
+        let counter = NativeClass::builder("Counter", 0, move |_| Ok((0.0, class_total.clone())))
+            .method(
+                "incr",
+                0,
+                |(count, total): &mut (f64, Rc<RefCell<f64>>), _| {
+                    *count += 1.0;
+                    *total.borrow_mut() += 1.0;
+                    Ok(Value::Number(*count))
+                },
+            )
+            .property("count", |(count, _)| Value::Number(*count))
+            .build();
+        let mut natives = stdlib::core();
+        natives.register_class(counter);
+
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let mut engine = Engine::with_natives(backend, natives.clone());
+            let source = "var a = Counter(); var b = Counter(); a.incr(); a.incr(); b.incr();";
+            engine.eval(source).unwrap();
+            assert_eq!(
+                engine.eval("a.count + b.count * 10").unwrap(),
+                Value::Number(12.0)
+            );
+            assert_eq!(
+                engine.eval("type(Counter) + \" \" + str(a)").unwrap(),
+                Value::from("class Counter instance")
+            );
+            assert_eq!(engine.eval("a == a and a != b").unwrap(), Value::Bool(true));
+            assert_eq!(
+                engine.eval("a.nope").unwrap_err().to_string(),
+                "[line 1] Undefined property 'nope'."
+            );
+            assert_eq!(
+                engine.eval("Counter(1)").unwrap_err().to_string(),
+                "[line 1] Expected 0 arguments but got 1."
+            );
+
+            let Some(Value::NativeInstance(a)) = engine.get_global("a") else {
+                panic!("a should be an instance");
+            };
+            assert_eq!(a.data::<(f64, Rc<RefCell<f64>>)>().unwrap().0, 2.0);
+        }
+        assert_eq!(*total.borrow(), 6.0);
+    }
+
+    #[test]
+    fn limits() {
+        for mut engine in engines() {
+            engine.set_limits(Limits {
+                max_steps: Some(1000),
+                ..Limits::default()
+            });
+            let err = engine
+                .eval("var a = 0;\nwhile (true) {\n  a = a + 1;\n}")
+                .unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<LimitExceeded>().map(|err| err.kind),
+                Some(LimitKind::Steps)
+            );
+            assert!(err.to_string().ends_with("Step limit exceeded."));
+            // Each call gets its own budget.
+            assert_eq!(engine.eval("a > 0").unwrap(), Value::Bool(true));
+
+            engine.set_limits(Limits {
+                max_call_depth: Some(0),
+                ..Limits::default()
+            });
+            let err = engine.eval("1;\nclock();").unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<LimitExceeded>(),
+                Some(&LimitExceeded {
+                    kind: LimitKind::CallDepth,
+                    line_number: 2
+                })
+            );
+            assert_eq!(err.to_string(), "[line 2] Stack overflow.");
+        }
+
+        // Only the VM has a heap to measure.
+        let mut engine = Engine::with_natives(Backend::Vm, stdlib::core());
+        engine.set_limits(Limits {
+            max_heap_bytes: Some(64 * 1024),
+            ..Limits::default()
+        });
+        // Garbage doesn't count.
+        engine
+            .eval("for (var i = 0; i < 1000; i = i + 1) { var s = str(i) + \"x\"; }")
+            .unwrap();
+        let err = engine
+            .eval("var s = \"x\";\nwhile (true) s = s + s;")
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(&LimitExceeded {
+                kind: LimitKind::HeapBytes,
+                line_number: 2
+            })
+        );
+        // Lists and maps count as they grow, not just when they're made.
+        for source in [
+            "var l = [];\nwhile (true) l.push(1);",
+            "var m = {};\nfor (var i = 0; true; i = i + 1) m[i] = i;",
+        ] {
+            let err = engine.eval(source).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<LimitExceeded>(),
+                Some(&LimitExceeded {
+                    kind: LimitKind::HeapBytes,
+                    line_number: 2
+                })
+            );
+        }
+        // So do lists that contain themselves, which are garbage like anything
+        // else once they can't be reached. A fresh engine, since the globals
+        // above are still live.
+        let mut engine = Engine::with_natives(Backend::Vm, stdlib::core());
+        engine.set_limits(Limits {
+            max_heap_bytes: Some(64 * 1024),
+            ..Limits::default()
+        });
+        engine
+            .eval("for (var i = 0; i < 1000; i = i + 1) { var l = [1, 2, 3]; l.push(l); }")
+            .unwrap();
+        let err = engine
+            .eval("var head = nil;\nwhile (true) { var l = [head, 1, 2, 3]; l.push(l); head = l; }")
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(&LimitExceeded {
+                kind: LimitKind::HeapBytes,
+                line_number: 2
+            })
+        );
+    }
+
+    #[test]
+    fn capabilities() {
+        for backend in [Backend::TreeWalk, Backend::Vm] {
+            let mut engine = Engine::with_capabilities(
+                backend,
+                Capabilities {
+                    time: true,
+                    ..Capabilities::none()
+                },
+            );
+            assert!(engine.eval("clock() > 0").is_ok());
+            assert_eq!(
+                engine.eval("exit(1);").unwrap_err().to_string(),
+                "[line 1] Capability denied: exit() needs the 'process' capability."
+            );
+        }
+    }
+
+    #[test]
+    fn output() {
+        for mut engine in engines() {
+            let stdout = OutputBuffer::new();
+            engine.set_stdout(stdout.clone());
+            engine.eval("print 1; print \"two\";").unwrap();
+            assert_eq!(stdout.contents(), "1\ntwo\n");
+        }
+    }
+}
@@ -1,25 +1,389 @@
-use crate::scanner;
+use crate::scanner::Token;
+use crate::stmt::Stmt;
+use crate::stmt::StmtId;
+use crate::symbol::Symbol;
+use serde::Deserialize;
+use serde::Serialize;
 
 pub trait Visitor {
     type Result;
 
-    fn visit_expr(&mut self, expr: &Expr) -> Self::Result;
+    fn visit_expr(&mut self, ast: &Ast, expr: NodeId) -> Self::Result;
 }
 
-// TODO: maybe expr should take tokens as reference? It would avoid cloning
-pub enum Expr<'a> {
+/// Identifies an expression node inside of an `Ast`. Later passes can key
+/// side tables off of these instead of mutating the tree.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub(crate) fn from_index(index: usize) -> Self {
+        NodeId(u32::try_from(index).expect("too many nodes for a single Ast"))
+    }
+
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Arena that owns every expression and statement node. Children are referred
+/// to by `NodeId` (or `StmtId`), so a node is always allocated after its
+/// children.
+///
+/// Nodes own their data rather than borrowing from the source, so an `Ast` can
+/// outlive the input it was parsed from (e.g. a REPL line), and can be sent
+/// between threads.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Ast {
+    nodes: Vec<Expr>,
+    stmts: Vec<Stmt>,
+}
+
+impl Ast {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            stmts: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, expr: Expr) -> NodeId {
+        let id = NodeId::from_index(self.nodes.len());
+        self.nodes.push(expr);
+        id
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Expr> {
+        self.nodes.get(id.index())
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Every node in allocation order, i.e. children before their parents.
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &Expr)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(index, expr)| (NodeId::from_index(index), expr))
+    }
+
+    pub fn add_stmt(&mut self, stmt: Stmt) -> StmtId {
+        let id = StmtId::from_index(self.stmts.len());
+        self.stmts.push(stmt);
+        id
+    }
+
+    pub fn get_stmt(&self, id: StmtId) -> Option<&Stmt> {
+        self.stmts.get(id.index())
+    }
+
+    pub fn stmts_len(&self) -> usize {
+        self.stmts.len()
+    }
+}
+
+impl std::ops::Index<NodeId> for Ast {
+    type Output = Expr;
+
+    fn index(&self, id: NodeId) -> &Self::Output {
+        &self.nodes[id.index()]
+    }
+}
+
+impl std::ops::Index<StmtId> for Ast {
+    type Output = Stmt;
+
+    fn index(&self, id: StmtId) -> &Self::Output {
+        &self.stmts[id.index()]
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum Expr {
+    Assign {
+        name: Symbol,
+        line_number: usize,
+        value: NodeId,
+    },
     Binary {
-        left: Box<Expr<'a>>,
-        operator: scanner::AnnotatedToken<'a>,
-        right: Box<Expr<'a>>,
-    },
-    Grouping(Box<Expr<'a>>),
-    Nil,
-    LiteralNumber(f64),
-    LiteralString(&'a str),
-    LiteralBool(bool),
+        left: NodeId,
+        operator: BinaryOp,
+        line_number: usize,
+        right: NodeId,
+    },
+    /// `callee(arguments)`. The line is the closing parenthesis's.
+    Call {
+        callee: NodeId,
+        line_number: usize,
+        arguments: Vec<NodeId>,
+    },
+    /// `condition ? then_branch : else_branch`
+    Conditional {
+        condition: NodeId,
+        then_branch: NodeId,
+        else_branch: NodeId,
+    },
+    /// An anonymous function, `fun (params) { ... }`. The body is always a
+    /// `Stmt::Block`, whose statements share a scope with the parameters. The
+    /// line is the `fun` keyword's.
+    Function {
+        params: Vec<Symbol>,
+        body: StmtId,
+        line_number: usize,
+    },
+    /// Property access, `object.name`.
+    Get {
+        object: NodeId,
+        name: Symbol,
+        line_number: usize,
+    },
+    Grouping(NodeId),
+    /// `object[index]`. The line is the closing bracket's.
+    Index {
+        object: NodeId,
+        index: NodeId,
+        line_number: usize,
+    },
+    /// A list literal, `[elements]`.
+    List(Vec<NodeId>),
+    /// A map literal, `{key: value, ...}`, as its key and value pairs in
+    /// order.
+    Map(Vec<(NodeId, NodeId)>),
+    Nil {
+        line_number: usize,
+    },
+    LiteralNumber {
+        value: f64,
+        line_number: usize,
+    },
+    /// The value is the string's contents, without the surrounding quotes.
+    LiteralString {
+        value: String,
+        line_number: usize,
+    },
+    LiteralBool {
+        value: bool,
+        line_number: usize,
+    },
+    /// Short-circuiting `and`/`or`.
+    Logical {
+        left: NodeId,
+        operator: LogicalOp,
+        right: NodeId,
+    },
+    /// `object[index] = value`. The line is the closing bracket's, like
+    /// `Index`.
+    SetIndex {
+        object: NodeId,
+        index: NodeId,
+        value: NodeId,
+        line_number: usize,
+    },
     Unary {
-        operator: scanner::AnnotatedToken<'a>,
-        right: Box<Expr<'a>>,
+        operator: UnaryOp,
+        line_number: usize,
+        right: NodeId,
     },
+    Variable {
+        name: Symbol,
+        line_number: usize,
+    },
+}
+
+impl Expr {
+    /// Where the node came from, for those that record it.
+    pub fn line_number(&self) -> Option<usize> {
+        match self {
+            Expr::Assign { line_number, .. }
+            | Expr::Binary { line_number, .. }
+            | Expr::Call { line_number, .. }
+            | Expr::Function { line_number, .. }
+            | Expr::Get { line_number, .. }
+            | Expr::Index { line_number, .. }
+            | Expr::LiteralBool { line_number, .. }
+            | Expr::LiteralNumber { line_number, .. }
+            | Expr::LiteralString { line_number, .. }
+            | Expr::Nil { line_number }
+            | Expr::SetIndex { line_number, .. }
+            | Expr::Unary { line_number, .. }
+            | Expr::Variable { line_number, .. } => Some(*line_number),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum BinaryOp {
+    /// Evaluates both operands, producing the right one.
+    #[serde(rename = ",")]
+    Comma,
+    #[serde(rename = "==")]
+    Equal,
+    #[serde(rename = "!=")]
+    NotEqual,
+    #[serde(rename = ">")]
+    Greater,
+    #[serde(rename = ">=")]
+    GreaterEqual,
+    #[serde(rename = "<")]
+    Less,
+    #[serde(rename = "<=")]
+    LessEqual,
+    #[serde(rename = "+")]
+    Add,
+    #[serde(rename = "-")]
+    Subtract,
+    #[serde(rename = "*")]
+    Multiply,
+    #[serde(rename = "/")]
+    Divide,
+}
+
+impl BinaryOp {
+    pub fn from_token(token: &Token) -> Option<Self> {
+        let op = match token {
+            Token::Comma => BinaryOp::Comma,
+            Token::EqualEqual => BinaryOp::Equal,
+            Token::BangEqual => BinaryOp::NotEqual,
+            Token::Greater => BinaryOp::Greater,
+            Token::GreaterEqual => BinaryOp::GreaterEqual,
+            Token::Less => BinaryOp::Less,
+            Token::LessEqual => BinaryOp::LessEqual,
+            Token::Plus => BinaryOp::Add,
+            Token::Minus => BinaryOp::Subtract,
+            Token::Star => BinaryOp::Multiply,
+            Token::Slash => BinaryOp::Divide,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    pub fn from_lexeme(lexeme: &str) -> Option<Self> {
+        let op = match lexeme {
+            "," => BinaryOp::Comma,
+            "==" => BinaryOp::Equal,
+            "!=" => BinaryOp::NotEqual,
+            ">" => BinaryOp::Greater,
+            ">=" => BinaryOp::GreaterEqual,
+            "<" => BinaryOp::Less,
+            "<=" => BinaryOp::LessEqual,
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Subtract,
+            "*" => BinaryOp::Multiply,
+            "/" => BinaryOp::Divide,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    /// The operator as it's written in Lox source.
+    pub fn lexeme(&self) -> &'static str {
+        match self {
+            BinaryOp::Comma => ",",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+        }
+    }
+}
+
+impl std::fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.lexeme())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum UnaryOp {
+    #[serde(rename = "+")]
+    Plus,
+    #[serde(rename = "-")]
+    Minus,
+}
+
+impl UnaryOp {
+    pub fn from_token(token: &Token) -> Option<Self> {
+        match token {
+            Token::Plus => Some(UnaryOp::Plus),
+            Token::Minus => Some(UnaryOp::Minus),
+            _ => None,
+        }
+    }
+
+    pub fn from_lexeme(lexeme: &str) -> Option<Self> {
+        match lexeme {
+            "+" => Some(UnaryOp::Plus),
+            "-" => Some(UnaryOp::Minus),
+            _ => None,
+        }
+    }
+
+    /// The operator as it's written in Lox source.
+    pub fn lexeme(&self) -> &'static str {
+        match self {
+            UnaryOp::Plus => "+",
+            UnaryOp::Minus => "-",
+        }
+    }
+}
+
+impl std::fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.lexeme())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum LogicalOp {
+    #[serde(rename = "and")]
+    And,
+    #[serde(rename = "or")]
+    Or,
+}
+
+impl LogicalOp {
+    pub fn from_token(token: &Token) -> Option<Self> {
+        match token {
+            Token::And => Some(LogicalOp::And),
+            Token::Or => Some(LogicalOp::Or),
+            _ => None,
+        }
+    }
+
+    pub fn from_lexeme(lexeme: &str) -> Option<Self> {
+        match lexeme {
+            "and" => Some(LogicalOp::And),
+            "or" => Some(LogicalOp::Or),
+            _ => None,
+        }
+    }
+
+    /// The operator as it's written in Lox source.
+    pub fn lexeme(&self) -> &'static str {
+        match self {
+            LogicalOp::And => "and",
+            LogicalOp::Or => "or",
+        }
+    }
+}
+
+impl std::fmt::Display for LogicalOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.lexeme())
+    }
 }
@@ -0,0 +1,692 @@
+use crate::expr;
+use crate::expr::Ast;
+use crate::expr::BinaryOp;
+use crate::expr::Expr;
+use crate::expr::LogicalOp;
+use crate::expr::NodeId;
+use crate::expr::Visitor;
+use crate::parser::Parser;
+use crate::scanner::AnnotatedToken;
+use crate::scanner::Comment;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+use crate::stmt::StmtId;
+use std::collections::HashMap;
+use std::ops::Range;
+
+const INDENT: &str = "  ";
+
+/// Stands in for a function's body in a formatted expression.
+/// NOTE: a string literal with this character in it would confuse
+/// `format_source`.
+pub const BODY_MARKER: char = '\0';
+
+/// Prints expressions back as canonical Lox: single spaces around binary
+/// operators, and only the parentheses that precedence actually requires.
+///
+/// A function's body is made of statements, which `format_source` lays out:
+/// here it's left as a `BODY_MARKER` between the braces, e.g. `fun (a) {\0}`.
+#[derive(Default)]
+pub struct Formatter {
+    // The bodies the markers stand for, in order.
+    bodies: Vec<StmtId>,
+}
+
+impl expr::Visitor for Formatter {
+    type Result = String;
+
+    fn visit_expr(&mut self, ast: &Ast, expr: NodeId) -> Self::Result {
+        self.format_expr(ast, expr, Precedence::Lowest)
+    }
+}
+
+impl Formatter {
+    // `min_precedence` is the loosest-binding expression that can appear here
+    // without parentheses.
+    fn format_expr(&mut self, ast: &Ast, expr: NodeId, min_precedence: Precedence) -> String {
+        use Expr::*;
+        let precedence = precedence(ast, expr);
+        let formatted = match &ast[expr] {
+            // Parentheses are added back below if they're needed.
+            Grouping(inner) => return self.format_expr(ast, *inner, min_precedence),
+            Assign { name, value, .. } => {
+                // Right-associative.
+                format!("{} = {}", name, self.format_expr(ast, *value, precedence))
+            }
+            Binary {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                // Binary operators are all left-associative, so the right
+                // operand has to bind tighter than the operator itself.
+                let left = self.format_expr(ast, *left, precedence);
+                let right = self.format_expr(ast, *right, precedence.next());
+                if *operator == BinaryOp::Comma {
+                    format!("{}, {}", left, right)
+                } else {
+                    format!("{} {} {}", left, operator, right)
+                }
+            }
+            Call {
+                callee, arguments, ..
+            } => {
+                // The callee goes first, so that function bodies are
+                // collected in order.
+                let callee = self.format_expr(ast, *callee, precedence);
+                // Commas would be read as separating arguments, so comma
+                // expressions need parentheses.
+                let arguments: Vec<_> = arguments
+                    .iter()
+                    .map(|argument| self.format_expr(ast, *argument, Precedence::Assignment))
+                    .collect();
+                format!("{}({})", callee, arguments.join(", "))
+            }
+            Function { params, body, .. } => {
+                self.bodies.push(*body);
+                let params: Vec<_> = params.iter().map(|param| param.to_string()).collect();
+                format!("fun ({}) {{{}}}", params.join(", "), BODY_MARKER)
+            }
+            Get { object, name, .. } => {
+                format!("{}.{}", self.format_expr(ast, *object, precedence), name)
+            }
+            Index { object, index, .. } => format!(
+                "{}[{}]",
+                self.format_expr(ast, *object, precedence),
+                self.format_expr(ast, *index, Precedence::Lowest)
+            ),
+            SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => format!(
+                "{}[{}] = {}",
+                self.format_expr(ast, *object, Precedence::Call),
+                self.format_expr(ast, *index, Precedence::Lowest),
+                self.format_expr(ast, *value, precedence)
+            ),
+            List(elements) => {
+                // Like arguments, commas separate the elements.
+                let elements: Vec<_> = elements
+                    .iter()
+                    .map(|element| self.format_expr(ast, *element, Precedence::Assignment))
+                    .collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Map(entries) => {
+                let entries: Vec<_> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        format!(
+                            "{}: {}",
+                            self.format_expr(ast, *key, Precedence::Assignment),
+                            self.format_expr(ast, *value, Precedence::Assignment)
+                        )
+                    })
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                // Right-associative, and anything goes in the middle.
+                format!(
+                    "{} ? {} : {}",
+                    self.format_expr(ast, *condition, precedence.next()),
+                    self.format_expr(ast, *then_branch, Precedence::Lowest),
+                    self.format_expr(ast, *else_branch, precedence)
+                )
+            }
+            Logical {
+                left,
+                operator,
+                right,
+            } => format!(
+                "{} {} {}",
+                self.format_expr(ast, *left, precedence),
+                operator,
+                self.format_expr(ast, *right, precedence.next())
+            ),
+            Unary {
+                operator, right, ..
+            } => {
+                format!("{}{}", operator, self.format_expr(ast, *right, precedence))
+            }
+            LiteralNumber { value: number, .. } => format!("{}", number),
+            LiteralString { value: ss, .. } => format!("\"{}\"", ss),
+            LiteralBool { value: bb, .. } => format!("{}", bb),
+            Nil { .. } => "nil".to_owned(),
+            Variable { name, .. } => name.to_string(),
+        };
+
+        if precedence < min_precedence {
+            format!("({})", formatted)
+        } else {
+            formatted
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+enum Precedence {
+    Lowest,
+    Comma,
+    Assignment,
+    Conditional,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+    Primary,
+}
+
+impl Precedence {
+    fn next(self) -> Self {
+        use Precedence::*;
+        match self {
+            Lowest => Comma,
+            Comma => Assignment,
+            Assignment => Conditional,
+            Conditional => Or,
+            Or => And,
+            And => Equality,
+            Equality => Comparison,
+            Comparison => Term,
+            Term => Factor,
+            Factor => Unary,
+            Unary => Call,
+            Call | Primary => Primary,
+        }
+    }
+}
+
+fn precedence(ast: &Ast, expr: NodeId) -> Precedence {
+    match &ast[expr] {
+        Expr::Assign { .. } | Expr::SetIndex { .. } => Precedence::Assignment,
+        Expr::Binary { operator, .. } => match operator {
+            BinaryOp::Comma => Precedence::Comma,
+            BinaryOp::Equal | BinaryOp::NotEqual => Precedence::Equality,
+            BinaryOp::Greater | BinaryOp::GreaterEqual | BinaryOp::Less | BinaryOp::LessEqual => {
+                Precedence::Comparison
+            }
+            BinaryOp::Add | BinaryOp::Subtract => Precedence::Term,
+            BinaryOp::Multiply | BinaryOp::Divide => Precedence::Factor,
+        },
+        Expr::Conditional { .. } => Precedence::Conditional,
+        Expr::Logical { operator, .. } => match operator {
+            LogicalOp::Or => Precedence::Or,
+            LogicalOp::And => Precedence::And,
+        },
+        Expr::Unary { .. } => Precedence::Unary,
+        Expr::Call { .. } | Expr::Get { .. } | Expr::Index { .. } => Precedence::Call,
+        Expr::Grouping(inner) => precedence(ast, *inner),
+        _ => Precedence::Primary,
+    }
+}
+
+/// Formats a whole source file.
+///
+/// Statements go on their own lines, indented by two spaces per block, with
+/// opening braces on the same line as the `if`/`while`/`for`/`else` they
+/// belong to. A single blank line between statements is kept.
+///
+/// Comments are kept: those at the end of a line stay there, while those
+/// inside of a line of code are moved onto their own lines above it.
+pub fn format_source(source: &str) -> anyhow::Result<String> {
+    let mut scanner = Scanner::new(source);
+    // Cloning is cheap since tokens borrow from `source`, and it frees up the
+    // scanner so we can ask it for comments.
+    let tokens = scanner.scan_tokens().clone();
+    if scanner.has_error() {
+        anyhow::bail!("Couldn't scan source:\n{}", scanner.errors().join("\n"));
+    }
+
+    let mut ast = Ast::new();
+    let mut parser = Parser::new(&tokens, &mut ast);
+    let statements = parser.parse_program()?;
+    let statement_tokens = parser.statement_tokens().clone();
+
+    let mut printer = ProgramPrinter {
+        ast: &ast,
+        tokens: &tokens,
+        statement_tokens: &statement_tokens,
+        comments: scanner.comments(),
+        next_comment: 0,
+        indent: 0,
+        last_line_number: None,
+        bodies: Vec::new(),
+        output: String::new(),
+    };
+    for stmt in statements {
+        printer.stmt(stmt);
+    }
+    // Whatever is left is at the end of the file.
+    printer.flush_comments(tokens.len() - 1);
+    Ok(printer.output)
+}
+
+// Lays out statements line by line, weaving comments back in by the index of
+// the token they preceded.
+struct ProgramPrinter<'a> {
+    ast: &'a Ast,
+    tokens: &'a [AnnotatedToken<'a>],
+    statement_tokens: &'a HashMap<StmtId, Range<usize>>,
+    comments: &'a [Comment<'a>],
+    next_comment: usize,
+    indent: usize,
+    // Source line of what was printed last, for keeping blank lines. `None`
+    // right after an opening brace, where blank lines are dropped.
+    last_line_number: Option<usize>,
+    // Bodies of the functions in the expressions formatted for the next line.
+    bodies: Vec<StmtId>,
+    output: String,
+}
+
+impl ProgramPrinter<'_> {
+    fn stmt(&mut self, stmt: StmtId) {
+        let span = self.statement_tokens[&stmt].clone();
+        let last_token = span.end - 1;
+        match &self.ast[stmt] {
+            Stmt::Block(statements) => {
+                self.line("{".to_owned(), span.start, span.start);
+                self.block_contents(statements, last_token);
+            }
+            Stmt::For { body, .. } => {
+                let header = self.for_header(stmt);
+                if let Some(brace) = self.header_and_body(header, span.start, *body) {
+                    self.line("}".to_owned(), brace, brace);
+                }
+            }
+            Stmt::If { .. } => self.if_stmt(stmt, ""),
+            Stmt::While {
+                condition, body, ..
+            } => {
+                let header = format!("while ({})", self.expr(*condition));
+                if let Some(brace) = self.header_and_body(header, span.start, *body) {
+                    self.line("}".to_owned(), brace, brace);
+                }
+            }
+            Stmt::Break { .. }
+            | Stmt::Continue { .. }
+            | Stmt::Expression { .. }
+            | Stmt::Print { .. }
+            | Stmt::Return { .. }
+            | Stmt::Var { .. } => {
+                let text = self.simple_stmt(stmt);
+                self.line(text, span.start, last_token);
+            }
+        }
+    }
+
+    // `else if` chains stay flat, so `prefix` is `"} else "` or `"else "` for
+    // all but the first `if`.
+    fn if_stmt(&mut self, stmt: StmtId, prefix: &str) {
+        let Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } = &self.ast[stmt]
+        else {
+            unreachable!("if_stmt called on another statement");
+        };
+        let header = format!("{}if ({})", prefix, self.expr(*condition));
+        let first_token = self.statement_tokens[&stmt].start;
+        let mut closing_brace = self.header_and_body(header, first_token, *then_branch);
+
+        if let Some(else_branch) = else_branch {
+            let prefix = match closing_brace {
+                Some(brace) => {
+                    self.flush_comments_indented(brace);
+                    "} else"
+                }
+                None => "else",
+            };
+            if let Stmt::If { .. } = self.ast[*else_branch] {
+                return self.if_stmt(*else_branch, &format!("{} ", prefix));
+            }
+            // The `else` keyword.
+            let first_token = self.statement_tokens[else_branch].start - 1;
+            closing_brace = self.header_and_body(prefix.to_owned(), first_token, *else_branch);
+        }
+        if let Some(brace) = closing_brace {
+            self.line("}".to_owned(), brace, brace);
+        }
+    }
+
+    // Prints `header` and then `body`. A block's opening brace goes on the
+    // header's line, and the index of its closing brace is returned so the
+    // caller can decide what goes on that line (e.g. `} else {`).
+    fn header_and_body(
+        &mut self,
+        header: String,
+        first_token: usize,
+        body: StmtId,
+    ) -> Option<usize> {
+        let span = self.statement_tokens[&body].clone();
+        match &self.ast[body] {
+            Stmt::Block(statements) => {
+                self.line(format!("{} {{", header), first_token, span.start);
+                let brace = span.end - 1;
+                self.block_contents_without_brace(statements, brace);
+                Some(brace)
+            }
+            _ => {
+                // The header ends just before the body.
+                self.line(header, first_token, span.start - 1);
+                self.indent += 1;
+                self.stmt(body);
+                self.indent -= 1;
+                None
+            }
+        }
+    }
+
+    fn block_contents(&mut self, statements: &[StmtId], closing_brace: usize) {
+        self.block_contents_without_brace(statements, closing_brace);
+        self.line("}".to_owned(), closing_brace, closing_brace);
+    }
+
+    fn block_contents_without_brace(&mut self, statements: &[StmtId], closing_brace: usize) {
+        self.indent += 1;
+        self.last_line_number = None;
+        for stmt in statements {
+            self.stmt(*stmt);
+        }
+        self.flush_comments(closing_brace);
+        self.indent -= 1;
+        // No blank lines before the closing brace either.
+        self.last_line_number = None;
+    }
+
+    fn flush_comments_indented(&mut self, closing_brace: usize) {
+        self.indent += 1;
+        self.flush_comments(closing_brace);
+        self.indent -= 1;
+        self.last_line_number = None;
+    }
+
+    fn for_header(&mut self, stmt: StmtId) -> String {
+        let Stmt::For {
+            initializer,
+            condition,
+            increment,
+            ..
+        } = &self.ast[stmt]
+        else {
+            unreachable!("for_header called on another statement");
+        };
+        let initializer = match initializer {
+            Some(initializer) => self.simple_stmt(*initializer),
+            None => ";".to_owned(),
+        };
+        let condition = match condition {
+            Some(condition) => format!(" {}", self.expr(*condition)),
+            None => String::new(),
+        };
+        let increment = match increment {
+            Some(increment) => format!(" {}", self.expr(*increment)),
+            None => String::new(),
+        };
+        format!("for ({}{};{})", initializer, condition, increment)
+    }
+
+    // Statements that fit on a single line, including their `;`.
+    fn simple_stmt(&mut self, stmt: StmtId) -> String {
+        match &self.ast[stmt] {
+            Stmt::Expression { expr, .. } => {
+                let expr = self.expr(*expr);
+                // Only a map formats starting with a brace, and at the start
+                // of a statement it would be read as a block.
+                if expr.starts_with('{') {
+                    format!("({});", expr)
+                } else {
+                    format!("{};", expr)
+                }
+            }
+            Stmt::Print { expr, .. } => format!("print {};", self.expr(*expr)),
+            Stmt::Return {
+                value: Some(value), ..
+            } => format!("return {};", self.expr(*value)),
+            Stmt::Return { .. } => "return;".to_owned(),
+            Stmt::Var {
+                name,
+                initializer: Some(initializer),
+                ..
+            } => format!("var {} = {};", name, self.expr(*initializer)),
+            Stmt::Var { name, .. } => format!("var {};", name),
+            Stmt::Break { .. } => "break;".to_owned(),
+            Stmt::Continue { .. } => "continue;".to_owned(),
+            _ => unreachable!("not a simple statement"),
+        }
+    }
+
+    // Formats an expression for the next line, keeping its function bodies
+    // for `line` to print.
+    fn expr(&mut self, expr: NodeId) -> String {
+        let mut formatter = Formatter::default();
+        let formatted = formatter.visit_expr(self.ast, expr);
+        self.bodies.append(&mut formatter.bodies);
+        formatted
+    }
+
+    // Prints a line of code made from the tokens `first_token..=last_token`.
+    // Function bodies in it are laid out like blocks, splitting the line
+    // around them.
+    fn line(&mut self, text: String, first_token: usize, last_token: usize) {
+        let ast = self.ast;
+        let mut parts = text.split(BODY_MARKER);
+        let mut first_token = first_token;
+        for body in std::mem::take(&mut self.bodies) {
+            let span = self.statement_tokens[&body].clone();
+            let Stmt::Block(statements) = &ast[body] else {
+                unreachable!("function bodies are blocks");
+            };
+            // This part of the line ends with the body's opening brace, and
+            // the next starts with its closing one.
+            self.single_line(parts.next().unwrap_or_default(), first_token, span.start);
+            self.block_contents_without_brace(statements, span.end - 1);
+            first_token = span.end - 1;
+        }
+        self.single_line(parts.next().unwrap_or_default(), first_token, last_token);
+    }
+
+    // Comments from before the last token go above the line, and a comment
+    // right after it on the same source line stays at the end.
+    fn single_line(&mut self, text: &str, first_token: usize, last_token: usize) {
+        self.flush_comments(last_token);
+        let line_number = self.tokens[last_token].line_number;
+        self.push_line(text, self.tokens[first_token].line_number, line_number);
+
+        if let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.token_index == last_token + 1 && comment.line_number == line_number {
+                // Replace the newline.
+                self.output.pop();
+                self.output.push(' ');
+                self.output.push_str(comment.text);
+                self.output.push('\n');
+                self.next_comment += 1;
+            }
+        }
+    }
+
+    // Prints every comment that comes before the token at `token_index` on
+    // its own line.
+    fn flush_comments(&mut self, token_index: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.token_index > token_index {
+                break;
+            }
+            self.push_line(comment.text, comment.line_number, comment.line_number);
+            self.next_comment += 1;
+        }
+    }
+
+    // The line numbers are where the text started and ended in the source.
+    fn push_line(&mut self, text: &str, first_line_number: usize, last_line_number: usize) {
+        if self
+            .last_line_number
+            .is_some_and(|previous| first_line_number > previous + 1)
+        {
+            self.output.push('\n');
+        }
+        self.last_line_number = Some(last_line_number);
+        self.output.push_str(&INDENT.repeat(self.indent));
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_formats(source: &str, expected: &str) {
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, expected);
+        // Formatting is idempotent.
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn spacing() {
+        assert_formats("1+2*  3==-  4;", "1 + 2 * 3 == -4;\n");
+    }
+
+    #[test]
+    fn redundant_parentheses_dropped() {
+        assert_formats("((1 * 2)) + (3);", "1 * 2 + 3;\n");
+        assert_formats("(1 + 2) + 3;", "1 + 2 + 3;\n");
+        assert_formats("-(1);", "-1;\n");
+    }
+
+    #[test]
+    fn required_parentheses_kept() {
+        assert_formats("(1 + 2) * 3;", "(1 + 2) * 3;\n");
+        assert_formats("1 - (2 - 3);", "1 - (2 - 3);\n");
+        assert_formats("-(1 + 2) == (1 == 2);", "-(1 + 2) == (1 == 2);\n");
+    }
+
+    #[test]
+    fn conditional_and_comma() {
+        assert_formats("1?2,3:4?5:6;", "1 ? 2, 3 : 4 ? 5 : 6;\n");
+        assert_formats("(1 ? 2 : 3) ? 4 : 5;", "(1 ? 2 : 3) ? 4 : 5;\n");
+        assert_formats("(1 , 2), 3;", "1, 2, 3;\n");
+        assert_formats("1 , (2, 3);", "1, (2, 3);\n");
+        assert_formats("(1 ? 2 : 3) + 4;", "(1 ? 2 : 3) + 4;\n");
+    }
+
+    #[test]
+    fn assignment_and_logical() {
+        assert_formats("a=(b=c);", "a = b = c;\n");
+        assert_formats("(a = b), c;", "a = b, c;\n");
+        assert_formats("a ? b : (c = d);", "a ? b : (c = d);\n");
+        assert_formats("(a or b) or (c and d);", "a or b or c and d;\n");
+        assert_formats("(a or b) and c;", "(a or b) and c;\n");
+    }
+
+    #[test]
+    fn calls() {
+        assert_formats("f ( 1,(2 , 3) ) ( );", "f(1, (2, 3))();\n");
+        assert_formats("(a + b)(c).d;", "(a + b)(c).d;\n");
+        assert_formats("-(Math . sqrt)(x = 2);", "-Math.sqrt(x = 2);\n");
+    }
+
+    #[test]
+    fn lists() {
+        assert_formats("[ 1,(2 , 3),[ ] ] ;", "[1, (2, 3), []];\n");
+        assert_formats("xs [ 0 ] [(1, 2)] = (a = b);", "xs[0][1, 2] = a = b;\n");
+        assert_formats("(a + b)[0];", "(a + b)[0];\n");
+    }
+
+    #[test]
+    fn maps() {
+        assert_formats(
+            "print {\"a\" :1,(2, 3):[ ]} ;",
+            "print {\"a\": 1, (2, 3): []};\n",
+        );
+        assert_formats("({}).keys();", "({}.keys());\n");
+        assert_formats("m[\"a\"] = {};", "m[\"a\"] = {};\n");
+    }
+
+    #[test]
+    fn literals() {
+        assert_formats("\"str\" != nil == true;", "\"str\" != nil == true;\n");
+        assert_formats("1.50;", "1.5;\n");
+    }
+
+    #[test]
+    fn statements() {
+        assert_formats(
+            "var a=1;var b;{print a;{b=a;}}",
+            "var a = 1;\nvar b;\n{\n  print a;\n  {\n    b = a;\n  }\n}\n",
+        );
+        assert_formats(
+            "while(a<3){a=a+1;}for(;;)print 1;",
+            "while (a < 3) {\n  a = a + 1;\n}\nfor (;;)\n  print 1;\n",
+        );
+        assert_formats(
+            "for(var i=0;i<3;i=i+1){}for(i=0;;){}",
+            "for (var i = 0; i < 3; i = i + 1) {\n}\nfor (i = 0;;) {\n}\n",
+        );
+    }
+
+    #[test]
+    fn if_else_chains() {
+        assert_formats(
+            "if(a){print 1;}else if(b)print 2;else{print 3;}",
+            "if (a) {\n  print 1;\n} else if (b)\n  print 2;\nelse {\n  print 3;\n}\n",
+        );
+    }
+
+    #[test]
+    fn blank_lines() {
+        assert_formats(
+            "var a;\n\n\nvar b;\n{\n\nprint a;\n\n}",
+            "var a;\n\nvar b;\n{\n  print a;\n}\n",
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_formats(
+            "// leading\n1 + // inner\n2; // trailing\n// last",
+            "// leading\n// inner\n1 + 2; // trailing\n// last\n",
+        );
+        assert_formats("// only a comment", "// only a comment\n");
+        assert_formats(
+            "if (a) { // why\n// first\nprint a;\n// end of block\n} else {\n}",
+            "if (a) { // why\n  // first\n  print a;\n  // end of block\n} else {\n}\n",
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_formats(
+            "var add=fun(a,b){return a+b;};print add(1,2);",
+            "var add = fun (a, b) {\n  return a + b;\n};\nprint add(1, 2);\n",
+        );
+        // Bodies nest, and each is laid out where it appears in the line.
+        assert_formats(
+            "f(fun(){ // why\nreturn fun(){return;};}, fun(x){})();",
+            "f(fun () { // why\n  return fun () {\n    return;\n  };\n}, fun (x) {\n})();\n",
+        );
+        assert_formats(
+            "while(fun(){return true;}()){}",
+            "while (fun () {\n  return true;\n}()) {\n}\n",
+        );
+    }
+
+    #[test]
+    fn trailing_tokens_rejected() {
+        assert!(format_source("1 2").is_err());
+        assert!(format_source("1;;").is_err());
+    }
+}
@@ -0,0 +1,507 @@
+use crate::interpreter::Function;
+use crate::map::OrderedMap;
+use crate::native::Namespace;
+use crate::native::NativeClass;
+use crate::native::NativeFunction;
+use crate::native::NativeInstance;
+use crate::symbol::InternedString;
+use crate::symbol::Symbol;
+use crate::vm::Closure;
+use crate::vm::Upvalue;
+use crate::vm_value::VmKey;
+use crate::vm_value::VmValue;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// A handle to an object on a `Heap`. Only meaningful for the heap that
+/// allocated it, and only while the object is reachable: once it's swept, the
+/// slot gets reused.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct ObjRef(u32);
+
+impl ObjRef {
+    pub(crate) fn from_index(index: usize) -> Self {
+        ObjRef(u32::try_from(index).expect("too many objects"))
+    }
+
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Anything the VM allocates.
+#[derive(Debug, PartialEq)]
+pub enum Object {
+    /// The same `InternedString` as any `Value::String` with these contents.
+    String(InternedString),
+    // Natives are shared with the `NativeRegistry`, the heap only owns the
+    // handle.
+    Native(Rc<NativeFunction>),
+    Namespace(Rc<Namespace>),
+    NativeClass(Rc<NativeClass>),
+    NativeInstance(Rc<NativeInstance>),
+    List(Vec<VmValue>),
+    Map(OrderedMap<VmKey, VmValue>),
+    /// A list or map method, read from `receiver` but not called yet.
+    BoundMethod {
+        receiver: ObjRef,
+        name: Symbol,
+    },
+    /// A closure made by this heap's VM. Its upvalues are on the heap too.
+    Closure(Rc<Closure>),
+    /// A variable captured by one or more closures.
+    Upvalue(Upvalue),
+    /// A function from the tree-walker, which the VM can hold but not call.
+    Function(Rc<Function>),
+    /// A closure made by another VM, which can't be called either. Its
+    /// upvalues are on the other VM's heap, so it isn't traced.
+    ForeignClosure(Rc<Closure>),
+}
+
+impl Object {
+    // Roughly what the object costs, to decide when to collect. Doesn't need
+    // to be exact, just to grow with the object.
+    fn size(&self) -> usize {
+        std::mem::size_of::<Entry>()
+            + match self {
+                Object::String(ss) => ss.len(),
+                Object::Native(_)
+                | Object::Namespace(_)
+                | Object::NativeClass(_)
+                | Object::NativeInstance(_) => 0,
+                Object::List(elements) => std::mem::size_of_val(elements.as_slice()),
+                Object::Map(map) => map.len() * 2 * std::mem::size_of::<VmValue>(),
+                Object::BoundMethod { .. } => 0,
+                Object::Closure(closure) => std::mem::size_of_val(closure.upvalues()),
+                Object::Upvalue(_) | Object::Function(_) | Object::ForeignClosure(_) => 0,
+            }
+    }
+
+    // What the object wraps, if it's shared with the host, so that wrapping
+    // it again gives the same object. See `Heap::alloc`.
+    fn shared(&self) -> Option<*const ()> {
+        match self {
+            Object::Native(native) => Some(Rc::as_ptr(native) as *const ()),
+            Object::Namespace(namespace) => Some(Rc::as_ptr(namespace) as *const ()),
+            Object::NativeClass(class) => Some(Rc::as_ptr(class) as *const ()),
+            Object::NativeInstance(instance) => Some(Rc::as_ptr(instance) as *const ()),
+            Object::Closure(closure) | Object::ForeignClosure(closure) => {
+                Some(Rc::as_ptr(closure) as *const ())
+            }
+            Object::Function(function) => Some(Rc::as_ptr(function) as *const ()),
+            Object::String(_)
+            | Object::List(_)
+            | Object::Map(_)
+            | Object::BoundMethod { .. }
+            | Object::Upvalue(_) => None,
+        }
+    }
+
+    // Adds the objects this one refers to to `children`.
+    fn children(&self, children: &mut Vec<ObjRef>) {
+        match self {
+            Object::List(elements) => {
+                children.extend(elements.iter().filter_map(|element| element.as_object()))
+            }
+            Object::Map(map) => {
+                for (key, value) in map.iter() {
+                    children.extend(key.value().as_object());
+                    children.extend(value.as_object());
+                }
+            }
+            Object::BoundMethod { receiver, .. } => children.push(*receiver),
+            Object::Closure(closure) => children.extend_from_slice(closure.upvalues()),
+            Object::Upvalue(Upvalue::Closed(value)) => children.extend(value.as_object()),
+            Object::String(_)
+            | Object::Native(_)
+            | Object::Namespace(_)
+            | Object::NativeClass(_)
+            | Object::NativeInstance(_)
+            | Object::Upvalue(Upvalue::Open(_))
+            | Object::Function(_)
+            | Object::ForeignClosure(_) => {}
+        }
+    }
+}
+
+/// Counters for `--gc-stats`.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct GcStats {
+    pub collections: usize,
+    pub objects_allocated: usize,
+    pub objects_freed: usize,
+    pub bytes_allocated: usize,
+    pub bytes_freed: usize,
+    /// The most bytes that were live at once.
+    pub peak_bytes: usize,
+}
+
+impl std::fmt::Display for GcStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "collections:       {}", self.collections)?;
+        writeln!(f, "objects allocated: {}", self.objects_allocated)?;
+        writeln!(f, "objects freed:     {}", self.objects_freed)?;
+        writeln!(f, "bytes allocated:   {}", self.bytes_allocated)?;
+        writeln!(f, "bytes freed:       {}", self.bytes_freed)?;
+        write!(f, "peak bytes:        {}", self.peak_bytes)
+    }
+}
+
+// The first collection happens after this many bytes, later ones once the heap
+// has grown by `HEAP_GROW_FACTOR` since the last.
+const INITIAL_NEXT_GC: usize = 1024 * 1024;
+const HEAP_GROW_FACTOR: usize = 2;
+
+struct Entry {
+    marked: bool,
+    // What `live_bytes` was charged for the object, which is out of date once
+    // a list or map changes, until `resize` is called.
+    size: usize,
+    object: Object,
+}
+
+/// The VM's object heap, collected by tracing mark-sweep.
+///
+/// The heap doesn't know most of the roots, so collecting is driven by its
+/// owner: mark every root with `mark_value`/`mark_object`, then call
+/// `collect`, which traces from them and frees whatever wasn't reached.
+/// `should_collect` says when it's time.
+///
+/// The exception is closures the host still holds, which the owner can't
+/// see: `collect` treats any closure with more than one `Rc` as a root.
+#[derive(Default)]
+pub struct Heap {
+    // Freed slots are `None`, and get reused before the vector grows.
+    objects: Vec<Option<Entry>>,
+    free: Vec<u32>,
+    // Every string on the heap, so that equal strings are the same object and
+    // can be compared by `ObjRef`. Not a root: strings are removed when
+    // they're swept.
+    strings: HashMap<InternedString, ObjRef>,
+    // Likewise for objects that wrap something shared with the host, by
+    // address.
+    shared: HashMap<*const (), ObjRef>,
+    // Marked but not yet traced.
+    gray: Vec<ObjRef>,
+    live_bytes: usize,
+    next_gc: usize,
+    stress: bool,
+    stats: GcStats,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Heap {
+            next_gc: INITIAL_NEXT_GC,
+            ..Default::default()
+        }
+    }
+
+    /// In stress mode, `should_collect` is always true, so that a missing
+    /// root shows up as soon as possible instead of once the heap is big.
+    pub fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.stress || self.live_bytes > self.next_gc
+    }
+
+    /// Roughly how much memory the objects that haven't been freed use.
+    pub fn live_bytes(&self) -> usize {
+        self.live_bytes
+    }
+
+    pub fn stats(&self) -> &GcStats {
+        &self.stats
+    }
+
+    /// How many objects haven't been freed yet, reachable or not.
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The string object with these contents, if there is one.
+    pub fn find_string(&self, string: &InternedString) -> Option<ObjRef> {
+        self.strings.get(string).copied()
+    }
+
+    /// Allocates a string, which mustn't be on the heap yet. Use `find_string`
+    /// first, or call `intern`.
+    pub fn alloc_string(&mut self, string: InternedString) -> ObjRef {
+        debug_assert!(self.find_string(&string).is_none());
+        let object = self.alloc_entry(Object::String(string.clone()));
+        self.strings.insert(string, object);
+        object
+    }
+
+    /// The string object with these contents, allocating it if needed. Like
+    /// `alloc_string`, never collects.
+    pub fn intern(&mut self, string: InternedString) -> ObjRef {
+        match self.find_string(&string) {
+            Some(object) => object,
+            None => self.alloc_string(string),
+        }
+    }
+
+    /// The object wrapping a closure, native or anything else shared with
+    /// the host, if there is one.
+    pub fn find_shared<T>(&self, shared: &Rc<T>) -> Option<ObjRef> {
+        self.shared.get(&(Rc::as_ptr(shared) as *const ())).copied()
+    }
+
+    /// Allocates anything but a string, which has to go through `intern` to
+    /// stay unique. Like `intern`, never collects.
+    ///
+    /// Wrapping something that's already on the heap, like a native that's
+    /// read twice, gives the object that's there, so objects are only ever
+    /// equal to themselves and can be compared by `ObjRef`.
+    pub fn alloc(&mut self, object: Object) -> ObjRef {
+        debug_assert!(
+            !matches!(object, Object::String(_)),
+            "strings must be interned"
+        );
+        let Some(shared) = object.shared() else {
+            return self.alloc_entry(object);
+        };
+        if let Some(existing) = self.shared.get(&shared) {
+            return *existing;
+        }
+        let object = self.alloc_entry(object);
+        self.shared.insert(shared, object);
+        object
+    }
+
+    // Never collects by itself, since the caller might be holding the only
+    // reference to something.
+    fn alloc_entry(&mut self, object: Object) -> ObjRef {
+        let size = object.size();
+        self.live_bytes += size;
+        self.stats.objects_allocated += 1;
+        self.stats.bytes_allocated += size;
+        self.stats.peak_bytes = self.stats.peak_bytes.max(self.live_bytes);
+
+        let entry = Some(Entry {
+            marked: false,
+            size,
+            object,
+        });
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index as usize] = entry;
+                ObjRef(index)
+            }
+            None => {
+                let object = ObjRef::from_index(self.objects.len());
+                self.objects.push(entry);
+                object
+            }
+        }
+    }
+
+    pub fn get(&self, object: ObjRef) -> &Object {
+        match &self.objects[object.index()] {
+            Some(entry) => &entry.object,
+            None => panic!("{:?} was already freed", object),
+        }
+    }
+
+    /// Call `resize` after changing the object's size.
+    pub fn get_mut(&mut self, object: ObjRef) -> &mut Object {
+        match &mut self.objects[object.index()] {
+            Some(entry) => &mut entry.object,
+            None => panic!("{:?} was already freed", object),
+        }
+    }
+
+    /// Brings what a list or map is charged up to date, since they change
+    /// after they're allocated. Anything else keeps its size.
+    pub fn resize(&mut self, object: ObjRef) {
+        let entry = self.objects[object.index()]
+            .as_mut()
+            .unwrap_or_else(|| panic!("{:?} was already freed", object));
+        let size = entry.object.size();
+        if size > entry.size {
+            self.live_bytes += size - entry.size;
+            self.stats.bytes_allocated += size - entry.size;
+            self.stats.peak_bytes = self.stats.peak_bytes.max(self.live_bytes);
+        } else {
+            self.live_bytes -= entry.size - size;
+            self.stats.bytes_freed += entry.size - size;
+        }
+        entry.size = size;
+    }
+
+    pub fn mark_value(&mut self, value: VmValue) {
+        if let Some(object) = value.as_object() {
+            self.mark_object(object);
+        }
+    }
+
+    pub fn mark_object(&mut self, object: ObjRef) {
+        let entry = self.objects[object.index()]
+            .as_mut()
+            .unwrap_or_else(|| panic!("{:?} was already freed", object));
+        if !entry.marked {
+            entry.marked = true;
+            self.gray.push(object);
+        }
+    }
+
+    /// Traces from everything marked so far, then frees the rest and clears
+    /// the marks for next time.
+    pub fn collect(&mut self) {
+        for index in 0..self.objects.len() {
+            if let Some(Entry {
+                object: Object::Closure(closure),
+                ..
+            }) = &self.objects[index]
+            {
+                if Rc::strong_count(closure) > 1 {
+                    self.mark_object(ObjRef::from_index(index));
+                }
+            }
+        }
+        self.trace_references();
+        self.sweep();
+        self.next_gc = (self.live_bytes * HEAP_GROW_FACTOR).max(INITIAL_NEXT_GC);
+        self.stats.collections += 1;
+    }
+
+    fn trace_references(&mut self) {
+        let mut children = Vec::new();
+        while let Some(object) = self.gray.pop() {
+            self.get(object).children(&mut children);
+            for child in children.drain(..) {
+                self.mark_object(child);
+            }
+        }
+    }
+
+    fn sweep(&mut self) {
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            match slot {
+                Some(entry) if entry.marked => entry.marked = false,
+                Some(entry) => {
+                    let size = entry.size;
+                    if let Object::String(ss) = &entry.object {
+                        self.strings.remove(ss);
+                    }
+                    if let Some(shared) = entry.object.shared() {
+                        self.shared.remove(&shared);
+                    }
+                    self.live_bytes -= size;
+                    self.stats.objects_freed += 1;
+                    self.stats.bytes_freed += size;
+                    *slot = None;
+                    self.free.push(index as u32);
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(heap: &mut Heap, ss: &str) -> ObjRef {
+        heap.intern(ss.into())
+    }
+
+    #[test]
+    fn frees_unmarked() {
+        let mut heap = Heap::new();
+        let kept = string(&mut heap, "kept");
+        string(&mut heap, "garbage");
+        heap.mark_value(VmValue::object(kept));
+        heap.mark_value(VmValue::number(1.0));
+        heap.collect();
+
+        assert_eq!(heap.len(), 1);
+        assert_eq!(heap.get(kept), &Object::String("kept".into()));
+        assert_eq!(heap.stats().collections, 1);
+        assert_eq!(heap.stats().objects_freed, 1);
+
+        // Marks are cleared, so it's garbage next time unless marked again.
+        heap.collect();
+        assert!(heap.is_empty());
+    }
+
+    #[test]
+    fn reuses_freed_slots() {
+        let mut heap = Heap::new();
+        let first = string(&mut heap, "a");
+        heap.collect();
+        let second = string(&mut heap, "b");
+        assert_eq!(first, second);
+        assert_eq!(heap.get(second), &Object::String("b".into()));
+    }
+
+    #[test]
+    fn interning() {
+        let mut heap = Heap::new();
+        let a = string(&mut heap, "a");
+        assert_eq!(string(&mut heap, "a"), a);
+        assert_ne!(string(&mut heap, "b"), a);
+        assert_eq!(heap.len(), 2);
+
+        // Swept strings are forgotten, rather than handed out again.
+        heap.collect();
+        assert_eq!(heap.find_string(&"a".into()), None);
+    }
+
+    #[test]
+    fn resize() {
+        let mut heap = Heap::new();
+        let object = heap.alloc(Object::List(Vec::new()));
+        let allocated = heap.live_bytes();
+        let Object::List(elements) = heap.get_mut(object) else {
+            unreachable!()
+        };
+        elements.push(VmValue::nil());
+        assert_eq!(heap.live_bytes(), allocated);
+        heap.resize(object);
+        assert!(heap.live_bytes() > allocated);
+
+        // The sweep frees what the list was charged by then.
+        heap.collect();
+        assert_eq!(heap.live_bytes(), 0);
+        assert_eq!(heap.stats().bytes_freed, heap.stats().bytes_allocated);
+    }
+
+    #[test]
+    fn traces_lists_and_maps() {
+        let mut heap = Heap::new();
+        let string = string(&mut heap, "s");
+        let list = heap.alloc(Object::List(vec![VmValue::object(string)]));
+        let mut map = OrderedMap::default();
+        map.insert(VmKey::new(VmValue::object(list)).unwrap(), VmValue::nil());
+        let map = heap.alloc(Object::Map(map));
+        heap.mark_object(map);
+        heap.collect();
+        assert_eq!(heap.len(), 3);
+
+        // A list that contains itself is freed once nothing else refers to
+        // it.
+        let Object::List(elements) = heap.get_mut(list) else {
+            unreachable!()
+        };
+        elements.push(VmValue::object(list));
+        heap.collect();
+        assert!(heap.is_empty());
+    }
+
+    #[test]
+    fn stress() {
+        let mut heap = Heap::new();
+        assert!(!heap.should_collect());
+        heap.set_stress(true);
+        assert!(heap.should_collect());
+    }
+}
@@ -0,0 +1,853 @@
+use crate::expr;
+use crate::expr::Ast;
+use crate::expr::Expr;
+use crate::expr::LogicalOp;
+use crate::expr::NodeId;
+use crate::expr::Visitor as _;
+use crate::limits::LimitExceeded;
+use crate::limits::LimitKind;
+use crate::limits::Limits;
+use crate::list;
+use crate::map;
+use crate::native::NativeRegistry;
+use crate::resolver::Resolver;
+use crate::stdlib;
+use crate::stmt;
+use crate::stmt::Stmt;
+use crate::stmt::StmtId;
+use crate::stmt::Visitor as _;
+use crate::symbol::Symbol;
+use crate::value;
+use crate::value::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Tree-walk interpreter. Globals are kept between calls to `interpret`, so
+/// the REPL can run one line at a time.
+pub struct Interpreter {
+    // The innermost scope of whatever is running.
+    environment: Rc<RefCell<Environment>>,
+    globals: Rc<RefCell<Environment>>,
+    // How many scopes out each local variable is, from the `Resolver`.
+    // Variables that aren't in here are globals.
+    locals: HashMap<NodeId, usize>,
+    // Consulted for globals the program didn't define.
+    natives: NativeRegistry,
+    // Where `print` writes.
+    stdout: Box<dyn Write>,
+    limits: Limits,
+    steps: u64,
+    call_depth: usize,
+    // The latest line we know of, for errors from nodes that don't have one.
+    line_number: usize,
+}
+
+impl Interpreter {
+    /// An interpreter with the standard library.
+    pub fn new() -> Self {
+        Self::with_natives(stdlib::core())
+    }
+
+    pub fn with_natives(natives: NativeRegistry) -> Self {
+        let globals = Rc::new(RefCell::new(Environment::default()));
+        Interpreter {
+            environment: globals.clone(),
+            globals,
+            locals: HashMap::new(),
+            natives,
+            stdout: Box::new(std::io::stdout()),
+            limits: Limits::default(),
+            steps: 0,
+            call_depth: 0,
+            line_number: 1,
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Sends the output of `print` somewhere other than stdout.
+    pub fn set_stdout(&mut self, stdout: impl Write + 'static) {
+        self.stdout = Box::new(stdout);
+    }
+
+    /// A global the program defined, or else a native.
+    pub fn get_global(&self, name: Symbol) -> Option<Value> {
+        self.globals
+            .borrow()
+            .get(name)
+            .or_else(|| self.natives.get(name))
+    }
+
+    /// Defines a global, or replaces it if it already exists.
+    pub fn set_global(&mut self, name: Symbol, value: Value) {
+        self.globals.borrow_mut().define(name, value);
+    }
+
+    /// Runs statements, stopping at the first runtime error. Each call gets
+    /// a fresh step budget.
+    pub fn interpret(&mut self, ast: &Ast, statements: &[StmtId]) -> anyhow::Result<()> {
+        self.steps = 0;
+        let mut resolver = Resolver::new();
+        for stmt in statements {
+            resolver.visit_stmt(ast, *stmt);
+        }
+        self.locals.extend(resolver.into_locals());
+
+        for stmt in statements {
+            match self.visit_stmt(ast, *stmt)? {
+                Flow::Normal => {}
+                Flow::Break | Flow::Continue => anyhow::bail!(
+                    "[line {}] Can't use 'break' or 'continue' outside of a loop.",
+                    self.line_number
+                ),
+                Flow::Return(_) => anyhow::bail!(
+                    "[line {}] Can't return from top-level code.",
+                    self.line_number
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates a single expression in the global scope, e.g. what's left
+    /// of a REPL line after its statements.
+    pub fn evaluate(&mut self, ast: &Ast, expr: NodeId) -> anyhow::Result<Value> {
+        let mut resolver = Resolver::new();
+        resolver.visit_expr(ast, expr);
+        self.locals.extend(resolver.into_locals());
+        self.visit_expr(ast, expr)
+    }
+
+    /// Calls a function, e.g. one the program stored in a global. Lox
+    /// functions have to be called with the `Ast` they were parsed into.
+    pub fn call(
+        &mut self,
+        ast: &Ast,
+        callee: &Value,
+        arguments: &[Value],
+    ) -> anyhow::Result<Value> {
+        if let Value::Function(function) = callee {
+            // Like with natives, the host getting the arguments wrong isn't
+            // anywhere in the source.
+            let (params, _) = function.declaration(ast);
+            if arguments.len() != params.len() {
+                anyhow::bail!(
+                    "Expected {} arguments but got {}.",
+                    params.len(),
+                    arguments.len()
+                );
+            }
+            // The closest there is to where the call happened.
+            self.line_number = ast[function.declaration].line_number().unwrap_or(1);
+        }
+        self.call_value(ast, callee, arguments)
+    }
+
+    fn limit_exceeded(&self, kind: LimitKind) -> anyhow::Error {
+        LimitExceeded {
+            kind,
+            line_number: self.line_number,
+        }
+        .into()
+    }
+
+    // Errors say where they happened, which for natives is `line_number`.
+    fn call_value(
+        &mut self,
+        ast: &Ast,
+        callee: &Value,
+        arguments: &[Value],
+    ) -> anyhow::Result<Value> {
+        if self
+            .limits
+            .max_call_depth
+            .is_some_and(|max| self.call_depth >= max)
+        {
+            return Err(self.limit_exceeded(LimitKind::CallDepth));
+        }
+        self.call_depth += 1;
+        let result = match callee {
+            Value::Function(function) => self.call_function(ast, function, arguments),
+            _ => value::call(callee, arguments)
+                .map_err(|err| runtime_error(self.line_number, &err.to_string())),
+        };
+        self.call_depth -= 1;
+        result
+    }
+
+    fn call_function(
+        &mut self,
+        ast: &Ast,
+        function: &Function,
+        arguments: &[Value],
+    ) -> anyhow::Result<Value> {
+        let (params, statements) = function.declaration(ast);
+        if arguments.len() != params.len() {
+            return Err(runtime_error(
+                self.line_number,
+                &format!(
+                    "Expected {} arguments but got {}.",
+                    params.len(),
+                    arguments.len()
+                ),
+            ));
+        }
+
+        let mut environment = Environment::new(function.closure.clone());
+        for (param, argument) in params.iter().zip(arguments) {
+            environment.define(*param, argument.clone());
+        }
+        let caller = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        let result = self.execute_statements(ast, statements);
+        self.environment = caller;
+        match result? {
+            Flow::Normal => Ok(Value::Nil),
+            Flow::Return(value) => Ok(value),
+            Flow::Break | Flow::Continue => Err(runtime_error(
+                self.line_number,
+                "Can't use 'break' or 'continue' outside of a loop.",
+            )),
+        }
+    }
+
+    // The scope a resolved variable is in, `distance` scopes out.
+    fn ancestor(&self, distance: usize) -> Rc<RefCell<Environment>> {
+        let mut environment = self.environment.clone();
+        for _ in 0..distance {
+            let enclosing = environment.borrow().enclosing.clone();
+            environment = enclosing.expect("the resolver only counts scopes that exist");
+        }
+        environment
+    }
+
+    fn look_up(&self, expr: NodeId, name: Symbol) -> Option<Value> {
+        match self.locals.get(&expr) {
+            Some(distance) => self.ancestor(*distance).borrow().get(name),
+            None => self
+                .globals
+                .borrow()
+                .get(name)
+                .or_else(|| self.natives.get(name)),
+        }
+    }
+
+    fn execute_block(&mut self, ast: &Ast, statements: &[StmtId]) -> anyhow::Result<Flow> {
+        let enclosing = self.environment.clone();
+        self.environment = Rc::new(RefCell::new(Environment::new(enclosing.clone())));
+        let result = self.execute_statements(ast, statements);
+        // Restore the scope even if the block failed, or the REPL would be left
+        // inside of it.
+        self.environment = enclosing;
+        result
+    }
+
+    // Stops early if a statement breaks out of the normal flow.
+    fn execute_statements(&mut self, ast: &Ast, statements: &[StmtId]) -> anyhow::Result<Flow> {
+        for stmt in statements {
+            let flow = self.visit_stmt(ast, *stmt)?;
+            if flow != Flow::Normal {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn execute_for(
+        &mut self,
+        ast: &Ast,
+        initializer: Option<StmtId>,
+        condition: Option<NodeId>,
+        increment: Option<NodeId>,
+        body: StmtId,
+    ) -> anyhow::Result<Flow> {
+        if let Some(initializer) = initializer {
+            self.visit_stmt(ast, initializer)?;
+        }
+        loop {
+            if let Some(condition) = condition {
+                if !self.visit_expr(ast, condition)?.is_truthy() {
+                    return Ok(Flow::Normal);
+                }
+            }
+            // `continue` still runs the increment.
+            match self.visit_stmt(ast, body)? {
+                Flow::Normal | Flow::Continue => {}
+                Flow::Break => return Ok(Flow::Normal),
+                flow @ Flow::Return(_) => return Ok(flow),
+            }
+            if let Some(increment) = increment {
+                self.visit_expr(ast, increment)?;
+            }
+        }
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl expr::Visitor for Interpreter {
+    type Result = anyhow::Result<Value>;
+
+    fn visit_expr(&mut self, ast: &Ast, expr: NodeId) -> Self::Result {
+        use expr::Expr::*;
+        if let Some(line_number) = ast[expr].line_number() {
+            self.line_number = line_number;
+        }
+        let value = match &ast[expr] {
+            Assign {
+                name,
+                line_number,
+                value,
+            } => {
+                let value = self.visit_expr(ast, *value)?;
+                let assigned = match self.locals.get(&expr) {
+                    Some(distance) => self
+                        .ancestor(*distance)
+                        .borrow_mut()
+                        .assign(*name, value.clone()),
+                    None => self.globals.borrow_mut().assign(*name, value.clone()),
+                };
+                if !assigned {
+                    return Err(undefined_variable(*line_number, *name));
+                }
+                value
+            }
+            Binary {
+                left,
+                operator,
+                line_number,
+                right,
+            } => {
+                let left = self.visit_expr(ast, *left)?;
+                let right = self.visit_expr(ast, *right)?;
+                value::binary(*operator, left, right)
+                    .map_err(|message| runtime_error(*line_number, message))?
+            }
+            Call {
+                callee,
+                line_number,
+                arguments,
+            } => {
+                let callee = self.visit_expr(ast, *callee)?;
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.visit_expr(ast, *argument))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                self.line_number = *line_number;
+                self.call_value(ast, &callee, &arguments)?
+            }
+            Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.visit_expr(ast, *condition)?.is_truthy() {
+                    self.visit_expr(ast, *then_branch)?
+                } else {
+                    self.visit_expr(ast, *else_branch)?
+                }
+            }
+            Get {
+                object,
+                name,
+                line_number,
+            } => {
+                let object = self.visit_expr(ast, *object)?;
+                value::get_property(&object, *name)
+                    .map_err(|err| runtime_error(*line_number, &err.to_string()))?
+            }
+            Function { .. } => Value::Function(Rc::new(self::Function {
+                declaration: expr,
+                closure: self.environment.clone(),
+            })),
+            Grouping(expr) => self.visit_expr(ast, *expr)?,
+            Index {
+                object,
+                index,
+                line_number,
+            } => {
+                let object = self.visit_expr(ast, *object)?;
+                let index = self.visit_expr(ast, *index)?;
+                value::get_index(&object, &index)
+                    .map_err(|err| runtime_error(*line_number, &err.to_string()))?
+            }
+            List(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.visit_expr(ast, *element))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                Value::List(Rc::new(list::List::new(elements)))
+            }
+            Map(entries) => {
+                let map = map::Map::new();
+                for (key, value) in entries {
+                    let key = self.visit_expr(ast, *key)?;
+                    let value = self.visit_expr(ast, *value)?;
+                    // The literal has no line of its own, so this is the
+                    // closest one we know.
+                    map.insert(key, value)
+                        .map_err(|err| runtime_error(self.line_number, &err.to_string()))?;
+                }
+                Value::Map(Rc::new(map))
+            }
+            Logical {
+                left,
+                operator,
+                right,
+            } => {
+                // Produces whichever operand decided the result, not a bool.
+                let left = self.visit_expr(ast, *left)?;
+                match operator {
+                    LogicalOp::Or if left.is_truthy() => left,
+                    LogicalOp::And if !left.is_truthy() => left,
+                    _ => self.visit_expr(ast, *right)?,
+                }
+            }
+            SetIndex {
+                object,
+                index,
+                value,
+                line_number,
+            } => {
+                let object = self.visit_expr(ast, *object)?;
+                let index = self.visit_expr(ast, *index)?;
+                let value = self.visit_expr(ast, *value)?;
+                value::set_index(&object, &index, value.clone())
+                    .map_err(|err| runtime_error(*line_number, &err.to_string()))?;
+                value
+            }
+            Unary {
+                operator,
+                line_number,
+                right,
+            } => {
+                let right = self.visit_expr(ast, *right)?;
+                value::unary(*operator, right)
+                    .map_err(|message| runtime_error(*line_number, message))?
+            }
+            LiteralNumber { value, .. } => Value::Number(*value),
+            LiteralString { value, .. } => Value::String(value.as_str().into()),
+            LiteralBool { value, .. } => Value::Bool(*value),
+            Nil { .. } => Value::Nil,
+            Variable { name, line_number } => self
+                .look_up(expr, *name)
+                .ok_or_else(|| undefined_variable(*line_number, *name))?,
+        };
+        Ok(value)
+    }
+}
+
+/// How a statement finished, so that `break`, `continue` and `return` can
+/// skip the rest of the loop body or function they're in.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+impl stmt::Visitor for Interpreter {
+    type Result = anyhow::Result<Flow>;
+
+    fn visit_stmt(&mut self, ast: &Ast, stmt: StmtId) -> Self::Result {
+        if let Some(line_number) = ast[stmt].line_number() {
+            self.line_number = line_number;
+        }
+        self.steps += 1;
+        if self.limits.max_steps.is_some_and(|max| self.steps > max) {
+            return Err(self.limit_exceeded(LimitKind::Steps));
+        }
+        match &ast[stmt] {
+            Stmt::Block(statements) => return self.execute_block(ast, statements),
+            Stmt::Break { .. } => return Ok(Flow::Break),
+            Stmt::Continue { .. } => return Ok(Flow::Continue),
+            Stmt::Expression { expr, .. } => {
+                self.visit_expr(ast, *expr)?;
+            }
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                // The initializer gets its own scope, like a block around the
+                // whole loop.
+                let enclosing = self.environment.clone();
+                self.environment = Rc::new(RefCell::new(Environment::new(enclosing.clone())));
+                let result = self.execute_for(ast, *initializer, *condition, *increment, *body);
+                self.environment = enclosing;
+                return result;
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                if self.visit_expr(ast, *condition)?.is_truthy() {
+                    return self.visit_stmt(ast, *then_branch);
+                } else if let Some(else_branch) = else_branch {
+                    return self.visit_stmt(ast, *else_branch);
+                }
+            }
+            Stmt::Print { expr, .. } => {
+                let value = self.visit_expr(ast, *expr)?;
+                writeln!(self.stdout, "{}", value)?;
+            }
+            Stmt::Return { value, line_number } => {
+                let value = match value {
+                    Some(value) => self.visit_expr(ast, *value)?,
+                    None => Value::Nil,
+                };
+                self.line_number = *line_number;
+                return Ok(Flow::Return(value));
+            }
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                let value = match initializer {
+                    Some(initializer) => self.visit_expr(ast, *initializer)?,
+                    None => Value::Nil,
+                };
+                // NOTE: redeclaring a variable in the same scope replaces it,
+                // so a function that captured the old one sees the new value.
+                // The VM gives each declaration a slot of its own.
+                self.environment.borrow_mut().define(*name, value);
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                while self.visit_expr(ast, *condition)?.is_truthy() {
+                    match self.visit_stmt(ast, *body)? {
+                        Flow::Normal | Flow::Continue => {}
+                        Flow::Break => break,
+                        flow @ Flow::Return(_) => return Ok(flow),
+                    }
+                }
+            }
+        }
+        Ok(Flow::Normal)
+    }
+}
+
+/// A Lox function, along with the scope it was declared in.
+///
+/// NOTE: a function stored in a variable it can see, e.g. one that calls
+/// itself, keeps that scope alive forever, since both are reference counted.
+pub struct Function {
+    // The `Expr::Function` node, which has the parameters and body.
+    declaration: NodeId,
+    closure: Rc<RefCell<Environment>>,
+}
+
+impl Function {
+    // The parameters and body statements.
+    fn declaration<'a>(&self, ast: &'a Ast) -> (&'a [Symbol], &'a [StmtId]) {
+        let Expr::Function { params, body, .. } = &ast[self.declaration] else {
+            unreachable!("functions are made from function expressions");
+        };
+        let Stmt::Block(statements) = &ast[*body] else {
+            unreachable!("function bodies are blocks");
+        };
+        (params, statements)
+    }
+}
+
+// Functions are only equal to themselves, like other objects.
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<fn>")
+    }
+}
+
+/// A scope's variables. Scopes are shared, since a function holds on to the
+/// scope it was declared in. Variables are only ever looked up in the scope
+/// the `Resolver` found them in, so there's no need to search outwards.
+#[derive(Default)]
+struct Environment {
+    values: HashMap<Symbol, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    fn new(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Environment {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    // Redefining a variable is allowed, it just replaces the old value.
+    fn define(&mut self, name: Symbol, value: Value) {
+        self.values.insert(name, value);
+    }
+
+    fn get(&self, name: Symbol) -> Option<Value> {
+        self.values.get(&name).cloned()
+    }
+
+    // Returns false if the variable was never defined.
+    fn assign(&mut self, name: Symbol, value: Value) -> bool {
+        match self.values.get_mut(&name) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn runtime_error(line_number: usize, message: &str) -> anyhow::Error {
+    anyhow::anyhow!("[line {}] {}", line_number, message)
+}
+
+fn undefined_variable(line_number: usize, name: Symbol) -> anyhow::Error {
+    runtime_error(line_number, &format!("Undefined variable '{}'.", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn evaluate(source: &str) -> anyhow::Result<Value> {
+        let mut scanner = Scanner::new(source);
+        let mut ast = Ast::new();
+        let expr = Parser::new(scanner.scan_tokens(), &mut ast).parse()?;
+        Interpreter::new().evaluate(&ast, expr)
+    }
+
+    // Runs a program, then evaluates `expr` in the global scope it left behind.
+    fn evaluate_after(program: &str, expr: &str) -> anyhow::Result<Value> {
+        let mut interpreter = Interpreter::new();
+        let mut ast = Ast::new();
+        let mut scanner = Scanner::new(program);
+        let statements = Parser::new(scanner.scan_tokens(), &mut ast).parse_program()?;
+        interpreter.interpret(&ast, &statements)?;
+
+        let mut scanner = Scanner::new(expr);
+        let expr = Parser::new(scanner.scan_tokens(), &mut ast).parse()?;
+        interpreter.evaluate(&ast, expr)
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(evaluate("1 + 2 * 3").unwrap(), Value::Number(7.0));
+        assert_eq!(evaluate("(1 + 2) * -3").unwrap(), Value::Number(-9.0));
+        assert_eq!(evaluate("10 - 4 - 3").unwrap(), Value::Number(3.0));
+        assert_eq!(evaluate("1 / 4").unwrap(), Value::Number(0.25));
+    }
+
+    #[test]
+    fn comparison_and_equality() {
+        assert_eq!(evaluate("1 < 2 == true").unwrap(), Value::Bool(true));
+        assert_eq!(evaluate("nil == false").unwrap(), Value::Bool(false));
+        assert_eq!(evaluate("\"a\" != \"a\"").unwrap(), Value::Bool(false));
+    }
+
+    #[test]
+    fn conditional_and_comma() {
+        assert_eq!(evaluate("nil ? 1 : 2").unwrap(), Value::Number(2.0));
+        assert_eq!(evaluate("0 ? 1 : 2").unwrap(), Value::Number(1.0));
+        assert_eq!(evaluate("1 + 2, 3").unwrap(), Value::Number(3.0));
+        // Only the chosen branch is evaluated.
+        assert_eq!(evaluate("true ? 1 : -nil").unwrap(), Value::Number(1.0));
+    }
+
+    #[test]
+    fn logical_operators() {
+        assert_eq!(
+            evaluate("nil or \"yes\"").unwrap(),
+            Value::String("yes".into())
+        );
+        assert_eq!(evaluate("1 and 2").unwrap(), Value::Number(2.0));
+        // Short-circuits.
+        assert_eq!(evaluate("false and -nil").unwrap(), Value::Bool(false));
+        assert_eq!(evaluate("true or -nil").unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn string_concatenation() {
+        assert_eq!(
+            evaluate("\"con\" + \"cat\"").unwrap(),
+            Value::String("concat".into())
+        );
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert_eq!(
+            evaluate("1 +\n\"one\"").unwrap_err().to_string(),
+            "[line 1] Operands must be two numbers or two strings."
+        );
+        assert_eq!(
+            evaluate("-\"one\"").unwrap_err().to_string(),
+            "[line 1] Operand must be a number."
+        );
+        assert_eq!(
+            evaluate("true < 1").unwrap_err().to_string(),
+            "[line 1] Operands must be numbers."
+        );
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(
+            evaluate_after("var a = 1; var b; a = b = a + 1;", "a + b").unwrap(),
+            Value::Number(4.0)
+        );
+        assert_eq!(
+            evaluate_after("", "\nundefined").unwrap_err().to_string(),
+            "[line 2] Undefined variable 'undefined'."
+        );
+        assert_eq!(
+            evaluate_after("missing = 1;", "nil")
+                .unwrap_err()
+                .to_string(),
+            "[line 1] Undefined variable 'missing'."
+        );
+    }
+
+    #[test]
+    fn scopes() {
+        assert_eq!(
+            evaluate_after("var a = 1; { var a = 2; a = 3; }", "a").unwrap(),
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            evaluate_after("var a = 1; { a = 2; }", "a").unwrap(),
+            Value::Number(2.0)
+        );
+        assert!(evaluate_after("{ var a = 1; }", "a").is_err());
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(
+            evaluate_after(
+                "var sum = 0; for (var i = 0; i < 5; i = i + 1) sum = sum + i;",
+                "sum"
+            )
+            .unwrap(),
+            Value::Number(10.0)
+        );
+        assert_eq!(
+            evaluate_after("var n = 0; while (n < 3) n = n + 1;", "n").unwrap(),
+            Value::Number(3.0)
+        );
+        assert_eq!(
+            evaluate_after("var x; if (nil) x = 1; else x = 2;", "x").unwrap(),
+            Value::Number(2.0)
+        );
+        assert_eq!(
+            evaluate_after(
+                "var sum = 0; for (var i = 0; i < 10; i = i + 1) { if (i == 2) continue; if (i == 4) break; sum = sum + i; }",
+                "sum"
+            )
+            .unwrap(),
+            Value::Number(4.0)
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(
+            evaluate("fun (a, b) { return a + b; }(1, 2)").unwrap(),
+            Value::Number(3.0)
+        );
+        assert_eq!(
+            evaluate_after(
+                "var make = fun () { var n = 0; return fun () { n = n + 1; return n; }; }; var counter = make(); counter();",
+                "counter()"
+            )
+            .unwrap(),
+            Value::Number(2.0)
+        );
+        // Resolved when it's written, not when it's called.
+        assert_eq!(
+            evaluate_after(
+                "var a = \"global\"; var f; { f = fun () { return a; }; var a = \"local\"; }",
+                "f()"
+            )
+            .unwrap(),
+            Value::String("global".into())
+        );
+        assert_eq!(
+            evaluate("fun (a) {}(\n)").unwrap_err().to_string(),
+            "[line 2] Expected 1 arguments but got 0."
+        );
+        // Errors inside of a function say where in the function they were.
+        assert_eq!(
+            evaluate("fun () {\n  return -nil;\n}()")
+                .unwrap_err()
+                .to_string(),
+            "[line 2] Operand must be a number."
+        );
+    }
+
+    #[test]
+    fn deep_recursion_is_a_stack_overflow() {
+        let err = evaluate_after("var f = fun () { return f(); };", "f()").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LimitExceeded>().map(|err| err.kind),
+            Some(LimitKind::CallDepth)
+        );
+    }
+
+    #[test]
+    fn natives() {
+        assert_eq!(
+            evaluate("Math.max(len(\"abc\"), num(\"2\"))").unwrap(),
+            Value::Number(3.0)
+        );
+        assert_eq!(
+            evaluate("type(clock)").unwrap(),
+            Value::String("function".into())
+        );
+        // Programs can shadow natives, but not assign to them.
+        assert_eq!(
+            evaluate_after("var str = 1;", "str").unwrap(),
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            evaluate("clock = 1").unwrap_err().to_string(),
+            "[line 1] Undefined variable 'clock'."
+        );
+        assert!(evaluate_after("", "nope()").is_err());
+    }
+
+    #[test]
+    fn call_errors() {
+        assert_eq!(
+            evaluate("str(1,\n2)").unwrap_err().to_string(),
+            "[line 2] Expected 1 arguments but got 2."
+        );
+        assert_eq!(
+            evaluate("\"str\"()").unwrap_err().to_string(),
+            "[line 1] Can only call functions and classes."
+        );
+        assert_eq!(
+            evaluate("Math.nope").unwrap_err().to_string(),
+            "[line 1] Undefined property 'nope'."
+        );
+        assert_eq!(
+            evaluate("1 .x").unwrap_err().to_string(),
+            "[line 1] Only instances, lists, maps and namespaces have properties."
+        );
+    }
+}
@@ -0,0 +1,576 @@
+//! JSON serialization of tokens and syntax trees, for tools that don't link
+//! against this crate.
+//!
+//! # Tokens
+//!
+//! A token list is an array of `{"token": TOKEN, "line_number": N}` objects,
+//! where `TOKEN` is `{"type": NAME}` named after the `scanner::Token` variant.
+//! Literal tokens carry their value as well:
+//!
+//! ```json
+//! {"type": "Identifier", "identifier": "foo"}
+//! {"type": "String", "quoted_str": "\"with quotes\""}
+//! {"type": "Number", "number": 1.5}
+//! ```
+//!
+//! # Syntax trees
+//!
+//! A syntax tree is stored the same way as `expr::Ast`: flat arrays of
+//! expression nodes and statements, in which children are referred to by
+//! their index, and always come before their parent.
+//!
+//! ```json
+//! {"version": 8, "root": 2, "nodes": [NODE, ...], "stmts": []}
+//! ```
+//!
+//! A whole program has its top-level `statements` instead of a `root`:
+//!
+//! ```json
+//! {"version": 8, "statements": [0, 2], "nodes": [NODE, ...], "stmts": [STMT, ...]}
+//! ```
+//!
+//! Each `NODE` is `{"type": NAME, "value": VALUE}`, named after the
+//! `expr::Expr` variant:
+//!
+//! | `type`          | `value`                                                       |
+//! |-----------------|---------------------------------------------------------------|
+//! | `Assign`        | `{"name": NAME, "line_number": N, "value": ID}`               |
+//! | `Binary`        | `{"left": ID, "operator": OP, "line_number": N, "right": ID}` |
+//! | `Call`          | `{"callee": ID, "line_number": N, "arguments": [ID, ...]}`    |
+//! | `Conditional`   | `{"condition": ID, "then_branch": ID, "else_branch": ID}`     |
+//! | `Function`      | `{"params": [NAME, ...], "body": SID, "line_number": N}`      |
+//! | `Get`           | `{"object": ID, "name": NAME, "line_number": N}`              |
+//! | `Index`         | `{"object": ID, "index": ID, "line_number": N}`               |
+//! | `List`          | `[ID, ...]`                                                   |
+//! | `Map`           | `[[ID, ID], ...]`, keys paired with their values              |
+//! | `Logical`       | `{"left": ID, "operator": "and" or "or", "right": ID}`        |
+//! | `SetIndex`      | `{"object": ID, "index": ID, "value": ID, "line_number": N}`  |
+//! | `Unary`         | `{"operator": OP, "line_number": N, "right": ID}`             |
+//! | `Grouping`      | `ID`                                                          |
+//! | `Variable`      | `{"name": NAME, "line_number": N}`                            |
+//! | `LiteralNumber` | `{"value": number, "line_number": N}`                         |
+//! | `LiteralString` | `{"value": string without quotes, "line_number": N}`          |
+//! | `LiteralBool`   | `{"value": boolean, "line_number": N}`                        |
+//! | `Nil`           | `{"line_number": N}`                                          |
+//!
+//! `OP` is the operator as written in Lox, e.g. `"=="`, `","` or `"-"`. A
+//! `Function`'s body is a `Block` statement, and everything inside of it must
+//! come before the function node.
+//!
+//! Each `STMT` is shaped the same way, named after the `stmt::Stmt` variant.
+//! `ID`s refer to nodes and `SID`s to statements:
+//!
+//! | `type`       | `value`                                                                   |
+//! |--------------|---------------------------------------------------------------------------|
+//! | `Block`      | `[SID, ...]`                                                              |
+//! | `Break`      | `{"line_number": N}`                                                      |
+//! | `Continue`   | `{"line_number": N}`                                                      |
+//! | `Expression` | `{"expr": ID, "line_number": N}`                                          |
+//! | `For`        | `{"initializer": SID, "condition": ID, "increment": ID, "body": SID}`     |
+//! | `If`         | `{"condition": ID, "then_branch": SID, "else_branch": SID}`               |
+//! | `Print`      | `{"expr": ID, "line_number": N}`                                          |
+//! | `Return`     | `{"value": ID, "line_number": N}`                                         |
+//! | `Var`        | `{"name": NAME, "line_number": N, "initializer": ID}`                     |
+//! | `While`      | `{"condition": ID, "body": SID, "line_number": N}`                        |
+//!
+//! The `for` clauses, `else_branch`, `initializer` and the returned `value` are
+//! `null` when missing.
+//!
+//! `version` is bumped whenever the schema changes in a way old readers can't
+//! handle.
+
+use crate::expr::Ast;
+use crate::expr::Expr;
+use crate::expr::NodeId;
+use crate::parser::MAX_ARGUMENTS;
+use crate::parser::MAX_LIST_ELEMENTS;
+use crate::parser::MAX_MAP_ENTRIES;
+use crate::parser::MAX_PARAMETERS;
+use crate::scanner::AnnotatedToken;
+use crate::stmt::Stmt;
+use crate::stmt::StmtId;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashSet;
+
+pub const AST_SCHEMA_VERSION: u32 = 8;
+
+#[derive(Serialize)]
+struct AstDocumentRef<'a> {
+    version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    root: Option<NodeId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    statements: Option<&'a [StmtId]>,
+    #[serde(flatten)]
+    ast: &'a Ast,
+}
+
+#[derive(Deserialize)]
+struct AstDocument {
+    version: u32,
+    #[serde(default)]
+    root: Option<NodeId>,
+    #[serde(default)]
+    statements: Option<Vec<StmtId>>,
+    nodes: Vec<Expr>,
+    #[serde(default)]
+    stmts: Vec<Stmt>,
+}
+
+pub fn tokens_to_json(tokens: &[AnnotatedToken]) -> anyhow::Result<String> {
+    Ok(serde_json::to_string_pretty(tokens)?)
+}
+
+pub fn ast_to_json(ast: &Ast, root: NodeId) -> anyhow::Result<String> {
+    let document = AstDocumentRef {
+        version: AST_SCHEMA_VERSION,
+        root: Some(root),
+        statements: None,
+        ast,
+    };
+    Ok(serde_json::to_string_pretty(&document)?)
+}
+
+pub fn program_to_json(ast: &Ast, statements: &[StmtId]) -> anyhow::Result<String> {
+    let document = AstDocumentRef {
+        version: AST_SCHEMA_VERSION,
+        root: None,
+        statements: Some(statements),
+        ast,
+    };
+    Ok(serde_json::to_string_pretty(&document)?)
+}
+
+/// Reads a syntax tree written by `ast_to_json`, returning it with its root.
+pub fn ast_from_json(json: &str) -> anyhow::Result<(Ast, NodeId)> {
+    let (ast, document) = read_document(json)?;
+    let Some(root) = document.root else {
+        anyhow::bail!("Syntax tree has no root.");
+    };
+    if ast.get(root).is_none() {
+        anyhow::bail!("Root {} isn't a node.", root.index());
+    }
+    Ok((ast, root))
+}
+
+/// Reads a program written by `program_to_json`, returning it with its
+/// top-level statements.
+pub fn program_from_json(json: &str) -> anyhow::Result<(Ast, Vec<StmtId>)> {
+    let (ast, document) = read_document(json)?;
+    let Some(statements) = document.statements else {
+        anyhow::bail!("Syntax tree has no statements.");
+    };
+    Ok((ast, statements))
+}
+
+// Everything but whether the expected root is there, which is left to the
+// caller.
+//
+// Trees read here haven't been through the parser, so this also makes the
+// checks it would have: that calls, functions, lists and maps aren't too big
+// for the VM's instructions, and that `break`, `continue` and `return` are
+// only used where they make sense. Backends can then treat anything else as
+// a bug.
+fn read_document(json: &str) -> anyhow::Result<(Ast, AstDocument)> {
+    let mut document: AstDocument = serde_json::from_str(json)?;
+    if document.version != AST_SCHEMA_VERSION {
+        anyhow::bail!(
+            "Unsupported syntax tree version {} (expected {}).",
+            document.version,
+            AST_SCHEMA_VERSION
+        );
+    }
+
+    let mut ast = Ast::new();
+    let mut functions = Vec::new();
+    for expr in std::mem::take(&mut document.nodes) {
+        let id = NodeId::from_index(ast.len());
+        // Only allowing references to earlier nodes rules out cycles too.
+        if children(&expr).iter().any(|child| *child >= id) {
+            anyhow::bail!(
+                "Node {} refers to a node that doesn't precede it.",
+                id.index()
+            );
+        }
+        if let Some(limit) = too_big(&expr) {
+            anyhow::bail!("Node {} has more than {}.", id.index(), limit);
+        }
+        if let Expr::Function { body, .. } = expr {
+            functions.push((id, body));
+        }
+        ast.add(expr);
+    }
+    // The newest node each statement refers to, directly or through the
+    // statements inside of it.
+    let mut newest_nodes: Vec<Option<NodeId>> = Vec::new();
+    for stmt in std::mem::take(&mut document.stmts) {
+        let id = StmtId::from_index(ast.stmts_len());
+        let (stmt_children, expr_children) = stmt_children(&stmt);
+        if stmt_children.iter().any(|child| *child >= id) {
+            anyhow::bail!(
+                "Statement {} refers to a statement that doesn't precede it.",
+                id.index()
+            );
+        }
+        if expr_children.iter().any(|child| ast.get(*child).is_none()) {
+            anyhow::bail!(
+                "Statement {} refers to a node that doesn't exist.",
+                id.index()
+            );
+        }
+        let newest = stmt_children
+            .iter()
+            .filter_map(|child| newest_nodes[child.index()])
+            .chain(expr_children)
+            .max();
+        newest_nodes.push(newest);
+        ast.add_stmt(stmt);
+    }
+    // Functions are the only nodes that refer to statements. Their bodies
+    // only referring to earlier nodes rules out cycles through them.
+    for &(id, body) in &functions {
+        match ast.get_stmt(body) {
+            Some(Stmt::Block(_)) => {}
+            Some(_) => anyhow::bail!("Node {} has a body that isn't a block.", id.index()),
+            None => anyhow::bail!(
+                "Node {} refers to a statement that doesn't exist.",
+                id.index()
+            ),
+        }
+        if newest_nodes[body.index()].is_some_and(|newest| newest >= id) {
+            anyhow::bail!(
+                "Node {} has a body that refers to a node that doesn't precede it.",
+                id.index()
+            );
+        }
+    }
+
+    if let Some(statements) = &document.statements {
+        if let Some(stmt) = statements
+            .iter()
+            .find(|stmt| ast.get_stmt(**stmt).is_none())
+        {
+            anyhow::bail!("Statement {} doesn't exist.", stmt.index());
+        }
+    }
+    // Statements can be shared, so each is only checked once per place it
+    // can be in.
+    let mut checked = HashSet::new();
+    for stmt in document.statements.iter().flatten() {
+        check_placement(&ast, *stmt, Placement::default(), &mut checked)?;
+    }
+    let in_function = Placement {
+        in_function: true,
+        in_loop: false,
+    };
+    for (_, body) in functions {
+        check_placement(&ast, body, in_function, &mut checked)?;
+    }
+    Ok((ast, document))
+}
+
+// What a statement is nested inside of, within its function.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+struct Placement {
+    in_function: bool,
+    in_loop: bool,
+}
+
+fn check_placement(
+    ast: &Ast,
+    stmt: StmtId,
+    placement: Placement,
+    checked: &mut HashSet<(StmtId, Placement)>,
+) -> anyhow::Result<()> {
+    if !checked.insert((stmt, placement)) {
+        return Ok(());
+    }
+    match &ast[stmt] {
+        Stmt::Break { .. } | Stmt::Continue { .. } if !placement.in_loop => {
+            anyhow::bail!("Statement {} is outside of a loop.", stmt.index())
+        }
+        Stmt::Return { .. } if !placement.in_function => {
+            anyhow::bail!("Statement {} is outside of a function.", stmt.index())
+        }
+        Stmt::For {
+            initializer, body, ..
+        } => {
+            if let Some(initializer) = initializer {
+                check_placement(ast, *initializer, placement, checked)?;
+            }
+            let in_loop = Placement {
+                in_loop: true,
+                ..placement
+            };
+            check_placement(ast, *body, in_loop, checked)?;
+        }
+        Stmt::While { body, .. } => {
+            let in_loop = Placement {
+                in_loop: true,
+                ..placement
+            };
+            check_placement(ast, *body, in_loop, checked)?;
+        }
+        stmt => {
+            for child in stmt_children(stmt).0 {
+                check_placement(ast, child, placement, checked)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// What a node has too many of for the VM to count in a byte, if anything.
+fn too_big(expr: &Expr) -> Option<String> {
+    let (count, limit, what) = match expr {
+        Expr::Call { arguments, .. } => (arguments.len(), MAX_ARGUMENTS, "arguments"),
+        Expr::Function { params, .. } => (params.len(), MAX_PARAMETERS, "parameters"),
+        Expr::List(elements) => (elements.len(), MAX_LIST_ELEMENTS, "elements"),
+        Expr::Map(entries) => (entries.len(), MAX_MAP_ENTRIES, "entries"),
+        _ => return None,
+    };
+    (count > limit).then(|| format!("{} {}", limit, what))
+}
+
+fn children(expr: &Expr) -> Vec<NodeId> {
+    match expr {
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            vec![*left, *right]
+        }
+        Expr::Call {
+            callee, arguments, ..
+        } => [*callee].into_iter().chain(arguments.iter().copied()).collect(),
+        Expr::Conditional {
+            condition,
+            then_branch,
+            else_branch,
+        } => vec![*condition, *then_branch, *else_branch],
+        Expr::Index { object, index, .. } => vec![*object, *index],
+        Expr::List(elements) => elements.clone(),
+        Expr::Map(entries) => entries
+            .iter()
+            .flat_map(|(key, value)| [*key, *value])
+            .collect(),
+        Expr::SetIndex {
+            object,
+            index,
+            value,
+            ..
+        } => vec![*object, *index, *value],
+        Expr::Grouping(inner)
+        | Expr::Get { object: inner, .. }
+        | Expr::Unary { right: inner, .. }
+        | Expr::Assign { value: inner, .. } => vec![*inner],
+        Expr::Nil { .. }
+        | Expr::LiteralNumber { .. }
+        | Expr::LiteralString { .. }
+        | Expr::LiteralBool { .. }
+        | Expr::Variable { .. } => vec![],
+        // The body is a statement, checked separately.
+        Expr::Function { .. } => vec![],
+    }
+}
+
+fn stmt_children(stmt: &Stmt) -> (Vec<StmtId>, Vec<NodeId>) {
+    match stmt {
+        Stmt::Block(statements) => (statements.clone(), vec![]),
+        Stmt::Break { .. } | Stmt::Continue { .. } => (vec![], vec![]),
+        Stmt::Expression { expr, .. } | Stmt::Print { expr, .. } => (vec![], vec![*expr]),
+        Stmt::For {
+            initializer,
+            condition,
+            increment,
+            body,
+        } => (
+            initializer.iter().chain([body]).copied().collect(),
+            condition.iter().chain(increment).copied().collect(),
+        ),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => (
+            [then_branch]
+                .into_iter()
+                .chain(else_branch)
+                .copied()
+                .collect(),
+            vec![*condition],
+        ),
+        Stmt::Return { value, .. } => (vec![], value.iter().copied().collect()),
+        Stmt::Var { initializer, .. } => (vec![], initializer.iter().copied().collect()),
+        Stmt::While {
+            condition, body, ..
+        } => (vec![*body], vec![*condition]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn parse(source: &str) -> (Ast, NodeId) {
+        let mut scanner = Scanner::new(source);
+        let mut ast = Ast::new();
+        let root = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse()
+            .unwrap();
+        (ast, root)
+    }
+
+    #[test]
+    fn tokens_schema() {
+        let mut scanner = Scanner::new("x = 1.5");
+        let json = tokens_to_json(scanner.scan_tokens()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!([
+                {"token": {"type": "Identifier", "identifier": "x"}, "line_number": 1},
+                {"token": {"type": "Equal"}, "line_number": 1},
+                {"token": {"type": "Number", "number": 1.5}, "line_number": 1},
+                {"token": {"type": "EOF"}, "line_number": 1},
+            ])
+        );
+    }
+
+    #[test]
+    fn ast_schema() {
+        let (ast, root) = parse("-(1 == \"one\")");
+        let value: serde_json::Value =
+            serde_json::from_str(&ast_to_json(&ast, root).unwrap()).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "version": 8,
+                "root": 4,
+                "nodes": [
+                    {"type": "LiteralNumber", "value": {"value": 1.0, "line_number": 1}},
+                    {"type": "LiteralString", "value": {"value": "one", "line_number": 1}},
+                    {
+                        "type": "Binary",
+                        "value": {"left": 0, "operator": "==", "line_number": 1, "right": 1}
+                    },
+                    {"type": "Grouping", "value": 2},
+                    {"type": "Unary", "value": {"operator": "-", "line_number": 1, "right": 3}},
+                ],
+                "stmts": [],
+            })
+        );
+    }
+
+    #[test]
+    fn ast_round_trip() {
+        let (ast, root) = parse("1 + 2 * -(3 - nil) != Math.max(a, true)()");
+        let json = ast_to_json(&ast, root).unwrap();
+        let (read_ast, read_root) = ast_from_json(&json).unwrap();
+        assert_eq!(read_ast, ast);
+        assert_eq!(read_root, root);
+    }
+
+    #[test]
+    fn program_round_trip() {
+        let source = "var a = 1; for (;a < 3;) { if (a) print a; else a = nil; }\n\
+                      var f = fun (x) { return fun () { return x; }; };";
+        let mut scanner = Scanner::new(source);
+        let mut ast = Ast::new();
+        let statements = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse_program()
+            .unwrap();
+        let json = program_to_json(&ast, &statements).unwrap();
+        let (read_ast, read_statements) = program_from_json(&json).unwrap();
+        assert_eq!(read_ast, ast);
+        assert_eq!(read_statements, statements);
+    }
+
+    #[test]
+    fn invalid_ast() {
+        // Forward reference.
+        assert!(ast_from_json(
+            r#"{"version": 8, "root": 0, "nodes": [{"type": "Grouping", "value": 0}]}"#
+        )
+        .is_err());
+        // Missing root.
+        assert!(ast_from_json(
+            r#"{"version": 8, "root": 1, "nodes": [{"type": "Nil", "value": {"line_number": 1}}]}"#
+        )
+        .is_err());
+        // Unknown version.
+        assert!(ast_from_json(
+            r#"{"version": 0, "root": 0, "nodes": [{"type": "Nil", "value": {"line_number": 1}}]}"#
+        )
+        .is_err());
+        // Statement forward reference.
+        assert!(program_from_json(
+            r#"{"version": 8, "statements": [0], "nodes": [], "stmts": [{"type": "Block", "value": [0]}]}"#
+        )
+        .is_err());
+        // Statement referring to a missing node.
+        assert!(program_from_json(
+            r#"{"version": 8, "statements": [0], "nodes": [], "stmts": [{"type": "Print", "value": {"expr": 0, "line_number": 1}}]}"#
+        )
+        .is_err());
+        // A function whose body refers back to it.
+        let cycle = r#"{"version": 8, "statements": [1], "nodes": [
+            {"type": "Function", "value": {"params": [], "body": 0, "line_number": 1}}
+        ], "stmts": [
+            {"type": "Return", "value": {"value": 0, "line_number": 1}},
+            {"type": "Block", "value": [0]}
+        ]}"#;
+        assert_eq!(
+            program_from_json(cycle).err().unwrap().to_string(),
+            "Node 0 has a body that isn't a block."
+        );
+        let cycle = cycle.replace(r#""body": 0"#, r#""body": 1"#);
+        assert_eq!(
+            program_from_json(&cycle).err().unwrap().to_string(),
+            "Node 0 has a body that refers to a node that doesn't precede it."
+        );
+        // Too many elements for `BuildList`.
+        let elements = vec![r#"{"type": "Nil", "value": {"line_number": 1}}"#; 256];
+        let ids: Vec<_> = (0..256).map(|id| id.to_string()).collect();
+        let list = format!(
+            r#"{{"version": 8, "root": 256, "nodes": [{}, {{"type": "List", "value": [{}]}}]}}"#,
+            elements.join(", "),
+            ids.join(", ")
+        );
+        assert_eq!(
+            ast_from_json(&list).err().unwrap().to_string(),
+            "Node 256 has more than 255 elements."
+        );
+        // `break` outside of a loop, even inside one's body.
+        let stray_break = r#"{"version": 8, "statements": [1], "nodes": [], "stmts": [
+            {"type": "Break", "value": {"line_number": 1}},
+            {"type": "Block", "value": [0]}
+        ]}"#;
+        assert_eq!(
+            program_from_json(stray_break).err().unwrap().to_string(),
+            "Statement 0 is outside of a loop."
+        );
+        let in_loop = r#"{"version": 8, "statements": [2], "nodes": [
+            {"type": "LiteralBool", "value": {"value": true, "line_number": 1}}
+        ], "stmts": [
+            {"type": "Break", "value": {"line_number": 1}},
+            {"type": "Block", "value": [0]},
+            {"type": "While", "value": {"condition": 0, "body": 1, "line_number": 1}}
+        ]}"#;
+        assert!(program_from_json(in_loop).is_ok());
+        // The same `break` reused outside of the loop.
+        let shared = in_loop.replace(r#""statements": [2]"#, r#""statements": [2, 0]"#);
+        assert_eq!(
+            program_from_json(&shared).err().unwrap().to_string(),
+            "Statement 0 is outside of a loop."
+        );
+        // `return` at the top level.
+        let stray_return = r#"{"version": 8, "statements": [0], "nodes": [], "stmts": [
+            {"type": "Return", "value": {"value": null, "line_number": 1}}
+        ]}"#;
+        assert_eq!(
+            program_from_json(stray_return).err().unwrap().to_string(),
+            "Statement 0 is outside of a function."
+        );
+    }
+}
@@ -1,5 +1,28 @@
 pub mod expr;
+pub mod stmt;
 pub mod ast_print;
+pub mod ast_read;
 pub mod rpn_print;
+pub mod rpn_eval;
+pub mod dot_print;
+pub mod json;
 pub mod scanner;
-pub mod parser;
\ No newline at end of file
+pub mod parser;
+pub mod formatter;
+pub mod symbol;
+pub mod value;
+pub mod list;
+pub mod map;
+pub mod native;
+pub mod stdlib;
+pub mod limits;
+pub mod resolver;
+pub mod interpreter;
+pub mod chunk;
+pub mod compiler;
+pub mod vm_value;
+pub mod heap;
+pub mod vm;
+pub mod disassembler;
+pub mod loxc;
+pub mod embed;
@@ -0,0 +1,75 @@
+//! Bounds on what a program may use, for running untrusted code.
+
+/// What a single run (`Interpreter::interpret`, `Vm::run`) may use. `None`
+/// means unlimited.
+///
+/// Steps are statements for the tree-walker and instructions for the VM, so
+/// the same program takes a different number of steps on each.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    /// How many calls can be in progress at once.
+    pub max_call_depth: Option<usize>,
+    /// Roughly, everything the program can still reach: strings, lists,
+    /// maps and closures, however they refer to each other.
+    ///
+    /// NOTE: only the VM enforces this, since the tree-walker's values
+    /// aren't on a heap of their own, so the CLI refuses it for the
+    /// tree-walker. It's checked after each instruction, so a single
+    /// allocation can go over.
+    pub max_heap_bytes: Option<usize>,
+}
+
+/// Deep enough for any reasonable recursion, but shallow enough that the
+/// tree-walker doesn't overflow the Rust stack first.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 256;
+
+impl Default for Limits {
+    /// Only the call depth is limited, since overflowing the Rust stack would
+    /// crash the whole process.
+    fn default() -> Self {
+        Limits {
+            max_steps: None,
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            max_heap_bytes: None,
+        }
+    }
+}
+
+impl Limits {
+    pub fn unlimited() -> Self {
+        Limits {
+            max_steps: None,
+            max_call_depth: None,
+            max_heap_bytes: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LimitKind {
+    Steps,
+    CallDepth,
+    HeapBytes,
+}
+
+/// The runtime error for going over one of the `Limits`. Hosts can tell it
+/// apart from other errors with `anyhow::Error::downcast_ref`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LimitExceeded {
+    pub kind: LimitKind,
+    pub line_number: usize,
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let message = match self.kind {
+            LimitKind::Steps => "Step limit exceeded.",
+            LimitKind::CallDepth => "Stack overflow.",
+            LimitKind::HeapBytes => "Heap limit exceeded.",
+        };
+        write!(f, "[line {}] {}", self.line_number, message)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
@@ -0,0 +1,305 @@
+use crate::native::NativeFunction;
+use crate::symbol::Symbol;
+use crate::value;
+use crate::value::Value;
+use std::cell::Ref;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Method = fn(&List, &[Value]) -> anyhow::Result<Value>;
+
+/// A Lox list, shared by every value that refers to it, so changes through one
+/// are seen through all of them.
+///
+/// These are the tree-walker's lists, and how hosts see the VM's, which are
+/// on its `Heap` instead.
+///
+/// NOTE: lists are reference counted, so one that ends up containing itself
+/// is never freed. The VM's are traced, so they don't have this problem.
+pub struct List {
+    // None of the methods run Lox code while they hold a borrow, so borrowing
+    // can't fail.
+    elements: RefCell<Vec<Value>>,
+}
+
+impl List {
+    pub fn new(elements: Vec<Value>) -> Self {
+        List {
+            elements: RefCell::new(elements),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// For hosts to read what a script put in the list.
+    pub fn elements(&self) -> Ref<'_, Vec<Value>> {
+        self.elements.borrow()
+    }
+
+    // Swaps in new elements, returning the old ones. For filling in a copy
+    // of a list that might contain itself, and for `value::break_cycles`.
+    pub(crate) fn replace(&self, elements: Vec<Value>) -> Vec<Value> {
+        std::mem::replace(&mut *self.elements.borrow_mut(), elements)
+    }
+
+    /// `list[index]`. Negative indices count back from the end.
+    pub fn get(&self, index: &Value) -> anyhow::Result<Value> {
+        let elements = self.elements.borrow();
+        let index = element_index(number(index), elements.len())?;
+        Ok(elements[index].clone())
+    }
+
+    /// `list[index] = value`.
+    pub fn set(&self, index: &Value, value: Value) -> anyhow::Result<()> {
+        let mut elements = self.elements.borrow_mut();
+        let index = element_index(number(index), elements.len())?;
+        elements[index] = value;
+        Ok(())
+    }
+
+    /// Binds one of the built-in methods to this list.
+    pub fn method(self: &Rc<Self>, name: Symbol) -> anyhow::Result<Value> {
+        let list = self.clone();
+        let (arity, method): (u8, Method) = match name.as_str() {
+            "push" => (1, List::push),
+            "pop" => (0, List::pop),
+            "len" => (0, |list, _| Ok(Value::Number(list.len() as f64))),
+            "insert" => (2, List::insert),
+            "remove" => (1, List::remove),
+            "slice" => (2, List::slice),
+            _ => anyhow::bail!("Undefined property '{}'.", name),
+        };
+        let bound = NativeFunction::new(&format!("list.{}", name), arity, move |arguments| {
+            method(&list, arguments)
+        });
+        Ok(Value::Native(Rc::new(bound)))
+    }
+
+    fn push(&self, arguments: &[Value]) -> anyhow::Result<Value> {
+        self.elements.borrow_mut().push(arguments[0].clone());
+        Ok(Value::Nil)
+    }
+
+    fn pop(&self, _: &[Value]) -> anyhow::Result<Value> {
+        self.elements
+            .borrow_mut()
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("Can't pop from an empty list."))
+    }
+
+    // Before the element at `index`, or at the end if it's the length.
+    fn insert(&self, arguments: &[Value]) -> anyhow::Result<Value> {
+        let mut elements = self.elements.borrow_mut();
+        let index = element_index(number(&arguments[0]), elements.len() + 1)?;
+        elements.insert(index, arguments[1].clone());
+        Ok(Value::Nil)
+    }
+
+    fn remove(&self, arguments: &[Value]) -> anyhow::Result<Value> {
+        let mut elements = self.elements.borrow_mut();
+        let index = element_index(number(&arguments[0]), elements.len())?;
+        Ok(elements.remove(index))
+    }
+
+    // A new list of the elements from `start` up to, but not including, `end`.
+    // Unlike indexing, out of range bounds are clamped rather than an error.
+    fn slice(&self, arguments: &[Value]) -> anyhow::Result<Value> {
+        let elements = self.elements.borrow();
+        let start = slice_bound(number(&arguments[0]), elements.len())?;
+        let end = slice_bound(number(&arguments[1]), elements.len())?.max(start);
+        let slice = elements[start..end].to_vec();
+        Ok(Value::List(Rc::new(List::new(slice))))
+    }
+
+    // Nested lists and maps are written inline, except for one that's
+    // already being written further out, which would never end. `open` has
+    // those.
+    pub(crate) fn write(
+        &self,
+        f: &mut std::fmt::Formatter,
+        open: &mut Vec<*const ()>,
+    ) -> std::fmt::Result {
+        let this = self as *const List as *const ();
+        if open.contains(&this) {
+            return write!(f, "[...]");
+        }
+        open.push(this);
+        write!(f, "[")?;
+        for (index, element) in self.elements.borrow().iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            value::write_nested(f, element, open)?;
+        }
+        open.pop();
+        write!(f, "]")
+    }
+}
+
+// Lists are only equal to themselves, like other objects.
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::fmt::Display for List {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.write(f, &mut Vec::new())
+    }
+}
+
+impl std::fmt::Debug for List {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+// Indices and bounds are taken as numbers, or `None` for anything else, so
+// that the VM can share the checks below.
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => Some(*number),
+        _ => None,
+    }
+}
+
+fn integer(index: Option<f64>) -> anyhow::Result<f64> {
+    match index {
+        Some(number) if number.fract() == 0.0 => Ok(number),
+        _ => anyhow::bail!("List index must be an integer."),
+    }
+}
+
+// Where `index` points in a list of `len` elements.
+pub(crate) fn element_index(index: Option<f64>, len: usize) -> anyhow::Result<usize> {
+    let number = integer(index)?;
+    let resolved = if number < 0.0 {
+        number + len as f64
+    } else {
+        number
+    };
+    if resolved < 0.0 || resolved >= len as f64 {
+        anyhow::bail!(
+            "Index {} is out of bounds for a list of length {}.",
+            number,
+            len
+        );
+    }
+    Ok(resolved as usize)
+}
+
+pub(crate) fn slice_bound(bound: Option<f64>, len: usize) -> anyhow::Result<usize> {
+    let number = integer(bound)?;
+    let resolved = if number < 0.0 {
+        number + len as f64
+    } else {
+        number
+    };
+    Ok(resolved.clamp(0.0, len as f64) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(numbers: &[f64]) -> Rc<List> {
+        Rc::new(List::new(
+            numbers.iter().copied().map(Value::Number).collect(),
+        ))
+    }
+
+    fn call(list: &Rc<List>, name: &str, arguments: &[Value]) -> anyhow::Result<Value> {
+        let Value::Native(method) = list.method(Symbol::intern(name))? else {
+            panic!("{} should be a bound method", name);
+        };
+        method.call(arguments)
+    }
+
+    #[test]
+    fn indexing() {
+        let xs = list(&[1.0, 2.0, 3.0]);
+        assert_eq!(xs.get(&Value::Number(0.0)).unwrap(), Value::Number(1.0));
+        assert_eq!(xs.get(&Value::Number(-1.0)).unwrap(), Value::Number(3.0));
+        xs.set(&Value::Number(-3.0), Value::Nil).unwrap();
+        assert_eq!(xs.to_string(), "[nil, 2, 3]");
+
+        for (index, message) in [
+            (
+                Value::Number(3.0),
+                "Index 3 is out of bounds for a list of length 3.",
+            ),
+            (
+                Value::Number(-4.0),
+                "Index -4 is out of bounds for a list of length 3.",
+            ),
+            (Value::Number(0.5), "List index must be an integer."),
+            (Value::Nil, "List index must be an integer."),
+        ] {
+            assert_eq!(xs.get(&index).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn methods() {
+        let xs = list(&[1.0]);
+        call(&xs, "push", &[Value::Number(2.0)]).unwrap();
+        call(&xs, "insert", &[Value::Number(0.0), Value::Number(0.0)]).unwrap();
+        call(&xs, "insert", &[Value::Number(3.0), Value::Number(3.0)]).unwrap();
+        assert_eq!(xs.to_string(), "[0, 1, 2, 3]");
+        assert_eq!(call(&xs, "len", &[]).unwrap(), Value::Number(4.0));
+
+        assert_eq!(
+            call(&xs, "remove", &[Value::Number(1.0)]).unwrap(),
+            Value::Number(1.0)
+        );
+        assert_eq!(call(&xs, "pop", &[]).unwrap(), Value::Number(3.0));
+        assert_eq!(xs.to_string(), "[0, 2]");
+
+        let empty = list(&[]);
+        assert_eq!(
+            call(&empty, "pop", &[]).unwrap_err().to_string(),
+            "Can't pop from an empty list."
+        );
+        assert_eq!(
+            call(&empty, "remove", &[Value::Number(0.0)])
+                .unwrap_err()
+                .to_string(),
+            "Index 0 is out of bounds for a list of length 0."
+        );
+        assert_eq!(
+            xs.method(Symbol::intern("nope")).unwrap_err().to_string(),
+            "Undefined property 'nope'."
+        );
+    }
+
+    #[test]
+    fn slices() {
+        let xs = list(&[1.0, 2.0, 3.0, 4.0]);
+        let slice = |start: f64, end: f64| {
+            call(&xs, "slice", &[Value::Number(start), Value::Number(end)])
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(slice(1.0, 3.0), "[2, 3]");
+        assert_eq!(slice(-2.0, 100.0), "[3, 4]");
+        assert_eq!(slice(-100.0, 1.0), "[1]");
+        assert_eq!(slice(3.0, 1.0), "[]");
+    }
+
+    #[test]
+    fn printing_cycles() {
+        let xs = list(&[1.0]);
+        let ys = list(&[]);
+        call(&ys, "push", &[Value::List(xs.clone())]).unwrap();
+        call(&xs, "push", &[Value::List(ys.clone())]).unwrap();
+        call(&xs, "push", &[Value::List(xs.clone())]).unwrap();
+        assert_eq!(xs.to_string(), "[1, [[...]], [...]]");
+    }
+}
@@ -0,0 +1,585 @@
+//! The `.loxc` precompiled bytecode format, so scripts can skip scanning,
+//! parsing and compiling at startup.
+//!
+//! All integers are little-endian. A file is a header followed by a payload:
+//!
+//! | Field      | Size | Contents                                        |
+//! |------------|------|-------------------------------------------------|
+//! | magic      | 4    | `LOXC`                                          |
+//! | version    | 2    | `FORMAT_VERSION`                                |
+//! | checksum   | 4    | CRC-32 (IEEE) of the payload                    |
+//! | payload    | rest | prototype count (u32), then each prototype      |
+//!
+//! The first prototype is the top-level script. A prototype is its name
+//! (string), arity (u8), upvalues and chunk. The upvalues are a count (u32),
+//! each an `is_local` byte and an index byte. A chunk is:
+//!
+//! - code: length (u32) and bytes
+//! - constants: count (u32), each a tag byte followed by its value: `0` nil,
+//!   `1` false, `2` true, `3` number (f64), `4` string
+//! - line info: run count (u32), each run a line number and byte count (u32s)
+//! - functions: count (u32), then each function's prototype
+//!
+//! Strings are a length (u32) followed by UTF-8 bytes.
+//!
+//! Files are validated when they're read, including the bytecode itself, so a
+//! bad file is an error rather than a crash in the VM. `FORMAT_VERSION` is
+//! bumped on any change to the layout, or to the instruction set:
+//!
+//! 1. The first version.
+//! 2. `CALL` and `GET_PROPERTY`.
+//! 3. `BUILD_LIST`, `GET_INDEX` and `SET_INDEX`.
+//! 4. `BUILD_MAP`.
+//! 5. Upvalues and nested functions in prototypes, and `CLOSURE`,
+//!    `GET_UPVALUE`, `SET_UPVALUE` and `CLOSE_UPVALUE`.
+
+use crate::chunk::Capture;
+use crate::chunk::Chunk;
+use crate::chunk::LineRun;
+use crate::chunk::OpCode;
+use crate::chunk::Prototype;
+use crate::value::Value;
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+pub const FORMAT_VERSION: u16 = 5;
+
+const HEADER_LEN: usize = 10;
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+
+/// Whether `bytes` look like a `.loxc` file, as opposed to Lox source.
+pub fn is_loxc(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn write(prototypes: &[Prototype]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    write_u32(&mut payload, prototypes.len());
+    for prototype in prototypes {
+        write_prototype(&mut payload, prototype);
+    }
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+/// Reads the prototypes in a `.loxc` file, the script first.
+pub fn read(bytes: &[u8]) -> anyhow::Result<Vec<Prototype>> {
+    if !is_loxc(bytes) {
+        anyhow::bail!("Not a .loxc file.");
+    }
+    if bytes.len() < HEADER_LEN {
+        anyhow::bail!("Truncated .loxc file.");
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FORMAT_VERSION {
+        anyhow::bail!(
+            "Unsupported .loxc version {} (expected {}).",
+            version,
+            FORMAT_VERSION
+        );
+    }
+    let checksum = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+    let payload = &bytes[HEADER_LEN..];
+    if crc32(payload) != checksum {
+        anyhow::bail!("Corrupt .loxc file: checksum mismatch.");
+    }
+
+    let mut reader = Reader { bytes: payload };
+    let count = reader.u32()?;
+    let mut prototypes = Vec::new();
+    for _ in 0..count {
+        let prototype = reader.prototype()?;
+        // Only nested functions have anything to capture.
+        if !prototype.upvalues.is_empty() {
+            anyhow::bail!("{} captures variables, but isn't nested.", prototype.name);
+        }
+        // Like the script, its locals start at the bottom of the stack.
+        validate(&prototype, 0)?;
+        prototypes.push(prototype);
+    }
+    if !reader.bytes.is_empty() {
+        anyhow::bail!("Unexpected data after the last prototype.");
+    }
+    if prototypes.is_empty() {
+        anyhow::bail!("No script in .loxc file.");
+    }
+    Ok(prototypes)
+}
+
+fn write_prototype(bytes: &mut Vec<u8>, prototype: &Prototype) {
+    write_string(bytes, &prototype.name);
+    bytes.push(prototype.arity);
+    write_u32(bytes, prototype.upvalues.len());
+    for capture in &prototype.upvalues {
+        bytes.push(capture.is_local as u8);
+        bytes.push(capture.index);
+    }
+    write_chunk(bytes, &prototype.chunk);
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, chunk: &Chunk) {
+    write_u32(bytes, chunk.code.len());
+    bytes.extend_from_slice(&chunk.code);
+
+    write_u32(bytes, chunk.constants.len());
+    for constant in &chunk.constants {
+        match constant {
+            Value::Nil => bytes.push(TAG_NIL),
+            Value::Bool(false) => bytes.push(TAG_FALSE),
+            Value::Bool(true) => bytes.push(TAG_TRUE),
+            Value::Number(number) => {
+                bytes.push(TAG_NUMBER);
+                bytes.extend_from_slice(&number.to_le_bytes());
+            }
+            Value::String(ss) => {
+                bytes.push(TAG_STRING);
+                write_string(bytes, ss);
+            }
+            Value::List(_)
+            | Value::Map(_)
+            | Value::Native(_)
+            | Value::Namespace(_)
+            | Value::NativeClass(_)
+            | Value::NativeInstance(_)
+            | Value::Function(_)
+            | Value::Closure(_) => {
+                unreachable!("only literals are constants, functions are in `functions`")
+            }
+        }
+    }
+
+    write_u32(bytes, chunk.lines.len());
+    for run in &chunk.lines {
+        write_u32(bytes, run.line_number);
+        write_u32(bytes, run.count);
+    }
+
+    write_u32(bytes, chunk.functions.len());
+    for function in &chunk.functions {
+        write_prototype(bytes, function);
+    }
+}
+
+fn write_string(bytes: &mut Vec<u8>, ss: &str) {
+    write_u32(bytes, ss.len());
+    bytes.extend_from_slice(ss.as_bytes());
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: usize) {
+    let value = u32::try_from(value).expect("too large for a .loxc file");
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&[u8]> {
+        if self.bytes.len() < len {
+            anyhow::bail!("Truncated .loxc file.");
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> anyhow::Result<usize> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let len = self.u32()?;
+        Ok(std::str::from_utf8(self.take(len)?)?.to_owned())
+    }
+
+    fn prototype(&mut self) -> anyhow::Result<Prototype> {
+        let name = self.string()?;
+        let arity = self.u8()?;
+        let mut upvalues = Vec::new();
+        for _ in 0..self.u32()? {
+            let is_local = match self.u8()? {
+                0 => false,
+                1 => true,
+                byte => anyhow::bail!("Invalid upvalue kind {}.", byte),
+            };
+            let index = self.u8()?;
+            upvalues.push(Capture { is_local, index });
+        }
+        let chunk = self.chunk()?;
+        Ok(Prototype {
+            name,
+            arity,
+            upvalues,
+            chunk,
+        })
+    }
+
+    fn chunk(&mut self) -> anyhow::Result<Chunk> {
+        let mut chunk = Chunk::new();
+        let len = self.u32()?;
+        chunk.code = self.take(len)?.to_vec();
+
+        for _ in 0..self.u32()? {
+            let constant = match self.u8()? {
+                TAG_NIL => Value::Nil,
+                TAG_FALSE => Value::Bool(false),
+                TAG_TRUE => Value::Bool(true),
+                TAG_NUMBER => Value::Number(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+                TAG_STRING => Value::String(self.string()?.into()),
+                tag => anyhow::bail!("Unknown constant tag {}.", tag),
+            };
+            chunk.constants.push(constant);
+        }
+
+        for _ in 0..self.u32()? {
+            let line_number = self.u32()?;
+            let count = self.u32()?;
+            chunk.lines.push(LineRun { line_number, count });
+        }
+
+        for _ in 0..self.u32()? {
+            let function = self.prototype()?;
+            chunk.functions.push(function.into());
+        }
+        Ok(chunk)
+    }
+}
+
+// Validates a prototype's chunk, and then its functions'.
+// `stack_start` is how many values the function starts with on its part of
+// the stack.
+fn validate(prototype: &Prototype, stack_start: usize) -> anyhow::Result<()> {
+    validate_chunk(&prototype.chunk, prototype.upvalues.len(), stack_start)
+        .map_err(|err| err.context(format!("Invalid bytecode in {}", prototype.name)))?;
+    for function in &prototype.chunk.functions {
+        // Captured locals are stack slots, which the VM checks, like
+        // `GetLocal`'s.
+        let upvalue_count = prototype.upvalues.len();
+        if let Some(capture) = function
+            .upvalues
+            .iter()
+            .find(|capture| !capture.is_local && capture.index as usize >= upvalue_count)
+        {
+            anyhow::bail!(
+                "Invalid bytecode in {}: upvalue {} doesn't exist.",
+                function.name,
+                capture.index
+            );
+        }
+        // The closure itself, then the arguments.
+        validate(function, 1 + function.arity as usize)?;
+    }
+    Ok(())
+}
+
+// Checks everything the VM takes for granted: every instruction is whole,
+// operands point at things that exist, every byte has a line, and nothing pops
+// more than is on the stack.
+fn validate_chunk(chunk: &Chunk, upvalue_count: usize, stack_start: usize) -> anyhow::Result<()> {
+    let lines: usize = chunk.lines.iter().map(|run| run.count).sum();
+    if lines != chunk.code.len() {
+        anyhow::bail!(
+            "Line info covers {} bytes, but there are {}.",
+            lines,
+            chunk.code.len()
+        );
+    }
+
+    // Jumps have to land on an instruction, so collect those first.
+    let mut instruction_starts = Vec::new();
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let op = OpCode::try_from(chunk.code[offset])
+            .map_err(|err| err.context(format!("At offset {}", offset)))?;
+        instruction_starts.push(offset);
+        offset += 1 + op.operand_len();
+    }
+    if offset != chunk.code.len() {
+        anyhow::bail!("The last instruction is missing operands.");
+    }
+    if chunk.code.last() != Some(&(OpCode::Return as u8)) {
+        anyhow::bail!("Code doesn't end with a return.");
+    }
+
+    for &offset in &instruction_starts {
+        // Already decoded above.
+        let op = OpCode::try_from(chunk.code[offset]).unwrap();
+        let operand = chunk.code.get(offset + 1).copied().unwrap_or_default();
+        match op {
+            OpCode::Constant if operand as usize >= chunk.constants.len() => {
+                anyhow::bail!("Constant {} at offset {} doesn't exist.", operand, offset);
+            }
+            OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+                if !matches!(
+                    chunk.constants.get(operand as usize),
+                    Some(Value::String(_))
+                ) =>
+            {
+                anyhow::bail!("Name at offset {} isn't a string.", offset);
+            }
+            OpCode::Closure if operand as usize >= chunk.functions.len() => {
+                anyhow::bail!("Function {} at offset {} doesn't exist.", operand, offset);
+            }
+            OpCode::GetUpvalue | OpCode::SetUpvalue if operand as usize >= upvalue_count => {
+                anyhow::bail!("Upvalue {} at offset {} doesn't exist.", operand, offset);
+            }
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                let jump = u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+                let next = offset + 3;
+                let target = if op == OpCode::Loop {
+                    next.checked_sub(jump as usize)
+                } else {
+                    Some(next + jump as usize)
+                };
+                if target.is_none_or(|target| instruction_starts.binary_search(&target).is_err()) {
+                    anyhow::bail!("Jump at offset {} doesn't land on an instruction.", offset);
+                }
+            }
+            // Stack slots are only known at runtime, so the VM checks them.
+            _ => {}
+        }
+    }
+    validate_stack(chunk, &instruction_starts, stack_start)
+}
+
+// Follows every path through the code, tracking how deep the stack is. Each
+// instruction has to be reached with the same depth every time, so that loops
+// don't grow or shrink the stack.
+fn validate_stack(
+    chunk: &Chunk,
+    instruction_starts: &[usize],
+    stack_start: usize,
+) -> anyhow::Result<()> {
+    // Indexed like `instruction_starts`, `None` until reached.
+    let mut depths: Vec<Option<usize>> = vec![None; instruction_starts.len()];
+    let mut pending = vec![(0, stack_start)];
+    while let Some((offset, depth)) = pending.pop() {
+        // Jumps were already checked to land on an instruction.
+        let index = instruction_starts.binary_search(&offset).unwrap();
+        match depths[index] {
+            Some(known) if known == depth => continue,
+            Some(_) => anyhow::bail!("Stack depth at offset {} depends on the path.", offset),
+            None => depths[index] = Some(depth),
+        }
+
+        let op = OpCode::try_from(chunk.code[offset]).unwrap();
+        let operand = chunk.code.get(offset + 1).copied().unwrap_or_default();
+        let (pops, pushes) = op.stack_effect(operand);
+        let Some(depth) = depth.checked_sub(pops) else {
+            anyhow::bail!("Instruction at offset {} pops an empty stack.", offset);
+        };
+        let depth = depth + pushes;
+        let next = offset + 1 + op.operand_len();
+        match op {
+            OpCode::Return => {}
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                let jump = u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+                let target = if op == OpCode::Loop {
+                    next - jump as usize
+                } else {
+                    next + jump as usize
+                };
+                pending.push((target, depth));
+                if op == OpCode::JumpIfFalse {
+                    pending.push((next, depth));
+                }
+            }
+            // Only `Return` ends the code, which was checked above.
+            _ => pending.push((next, depth)),
+        }
+    }
+    Ok(())
+}
+
+// Bitwise CRC-32 with the IEEE polynomial, as used by zip and PNG. Files are
+// small enough that a lookup table isn't worth it.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::SCRIPT_NAME;
+    use crate::compiler;
+    use crate::expr::Ast;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use std::rc::Rc;
+
+    fn compile(source: &str) -> Vec<Prototype> {
+        let mut scanner = Scanner::new(source);
+        let mut ast = Ast::new();
+        let statements = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse_program()
+            .unwrap();
+        vec![Prototype {
+            name: SCRIPT_NAME.to_owned(),
+            arity: 0,
+            upvalues: Vec::new(),
+            chunk: compiler::compile(&ast, &statements).unwrap(),
+        }]
+    }
+
+    // Fixes up the checksum after tampering with the payload.
+    fn reseal(bytes: &mut [u8]) {
+        let checksum = crc32(&bytes[HEADER_LEN..]);
+        bytes[6..HEADER_LEN].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let prototypes =
+            compile("var a = \"str\";\nwhile (a) { var b = -1.5; a = b > 0 and nil; }");
+        let bytes = write(&prototypes);
+        assert!(is_loxc(&bytes));
+        assert_eq!(read(&bytes).unwrap(), prototypes);
+    }
+
+    #[test]
+    fn functions_round_trip() {
+        let prototypes =
+            compile("var counter = fun () { var n = 0; return fun () { n = n + 1; return n; }; };");
+        assert_eq!(prototypes[0].chunk.functions[0].chunk.functions.len(), 1);
+        let bytes = write(&prototypes);
+        assert_eq!(read(&bytes).unwrap(), prototypes);
+    }
+
+    #[test]
+    fn upvalue_validation() {
+        let mut prototypes = compile("{ var a; fun () { return a; }; }");
+        let function = Rc::get_mut(&mut prototypes[0].chunk.functions[0]).unwrap();
+        // The script has no upvalues of its own to capture.
+        function.upvalues[0].is_local = false;
+        assert_eq!(
+            read(&write(&prototypes)).unwrap_err().to_string(),
+            "Invalid bytecode in <fn>: upvalue 0 doesn't exist."
+        );
+
+        let mut prototypes = compile("fun () { return 1; };");
+        let function = Rc::get_mut(&mut prototypes[0].chunk.functions[0]).unwrap();
+        function.chunk.code[0] = OpCode::GetUpvalue as u8;
+        assert!(read(&write(&prototypes)).is_err());
+    }
+
+    #[test]
+    fn format_version_covers_the_instruction_set() {
+        // A new opcode needs a new version, and then this updated.
+        assert_eq!(FORMAT_VERSION, 5);
+        assert!(OpCode::try_from(36).is_ok());
+        assert!(OpCode::try_from(37).is_err());
+    }
+
+    #[test]
+    fn header_validation() {
+        let bytes = write(&compile("print 1;"));
+        assert!(read(b"print 1;").is_err());
+        assert!(read(&bytes[..8]).is_err());
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4] = 99;
+        assert_eq!(
+            read(&wrong_version).unwrap_err().to_string(),
+            format!(
+                "Unsupported .loxc version 99 (expected {}).",
+                FORMAT_VERSION
+            )
+        );
+
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            read(&corrupted).unwrap_err().to_string(),
+            "Corrupt .loxc file: checksum mismatch."
+        );
+    }
+
+    #[test]
+    fn bytecode_validation() {
+        let bytes = write(&compile("print 1;"));
+        // Code is `CONSTANT 0, PRINT, RETURN`, starting after the prototype's
+        // count, name, arity, upvalue count and code length.
+        let code_start = HEADER_LEN + 4 + 4 + SCRIPT_NAME.len() + 1 + 4 + 4;
+        assert_eq!(bytes[code_start], OpCode::Constant as u8);
+
+        let mut bad_constant = bytes.clone();
+        bad_constant[code_start + 1] = 7;
+        reseal(&mut bad_constant);
+        assert!(read(&bad_constant).is_err());
+
+        let mut bad_opcode = bytes.clone();
+        bad_opcode[code_start + 2] = 0xff;
+        reseal(&mut bad_opcode);
+        assert!(read(&bad_opcode).is_err());
+
+        let mut no_return = bytes.clone();
+        no_return[code_start + 3] = OpCode::Nil as u8;
+        reseal(&mut no_return);
+        assert!(read(&no_return).is_err());
+
+        let mut underflow = Chunk::new();
+        for op in [OpCode::Pop, OpCode::Pop, OpCode::Pop, OpCode::Return] {
+            underflow.write_op(op, 1);
+        }
+        let underflow = Prototype {
+            name: SCRIPT_NAME.to_owned(),
+            arity: 0,
+            upvalues: Vec::new(),
+            chunk: underflow,
+        };
+        assert_eq!(
+            format!("{:#}", read(&write(&[underflow])).unwrap_err()),
+            "Invalid bytecode in <script>: Instruction at offset 0 pops an empty stack."
+        );
+
+        // Each time around the loop pushes another value, instead of popping
+        // the condition.
+        let mut prototypes = compile("while (true) {}");
+        let code = &mut prototypes[0].chunk.code;
+        assert_eq!(code[4], OpCode::Pop as u8);
+        code[4] = OpCode::Nil as u8;
+        assert!(
+            format!("{:#}", read(&write(&prototypes)).unwrap_err()).contains("depends on the path")
+        );
+
+        // A function's arguments are already on the stack.
+        assert!(read(&write(&compile("fun (a, b) { return a + b; };"))).is_ok());
+        let mut prototypes = compile("fun (a) { a; };");
+        let function = Rc::get_mut(&mut prototypes[0].chunk.functions[0]).unwrap();
+        function.arity = 0;
+        function.chunk.code[0] = OpCode::Pop as u8;
+        function.chunk.code[1] = OpCode::Pop as u8;
+        assert!(read(&write(&prototypes)).is_err());
+    }
+}
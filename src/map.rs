@@ -0,0 +1,316 @@
+use crate::list::List;
+use crate::native::NativeFunction;
+use crate::symbol::Symbol;
+use crate::value;
+use crate::value::HashKey;
+use crate::value::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::rc::Rc;
+
+type Method = fn(&Map, &[Value]) -> anyhow::Result<Value>;
+
+/// A Lox map, from any value but NaN to any value. Like lists, maps are
+/// shared rather than copied.
+///
+/// Entries stay in the order their keys were first inserted, so printing a map
+/// or looping over its `keys()` gives the same result every time.
+///
+/// Like `List`, these are the tree-walker's maps, and how hosts see the VM's.
+/// One that ends up containing itself is never freed, unlike the VM's, which
+/// are traced.
+#[derive(Default)]
+pub struct Map {
+    // None of the methods run Lox code while they hold a borrow, so borrowing
+    // can't fail.
+    inner: RefCell<OrderedMap<HashKey, Value>>,
+}
+
+/// Entries in the order their keys were first inserted. Both `Map` and the
+/// VM's maps, which have their own keys and values, are built on it.
+#[derive(Debug)]
+pub struct OrderedMap<K, V> {
+    entries: Vec<(K, V)>,
+    // Where each key is in `entries`.
+    indices: HashMap<K, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `map[key]`. Unlike a missing property, a missing key is an error too,
+    /// use `has` to check first.
+    pub fn get(&self, key: &Value) -> anyhow::Result<Value> {
+        // NaN can't be a key, so it's never in the map.
+        HashKey::new(key.clone())
+            .ok()
+            .and_then(|hash_key| self.inner.borrow().get(&hash_key).cloned())
+            .ok_or_else(|| undefined_key(key))
+    }
+
+    /// `map[key] = value`, adding the key if it's new.
+    pub fn insert(&self, key: Value, value: Value) -> anyhow::Result<()> {
+        let key = HashKey::new(key)?;
+        self.inner.borrow_mut().insert(key, value);
+        Ok(())
+    }
+
+    pub fn contains_key(&self, key: &Value) -> bool {
+        HashKey::new(key.clone()).is_ok_and(|key| self.inner.borrow().contains_key(&key))
+    }
+
+    /// For hosts to read what a script put in the map, in order.
+    pub fn entries(&self) -> Vec<(Value, Value)> {
+        let inner = self.inner.borrow();
+        let entries = inner
+            .iter()
+            .map(|(key, value)| (key.value().clone(), value.clone()));
+        entries.collect()
+    }
+
+    // Drops every entry, for `value::break_cycles`.
+    pub(crate) fn clear(&self) {
+        let entries = std::mem::take(&mut *self.inner.borrow_mut());
+        drop(entries);
+    }
+
+    /// Binds one of the built-in methods to this map.
+    pub fn method(self: &Rc<Self>, name: Symbol) -> anyhow::Result<Value> {
+        let map = self.clone();
+        let (arity, method): (u8, Method) = match name.as_str() {
+            "keys" => (0, Map::keys),
+            "values" => (0, Map::values),
+            "has" => (1, |map, arguments| {
+                Ok(Value::Bool(map.contains_key(&arguments[0])))
+            }),
+            "remove" => (1, Map::remove),
+            "len" => (0, |map, _| Ok(Value::Number(map.len() as f64))),
+            _ => anyhow::bail!("Undefined property '{}'.", name),
+        };
+        let bound = NativeFunction::new(&format!("map.{}", name), arity, move |arguments| {
+            method(&map, arguments)
+        });
+        Ok(Value::Native(Rc::new(bound)))
+    }
+
+    fn keys(&self, _: &[Value]) -> anyhow::Result<Value> {
+        let inner = self.inner.borrow();
+        let keys = inner.iter().map(|(key, _)| key.value().clone());
+        Ok(Value::List(Rc::new(List::new(keys.collect()))))
+    }
+
+    fn values(&self, _: &[Value]) -> anyhow::Result<Value> {
+        let inner = self.inner.borrow();
+        let values = inner.iter().map(|(_, value)| value.clone());
+        Ok(Value::List(Rc::new(List::new(values.collect()))))
+    }
+
+    // Returns the removed value.
+    fn remove(&self, arguments: &[Value]) -> anyhow::Result<Value> {
+        let key = &arguments[0];
+        HashKey::new(key.clone())
+            .ok()
+            .and_then(|hash_key| self.inner.borrow_mut().remove(&hash_key))
+            .ok_or_else(|| undefined_key(key))
+    }
+
+    pub(crate) fn write(
+        &self,
+        f: &mut std::fmt::Formatter,
+        open: &mut Vec<*const ()>,
+    ) -> std::fmt::Result {
+        let this = self as *const Map as *const ();
+        if open.contains(&this) {
+            return write!(f, "{{...}}");
+        }
+        open.push(this);
+        write!(f, "{{")?;
+        for (index, (key, value)) in self.inner.borrow().iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            value::write_nested(f, key.value(), open)?;
+            write!(f, ": ")?;
+            value::write_nested(f, value, open)?;
+        }
+        open.pop();
+        write!(f, "}}")
+    }
+}
+
+// Not derived, since that would need `K: Default` and `V: Default`.
+impl<K, V> Default for OrderedMap<K, V> {
+    fn default() -> Self {
+        OrderedMap {
+            entries: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+}
+
+// The same entries in the same order. The indices follow from those.
+impl<K: PartialEq, V: PartialEq> PartialEq for OrderedMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> OrderedMap<K, V> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let index = self.indices.get(key)?;
+        Some(&self.entries[*index].1)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.indices.contains_key(key)
+    }
+
+    /// Replaces the value if the key is already there, keeping its place.
+    pub fn insert(&mut self, key: K, value: V) {
+        match self.indices.get(&key) {
+            Some(index) => self.entries[*index].1 = value,
+            None => {
+                self.indices.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    /// NOTE: keeping the order means shifting every later entry down, so this
+    /// is linear in the size of the map.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let index = self.indices.remove(key)?;
+        let (_, value) = self.entries.remove(index);
+        for later in self.indices.values_mut() {
+            if *later > index {
+                *later -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+pub(crate) fn undefined_key(key: &impl std::fmt::Display) -> anyhow::Error {
+    anyhow::anyhow!("Undefined key '{}'.", key)
+}
+
+// Maps are only equal to themselves, like lists.
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::fmt::Display for Map {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.write(f, &mut Vec::new())
+    }
+}
+
+impl std::fmt::Debug for Map {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(map: &Rc<Map>, name: &str, arguments: &[Value]) -> anyhow::Result<Value> {
+        let Value::Native(method) = map.method(Symbol::intern(name))? else {
+            panic!("{} should be a bound method", name);
+        };
+        method.call(arguments)
+    }
+
+    #[test]
+    fn keys_follow_equality() {
+        let map = Map::new();
+        map.insert(Value::Number(0.0), "zero".into()).unwrap();
+        map.insert(Value::Number(-0.0), "negative zero".into())
+            .unwrap();
+        map.insert("a".into(), Value::Nil).unwrap();
+        map.insert(Value::Bool(true), Value::Nil).unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(
+            map.get(&Value::Number(0.0)).unwrap(),
+            "negative zero".into()
+        );
+        assert!(map.contains_key(&"a".into()));
+        assert!(!map.contains_key(&Value::Number(1.0)));
+        assert!(!map.contains_key(&Value::Nil));
+
+        // Lists are keys by identity, not by their elements.
+        let list = Value::from(vec![1]);
+        map.insert(list.clone(), Value::Nil).unwrap();
+        assert!(map.contains_key(&list));
+        assert!(!map.contains_key(&vec![1].into()));
+
+        assert_eq!(
+            map.insert(Value::Number(f64::NAN), Value::Nil)
+                .unwrap_err()
+                .to_string(),
+            "Map keys can't be NaN."
+        );
+        assert_eq!(
+            map.get(&"b".into()).unwrap_err().to_string(),
+            "Undefined key 'b'."
+        );
+    }
+
+    #[test]
+    fn methods() {
+        let map = Rc::new(Map::new());
+        for (key, value) in [("c", 1), ("a", 2), ("b", 3)] {
+            map.insert(key.into(), value.into()).unwrap();
+        }
+        assert_eq!(call(&map, "keys", &[]).unwrap().to_string(), "[c, a, b]");
+        assert_eq!(
+            call(&map, "remove", &["a".into()]).unwrap(),
+            Value::Number(2.0)
+        );
+        assert_eq!(call(&map, "values", &[]).unwrap().to_string(), "[1, 3]");
+        // The indices of later keys moved down.
+        assert_eq!(map.get(&"b".into()).unwrap(), Value::Number(3.0));
+        assert_eq!(
+            call(&map, "has", &["a".into()]).unwrap(),
+            Value::Bool(false)
+        );
+        assert_eq!(call(&map, "len", &[]).unwrap(), Value::Number(2.0));
+        assert!(call(&map, "remove", &["a".into()]).is_err());
+    }
+
+    #[test]
+    fn printing() {
+        let map = Rc::new(Map::new());
+        assert_eq!(map.to_string(), "{}");
+        map.insert("list".into(), vec![1, 2].into()).unwrap();
+        map.insert(Value::Number(1.0), Value::Map(map.clone()))
+            .unwrap();
+        assert_eq!(map.to_string(), "{list: [1, 2], 1: {...}}");
+    }
+}
@@ -0,0 +1,435 @@
+use crate::symbol::Symbol;
+use crate::value::Value;
+use std::any::Any;
+use std::cell::Ref;
+use std::cell::RefCell;
+use std::cell::RefMut;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+/// What a native function runs. Errors are bare messages, like operator
+/// errors, since only the caller knows the line.
+pub type NativeFn = dyn Fn(&[Value]) -> anyhow::Result<Value>;
+
+/// A function implemented in Rust, callable from Lox.
+pub struct NativeFunction {
+    name: Symbol,
+    arity: u8,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: u8,
+        function: impl Fn(&[Value]) -> anyhow::Result<Value> + 'static,
+    ) -> Self {
+        NativeFunction {
+            name: Symbol::intern(name),
+            arity,
+            function: Box::new(function),
+        }
+    }
+
+    pub fn name(&self) -> Symbol {
+        self.name
+    }
+
+    pub fn arity(&self) -> u8 {
+        self.arity
+    }
+
+    /// Checks the number of arguments before running the function, so it
+    /// can index `arguments` freely.
+    pub fn call(&self, arguments: &[Value]) -> anyhow::Result<Value> {
+        check_arity(self.arity, arguments)?;
+        (self.function)(arguments)
+    }
+}
+
+// Functions are only equal to themselves.
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+/// Natives grouped under one global, like `Math.sqrt`. Members are read with
+/// property access, and can't be assigned to.
+pub struct Namespace {
+    name: Symbol,
+    members: HashMap<Symbol, Value>,
+}
+
+impl Namespace {
+    pub fn new(name: &str) -> Self {
+        Namespace {
+            name: Symbol::intern(name),
+            members: HashMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> Symbol {
+        self.name
+    }
+
+    pub fn function(
+        &mut self,
+        name: &str,
+        arity: u8,
+        function: impl Fn(&[Value]) -> anyhow::Result<Value> + 'static,
+    ) -> &mut Self {
+        // Named after the namespace too, so error messages and printing say
+        // which one it is.
+        let native = NativeFunction::new(&format!("{}.{}", self.name, name), arity, function);
+        self.constant(name, Value::Native(Rc::new(native)))
+    }
+
+    pub fn constant(&mut self, name: &str, value: Value) -> &mut Self {
+        self.members.insert(Symbol::intern(name), value);
+        self
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<Value> {
+        self.members.get(&name).cloned()
+    }
+}
+
+impl PartialEq for Namespace {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::fmt::Debug for Namespace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<namespace {}>", self.name)
+    }
+}
+
+// What a class's methods and getters run, with the instance's data still
+// type-erased. `ClassBuilder` wraps the typed closures the host gives it.
+type Constructor = dyn Fn(&[Value]) -> anyhow::Result<Box<dyn Any>>;
+type Method = dyn Fn(&mut dyn Any, &[Value]) -> anyhow::Result<Value>;
+type Getter = dyn Fn(&dyn Any) -> Value;
+
+/// A Rust type exposed to Lox as a class. Calling the class runs its
+/// constructor, which makes the Rust value behind a `NativeInstance`, and
+/// the instance's methods and properties are closures over that value.
+///
+/// Built with `NativeClass::builder`, then registered with
+/// `NativeRegistry::register_class`.
+pub struct NativeClass {
+    name: Symbol,
+    arity: u8,
+    constructor: Box<Constructor>,
+    methods: HashMap<Symbol, (u8, Rc<Method>)>,
+    // NOTE: properties are read-only, since there's no syntax to assign to
+    // one yet.
+    properties: HashMap<Symbol, Box<Getter>>,
+}
+
+impl NativeClass {
+    /// Starts a class whose instances hold a `T`, made by `constructor` when
+    /// the class is called with `arity` arguments.
+    pub fn builder<T: 'static>(
+        name: &str,
+        arity: u8,
+        constructor: impl Fn(&[Value]) -> anyhow::Result<T> + 'static,
+    ) -> ClassBuilder<T> {
+        ClassBuilder {
+            class: NativeClass {
+                name: Symbol::intern(name),
+                arity,
+                constructor: Box::new(move |arguments| Ok(Box::new(constructor(arguments)?))),
+                methods: HashMap::new(),
+                properties: HashMap::new(),
+            },
+            data: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> Symbol {
+        self.name
+    }
+
+    /// Makes a new instance, like calling the class from Lox does.
+    pub fn construct(self: &Rc<Self>, arguments: &[Value]) -> anyhow::Result<NativeInstance> {
+        check_arity(self.arity, arguments)?;
+        Ok(NativeInstance {
+            class: self.clone(),
+            data: RefCell::new((self.constructor)(arguments)?),
+        })
+    }
+}
+
+impl PartialEq for NativeClass {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::fmt::Debug for NativeClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Declares a `NativeClass` whose instances hold a `T`.
+pub struct ClassBuilder<T> {
+    class: NativeClass,
+    data: PhantomData<T>,
+}
+
+impl<T: 'static> ClassBuilder<T> {
+    /// A method, which gets the instance's data along with its arguments.
+    pub fn method(
+        mut self,
+        name: &str,
+        arity: u8,
+        method: impl Fn(&mut T, &[Value]) -> anyhow::Result<Value> + 'static,
+    ) -> Self {
+        let method: Rc<Method> =
+            Rc::new(move |data, arguments| method(downcast_mut(data), arguments));
+        self.class
+            .methods
+            .insert(Symbol::intern(name), (arity, method));
+        self
+    }
+
+    /// A property, read by calling `getter` on the instance's data.
+    pub fn property(mut self, name: &str, getter: impl Fn(&T) -> Value + 'static) -> Self {
+        let getter: Box<Getter> = Box::new(move |data| getter(downcast_ref(data)));
+        self.class.properties.insert(Symbol::intern(name), getter);
+        self
+    }
+
+    pub fn build(self) -> NativeClass {
+        self.class
+    }
+}
+
+// Instances are only ever made by their class's constructor, which boxes the
+// class's type.
+fn downcast_ref<T: 'static>(data: &dyn Any) -> &T {
+    data.downcast_ref()
+        .expect("instance data should have its class's type")
+}
+
+fn downcast_mut<T: 'static>(data: &mut dyn Any) -> &mut T {
+    data.downcast_mut()
+        .expect("instance data should have its class's type")
+}
+
+/// An instance of a `NativeClass`, holding the Rust value its constructor
+/// made.
+pub struct NativeInstance {
+    class: Rc<NativeClass>,
+    data: RefCell<Box<dyn Any>>,
+}
+
+impl NativeInstance {
+    pub fn class(&self) -> &Rc<NativeClass> {
+        &self.class
+    }
+
+    /// The Rust value behind the instance, if it's a `T`. For hosts to read
+    /// what a script did to it.
+    pub fn data<T: 'static>(&self) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.data.borrow(), |data| data.downcast_ref()).ok()
+    }
+
+    /// Reads a property, or binds a method to this instance.
+    pub fn get(self: &Rc<Self>, name: Symbol) -> anyhow::Result<Value> {
+        if let Some(getter) = self.class.properties.get(&name) {
+            return Ok(getter(&**self.borrow_data()?));
+        }
+        let Some((arity, method)) = self.class.methods.get(&name) else {
+            anyhow::bail!("Undefined property '{}'.", name);
+        };
+        let instance = self.clone();
+        let method = method.clone();
+        let bound = NativeFunction::new(
+            &format!("{}.{}", self.class.name, name),
+            *arity,
+            move |arguments| method(&mut **instance.borrow_data_mut()?, arguments),
+        );
+        Ok(Value::Native(Rc::new(bound)))
+    }
+
+    // A method could be passed its own instance, or one that's busy further
+    // up the stack, so borrowing can fail.
+    fn borrow_data(&self) -> anyhow::Result<Ref<'_, Box<dyn Any>>> {
+        self.data.try_borrow().map_err(|_| self.in_use())
+    }
+
+    fn borrow_data_mut(&self) -> anyhow::Result<RefMut<'_, Box<dyn Any>>> {
+        self.data.try_borrow_mut().map_err(|_| self.in_use())
+    }
+
+    fn in_use(&self) -> anyhow::Error {
+        anyhow::anyhow!(
+            "Can't use a {} instance while one of its methods is running.",
+            self.class.name
+        )
+    }
+}
+
+impl PartialEq for NativeInstance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::fmt::Debug for NativeInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+fn check_arity(arity: u8, arguments: &[Value]) -> anyhow::Result<()> {
+    if arguments.len() != arity as usize {
+        anyhow::bail!("Expected {} arguments but got {}.", arity, arguments.len());
+    }
+    Ok(())
+}
+
+/// The natives a program can use, by global name. Both backends fall back to
+/// it when a global isn't defined, so a program can shadow a native with its
+/// own variable, but can't assign to one.
+///
+/// `stdlib::core` has the standard library; `new` is empty.
+#[derive(Default, Clone)]
+pub struct NativeRegistry {
+    globals: HashMap<Symbol, Value>,
+}
+
+impl NativeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces any native that already has the name.
+    pub fn register(
+        &mut self,
+        name: &str,
+        arity: u8,
+        function: impl Fn(&[Value]) -> anyhow::Result<Value> + 'static,
+    ) {
+        let native = NativeFunction::new(name, arity, function);
+        self.globals
+            .insert(native.name(), Value::Native(Rc::new(native)));
+    }
+
+    pub fn register_namespace(&mut self, namespace: Namespace) {
+        self.globals
+            .insert(namespace.name(), Value::Namespace(Rc::new(namespace)));
+    }
+
+    pub fn register_class(&mut self, class: NativeClass) {
+        self.globals
+            .insert(class.name(), Value::NativeClass(Rc::new(class)));
+    }
+
+    pub fn get(&self, name: Symbol) -> Option<Value> {
+        self.globals.get(&name).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry() {
+        let mut natives = NativeRegistry::new();
+        natives.register("twice", 1, |arguments| match arguments[0] {
+            Value::Number(number) => Ok(Value::Number(number * 2.0)),
+            _ => anyhow::bail!("Expected a number."),
+        });
+        let mut namespace = Namespace::new("Consts");
+        namespace.constant("one", Value::Number(1.0));
+        natives.register_namespace(namespace);
+
+        let Some(Value::Native(twice)) = natives.get(Symbol::intern("twice")) else {
+            panic!("twice should be registered");
+        };
+        assert_eq!(
+            twice.call(&[Value::Number(2.0)]).unwrap(),
+            Value::Number(4.0)
+        );
+        assert_eq!(
+            twice.call(&[]).unwrap_err().to_string(),
+            "Expected 1 arguments but got 0."
+        );
+
+        let Some(Value::Namespace(consts)) = natives.get(Symbol::intern("Consts")) else {
+            panic!("Consts should be registered");
+        };
+        assert_eq!(consts.get(Symbol::intern("one")), Some(Value::Number(1.0)));
+        assert_eq!(natives.get(Symbol::intern("missing")), None);
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Counter {
+        count: f64,
+    }
+
+    fn counter_class() -> Rc<NativeClass> {
+        let class = NativeClass::builder("Counter", 1, |arguments| match arguments[0] {
+            Value::Number(count) => Ok(Counter { count }),
+            _ => anyhow::bail!("Expected a number."),
+        })
+        .method("add", 1, |counter: &mut Counter, arguments| {
+            let Value::Number(amount) = arguments[0] else {
+                anyhow::bail!("Expected a number.");
+            };
+            counter.count += amount;
+            Ok(Value::Nil)
+        })
+        .property("count", |counter| Value::Number(counter.count))
+        .build();
+        Rc::new(class)
+    }
+
+    #[test]
+    fn classes() {
+        let class = counter_class();
+        let instance = Rc::new(class.construct(&[Value::Number(1.0)]).unwrap());
+        let Value::Native(add) = instance.get(Symbol::intern("add")).unwrap() else {
+            panic!("add should be a bound method");
+        };
+        assert_eq!(format!("{:?}", add), "<native fn Counter.add>");
+        add.call(&[Value::Number(2.0)]).unwrap();
+        assert_eq!(
+            instance.get(Symbol::intern("count")).unwrap(),
+            Value::Number(3.0)
+        );
+        assert_eq!(*instance.data::<Counter>().unwrap(), Counter { count: 3.0 });
+        assert!(instance.data::<String>().is_none());
+
+        assert_eq!(
+            instance
+                .get(Symbol::intern("nope"))
+                .unwrap_err()
+                .to_string(),
+            "Undefined property 'nope'."
+        );
+        assert_eq!(
+            class.construct(&[]).unwrap_err().to_string(),
+            "Expected 1 arguments but got 0."
+        );
+        assert_eq!(
+            class.construct(&[Value::Nil]).unwrap_err().to_string(),
+            "Expected a number."
+        );
+        assert_eq!(format!("{:?}", instance), "Counter instance");
+    }
+}
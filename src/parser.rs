@@ -1,104 +1,751 @@
+use crate::ast_print::token_to_string;
 use crate::expr;
 use crate::scanner::AnnotatedToken;
 use crate::scanner::Token;
+use crate::stmt::Stmt;
+use crate::stmt::StmtId;
+use crate::symbol::Symbol;
+use expr::Ast;
+use expr::BinaryOp;
 use expr::Expr;
+use expr::LogicalOp;
+use expr::NodeId;
+use expr::UnaryOp;
+use std::collections::HashMap;
+use std::ops::Range;
 
-pub struct Parser<'a> {
+/// The most arguments a call can have, since the VM's `Call` instruction
+/// counts them in a byte.
+pub const MAX_ARGUMENTS: usize = 255;
+
+/// The most elements a list literal can have, since `BuildList` counts them in
+/// a byte too. Longer lists can still be built with `push`.
+pub const MAX_LIST_ELEMENTS: usize = 255;
+
+/// The most entries a map literal can have, for the same reason.
+pub const MAX_MAP_ENTRIES: usize = 255;
+
+/// The most parameters a function can have, so that any function can be
+/// called.
+pub const MAX_PARAMETERS: usize = MAX_ARGUMENTS;
+
+pub struct Parser<'a, 'ast> {
     // TODO: actually this would probably be some iterator of tokens.
     tokens: &'a Vec<AnnotatedToken<'a>>,
     current_index: usize,
+    // Parsed nodes are appended to this arena.
+    ast: &'ast mut Ast,
+    // Errors we could recover from, reported once parsing is done.
+    errors: Vec<String>,
+    statement_tokens: HashMap<StmtId, Range<usize>>,
+    // How many loops the statement being parsed is inside of, so that
+    // `break` and `continue` outside of one can be reported.
+    loop_depth: usize,
+    // Likewise for functions and `return`.
+    function_depth: usize,
 }
 
-impl<'a> Parser<'a> {
+impl<'a, 'ast> Parser<'a, 'ast> {
     // TODO: should we require that tokens ends with EOF?
     // TODO: this should be a stream, I guess
-    pub fn new(tokens: &'a Vec<AnnotatedToken>) -> Self {
+    pub fn new(tokens: &'a Vec<AnnotatedToken>, ast: &'ast mut Ast) -> Self {
         Parser {
             tokens,
             current_index: 0,
+            ast,
+            errors: Vec::new(),
+            statement_tokens: HashMap::new(),
+            loop_depth: 0,
+            function_depth: 0,
+        }
+    }
+
+    /// Parses a single expression.
+    pub fn parse(&mut self) -> anyhow::Result<NodeId> {
+        match self.expression() {
+            Ok(expr) if self.errors.is_empty() => Ok(expr),
+            Ok(_) => anyhow::bail!(self.errors.join("\n")),
+            Err(err) => {
+                self.errors.push(err.to_string());
+                anyhow::bail!(self.errors.join("\n"))
+            }
+        }
+    }
+
+    /// Parses declarations up until EOF, returning the top-level ones.
+    pub fn parse_program(&mut self) -> anyhow::Result<Vec<StmtId>> {
+        let mut statements = Vec::new();
+        while !self.at_end() {
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
+            }
+        }
+
+        if !self.errors.is_empty() {
+            anyhow::bail!(self.errors.join("\n"));
         }
+        Ok(statements)
     }
 
-    pub fn parse(&mut self) -> anyhow::Result<Box<expr::Expr<'a>>> {
-        self.expression()
+    /// Index of the next token that hasn't been consumed yet.
+    pub fn position(&self) -> usize {
+        self.current_index
+    }
+
+    /// The tokens each parsed statement was made from, as indices into the
+    /// token list.
+    pub fn statement_tokens(&self) -> &HashMap<StmtId, Range<usize>> {
+        &self.statement_tokens
     }
 
     // Recursive descent parsing
-    fn expression(&mut self) -> anyhow::Result<Box<expr::Expr<'a>>> {
-        self.equality()
+
+    // varDecl | statement
+    // Errors are recorded, and we skip ahead to the next statement so that we
+    // can keep reporting errors after this one.
+    fn declaration(&mut self) -> Option<StmtId> {
+        let result = if self.matches(|token| matches!(token, Token::Var)) {
+            self.var_declaration()
+        } else {
+            self.statement()
+        };
+
+        match result {
+            Ok(stmt) => Some(stmt),
+            Err(err) => {
+                self.errors.push(err.to_string());
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    // "var" IDENTIFIER ( "=" expression )? ";"
+    fn var_declaration(&mut self) -> anyhow::Result<StmtId> {
+        let start = self.current_index;
+        self.advance();
+        let (name, line_number) = self.consume_identifier("Expect variable name.")?;
+        let initializer = if self.matches(|token| matches!(token, Token::Equal)) {
+            self.advance();
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(
+            |token| matches!(token, Token::Semicolon),
+            "Expect ';' after variable declaration.",
+        )?;
+
+        Ok(self.add_stmt(
+            start,
+            Stmt::Var {
+                name,
+                line_number,
+                initializer,
+            },
+        ))
+    }
+
+    // exprStmt | forStmt | ifStmt | printStmt | returnStmt | whileStmt
+    // | breakStmt | continueStmt | block
+    fn statement(&mut self) -> anyhow::Result<StmtId> {
+        match self.peek().token {
+            Token::Break | Token::Continue => self.jump_statement(),
+            Token::For => self.for_statement(),
+            Token::If => self.if_statement(),
+            Token::Print => self.print_statement(),
+            Token::Return => self.return_statement(),
+            Token::While => self.while_statement(),
+            Token::LeftBrace => self.block(),
+            _ => self.expression_statement(),
+        }
+    }
+
+    // "for" "(" ( varDecl | exprStmt | ";" ) expression? ";" expression? ")"
+    // statement
+    fn for_statement(&mut self) -> anyhow::Result<StmtId> {
+        let start = self.current_index;
+        self.advance();
+        self.consume(
+            |token| matches!(token, Token::LeftParen),
+            "Expect '(' after 'for'.",
+        )?;
+
+        let initializer = match self.peek().token {
+            Token::Semicolon => {
+                self.advance();
+                None
+            }
+            Token::Var => Some(self.var_declaration()?),
+            _ => Some(self.expression_statement()?),
+        };
+        let condition = if self.matches(|token| matches!(token, Token::Semicolon)) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(
+            |token| matches!(token, Token::Semicolon),
+            "Expect ';' after loop condition.",
+        )?;
+        let increment = if self.matches(|token| matches!(token, Token::RightParen)) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(
+            |token| matches!(token, Token::RightParen),
+            "Expect ')' after for clauses.",
+        )?;
+        let body = self.loop_body()?;
+
+        Ok(self.add_stmt(
+            start,
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            },
+        ))
+    }
+
+    // "if" "(" expression ")" statement ( "else" statement )?
+    fn if_statement(&mut self) -> anyhow::Result<StmtId> {
+        let start = self.current_index;
+        self.advance();
+        self.consume(
+            |token| matches!(token, Token::LeftParen),
+            "Expect '(' after 'if'.",
+        )?;
+        let condition = self.expression()?;
+        self.consume(
+            |token| matches!(token, Token::RightParen),
+            "Expect ')' after if condition.",
+        )?;
+
+        let then_branch = self.statement()?;
+        // A dangling `else` binds to the nearest `if`.
+        let else_branch = if self.matches(|token| matches!(token, Token::Else)) {
+            self.advance();
+            Some(self.statement()?)
+        } else {
+            None
+        };
+
+        Ok(self.add_stmt(
+            start,
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            },
+        ))
+    }
+
+    // "print" expression ";"
+    fn print_statement(&mut self) -> anyhow::Result<StmtId> {
+        let start = self.current_index;
+        let line_number = self.peek().line_number;
+        self.advance();
+        let expr = self.expression()?;
+        self.consume(
+            |token| matches!(token, Token::Semicolon),
+            "Expect ';' after value.",
+        )?;
+        Ok(self.add_stmt(start, Stmt::Print { expr, line_number }))
+    }
+
+    // "return" expression? ";"
+    fn return_statement(&mut self) -> anyhow::Result<StmtId> {
+        let start = self.current_index;
+        let line_number = self.peek().line_number;
+        if self.function_depth == 0 {
+            // Like `break`, no need to synchronize.
+            let error = self.error("Can't return from top-level code.");
+            self.errors.push(error.to_string());
+        }
+        self.advance();
+        let value = if self.matches(|token| matches!(token, Token::Semicolon)) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(
+            |token| matches!(token, Token::Semicolon),
+            "Expect ';' after return value.",
+        )?;
+        Ok(self.add_stmt(start, Stmt::Return { value, line_number }))
+    }
+
+    // "while" "(" expression ")" statement
+    fn while_statement(&mut self) -> anyhow::Result<StmtId> {
+        let start = self.current_index;
+        let line_number = self.peek().line_number;
+        self.advance();
+        self.consume(
+            |token| matches!(token, Token::LeftParen),
+            "Expect '(' after 'while'.",
+        )?;
+        let condition = self.expression()?;
+        self.consume(
+            |token| matches!(token, Token::RightParen),
+            "Expect ')' after condition.",
+        )?;
+        let body = self.loop_body()?;
+        Ok(self.add_stmt(
+            start,
+            Stmt::While {
+                condition,
+                body,
+                line_number,
+            },
+        ))
+    }
+
+    fn loop_body(&mut self) -> anyhow::Result<StmtId> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    // ( "break" | "continue" ) ";"
+    fn jump_statement(&mut self) -> anyhow::Result<StmtId> {
+        let start = self.current_index;
+        let annotated_token = self.peek();
+        let keyword = token_to_string(&annotated_token.token);
+        if self.loop_depth == 0 {
+            // The statement itself parses fine, so there's no need to
+            // synchronize.
+            let error = self.error(&format!("Can't use '{}' outside of a loop.", keyword));
+            self.errors.push(error.to_string());
+        }
+        self.advance();
+        let line_number = annotated_token.line_number;
+        let stmt = if annotated_token.token == Token::Break {
+            Stmt::Break { line_number }
+        } else {
+            Stmt::Continue { line_number }
+        };
+        self.consume(
+            |token| matches!(token, Token::Semicolon),
+            &format!("Expect ';' after '{}'.", keyword),
+        )?;
+        Ok(self.add_stmt(start, stmt))
+    }
+
+    // "{" declaration* "}"
+    fn block(&mut self) -> anyhow::Result<StmtId> {
+        let start = self.current_index;
+        self.advance();
+        let mut statements = Vec::new();
+        while !self.matches(|token| matches!(token, Token::RightBrace)) && !self.at_end() {
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
+            }
+        }
+        self.consume(
+            |token| matches!(token, Token::RightBrace),
+            "Expect '}' after block.",
+        )?;
+        Ok(self.add_stmt(start, Stmt::Block(statements)))
+    }
+
+    // expression ";"
+    fn expression_statement(&mut self) -> anyhow::Result<StmtId> {
+        let start = self.current_index;
+        let line_number = self.peek().line_number;
+        let expr = self.expression()?;
+        self.consume(
+            |token| matches!(token, Token::Semicolon),
+            "Expect ';' after expression.",
+        )?;
+        Ok(self.add_stmt(start, Stmt::Expression { expr, line_number }))
+    }
+
+    fn expression(&mut self) -> anyhow::Result<NodeId> {
+        self.comma()
+    }
+
+    // assignment ( "," assignment )*
+    // NOTE: left-associative
+    fn comma(&mut self) -> anyhow::Result<NodeId> {
+        self.subrule_operator_subrule_left_associative_helper(Self::assignment, |op| {
+            matches!(op, BinaryOp::Comma)
+        })
+    }
+
+    // ( IDENTIFIER | call "[" expression "]" ) "=" assignment | conditional
+    // NOTE: RIGHT associative
+    fn assignment(&mut self) -> anyhow::Result<NodeId> {
+        let target = self.conditional()?;
+        if !self.matches(|token| matches!(token, Token::Equal)) {
+            return Ok(target);
+        }
+        let equals = self.error("Invalid assignment target.");
+        self.advance();
+        let value = self.assignment()?;
+
+        match &self.ast[target] {
+            Expr::Variable { name, line_number } => {
+                let assign = Expr::Assign {
+                    name: *name,
+                    line_number: *line_number,
+                    value,
+                };
+                Ok(self.ast.add(assign))
+            }
+            Expr::Index {
+                object,
+                index,
+                line_number,
+            } => {
+                // The `Index` node is left behind in the arena, like the
+                // variable above.
+                let set_index = Expr::SetIndex {
+                    object: *object,
+                    index: *index,
+                    value,
+                    line_number: *line_number,
+                };
+                Ok(self.ast.add(set_index))
+            }
+            // No need to synchronize, we know exactly where we are.
+            _ => {
+                self.errors.push(equals.to_string());
+                Ok(target)
+            }
+        }
+    }
+
+    // logic_or ( "?" expression ":" conditional )?
+    // NOTE: RIGHT associative
+    fn conditional(&mut self) -> anyhow::Result<NodeId> {
+        let condition = self.logic_or()?;
+        if !self.matches(|token| matches!(token, Token::Question)) {
+            return Ok(condition);
+        }
+        self.advance();
+
+        // Like C, anything goes between the `?` and `:`, even commas.
+        let then_branch = self.expression()?;
+        self.consume(
+            |token| matches!(token, Token::Colon),
+            "Couldn't find ':' of conditional expression.",
+        )?;
+        let else_branch = self.conditional()?;
+
+        Ok(self.ast.add(Expr::Conditional {
+            condition,
+            then_branch,
+            else_branch,
+        }))
+    }
+
+    // logic_and ( "or" logic_and )*
+    fn logic_or(&mut self) -> anyhow::Result<NodeId> {
+        self.logical_left_associative_helper(Self::logic_and, LogicalOp::Or)
+    }
+
+    // equality ( "and" equality )*
+    fn logic_and(&mut self) -> anyhow::Result<NodeId> {
+        self.logical_left_associative_helper(Self::equality, LogicalOp::And)
     }
 
     // comparison ( ( "!=" | "==" ) comparison )*
-    fn equality(&mut self) -> anyhow::Result<Box<expr::Expr<'a>>> {
-        self.subrule_operator_subrule_left_associative_helper(Self::comparison, |token| {
-            matches!(token, Token::BangEqual | Token::EqualEqual)
+    fn equality(&mut self) -> anyhow::Result<NodeId> {
+        self.subrule_operator_subrule_left_associative_helper(Self::comparison, |op| {
+            matches!(op, BinaryOp::NotEqual | BinaryOp::Equal)
         })
     }
 
     // term ( ( ">" | ">=" | "<" | "<=" ) term )*
     // NOTE: left-associative
-    fn comparison(&mut self) -> anyhow::Result<Box<expr::Expr<'a>>> {
-        self.subrule_operator_subrule_left_associative_helper(Self::term, |token| {
+    fn comparison(&mut self) -> anyhow::Result<NodeId> {
+        self.subrule_operator_subrule_left_associative_helper(Self::term, |op| {
             matches!(
-                token,
-                Token::Greater | Token::GreaterEqual | Token::Less | Token::LessEqual
+                op,
+                BinaryOp::Greater | BinaryOp::GreaterEqual | BinaryOp::Less | BinaryOp::LessEqual
             )
         })
     }
 
     // factor ( ( "-" | "+" ) factor )*
     // NOTE: left-associative
-    fn term(&mut self) -> anyhow::Result<Box<expr::Expr<'a>>> {
-        self.subrule_operator_subrule_left_associative_helper(Self::factor, |token| {
-            matches!(token, Token::Minus | Token::Plus)
+    fn term(&mut self) -> anyhow::Result<NodeId> {
+        self.subrule_operator_subrule_left_associative_helper(Self::factor, |op| {
+            matches!(op, BinaryOp::Subtract | BinaryOp::Add)
         })
     }
 
     // unary ( ( "/" | "*" ) unary )*
     // LEFT-associative
-    fn factor(&mut self) -> anyhow::Result<Box<expr::Expr<'a>>> {
-        self.subrule_operator_subrule_left_associative_helper(Self::unary, |token| {
-            matches!(token, Token::Slash | Token::Star)
+    fn factor(&mut self) -> anyhow::Result<NodeId> {
+        self.subrule_operator_subrule_left_associative_helper(Self::unary, |op| {
+            matches!(op, BinaryOp::Divide | BinaryOp::Multiply)
         })
     }
 
     // NOTE: RIGHT associative
-    fn unary(&mut self) -> anyhow::Result<Box<expr::Expr<'a>>> {
-        let annotated_token = self.peek().clone();
-        match annotated_token.token {
-            Token::Plus | Token::Minus => {
+    fn unary(&mut self) -> anyhow::Result<NodeId> {
+        let annotated_token = self.peek();
+        match UnaryOp::from_token(&annotated_token.token) {
+            Some(operator) => {
                 self.advance();
-                return Ok(Box::new(Expr::Unary {
-                    operator: annotated_token,
-                    right: self.unary()?,
-                }));
+                let right = self.unary()?;
+                Ok(self.ast.add(Expr::Unary {
+                    operator,
+                    line_number: annotated_token.line_number,
+                    right,
+                }))
+            }
+            None => self.call(),
+        }
+    }
+
+    // primary ( "(" arguments? ")" | "." IDENTIFIER | "[" expression "]" )*
+    fn call(&mut self) -> anyhow::Result<NodeId> {
+        let mut expr = self.primary()?;
+        loop {
+            match self.peek().token {
+                Token::LeftParen => {
+                    self.advance();
+                    expr = self.finish_call(expr)?;
+                }
+                Token::Dot => {
+                    self.advance();
+                    let (name, line_number) =
+                        self.consume_identifier("Expect property name after '.'.")?;
+                    expr = self.ast.add(Expr::Get {
+                        object: expr,
+                        name,
+                        line_number,
+                    });
+                }
+                Token::LeftBracket => {
+                    self.advance();
+                    let index = self.expression()?;
+                    let line_number = self.peek().line_number;
+                    self.consume(
+                        |token| matches!(token, Token::RightBracket),
+                        "Expect ']' after index.",
+                    )?;
+                    expr = self.ast.add(Expr::Index {
+                        object: expr,
+                        index,
+                        line_number,
+                    });
+                }
+                _ => return Ok(expr),
             }
-            _ => return self.primary(),
         }
     }
 
-    fn primary(&mut self) -> anyhow::Result<Box<expr::Expr<'a>>> {
-        let expr = match &self.peek().token {
-            Token::False => Box::new(expr::Expr::LiteralBool(false)),
-            Token::True => Box::new(expr::Expr::LiteralBool(true)),
-            Token::Nil => Box::new(expr::Expr::Nil),
-            Token::Number { number } => Box::new(expr::Expr::LiteralNumber(number.clone())),
-            Token::String { quoted_str } => Box::new(expr::Expr::LiteralString(quoted_str)),
+    // assignment ( "," assignment )*
+    // NOTE: arguments skip the comma operator, since commas separate them.
+    fn finish_call(&mut self, callee: NodeId) -> anyhow::Result<NodeId> {
+        let mut arguments = Vec::new();
+        if !self.matches(|token| matches!(token, Token::RightParen)) {
+            loop {
+                if arguments.len() == MAX_ARGUMENTS {
+                    // The call still parses fine, so there's no need to
+                    // synchronize.
+                    let error = self.error("Can't have more than 255 arguments.");
+                    self.errors.push(error.to_string());
+                }
+                arguments.push(self.assignment()?);
+                if !self.matches(|token| matches!(token, Token::Comma)) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+
+        let line_number = self.peek().line_number;
+        self.consume(
+            |token| matches!(token, Token::RightParen),
+            "Expect ')' after arguments.",
+        )?;
+        Ok(self.ast.add(Expr::Call {
+            callee,
+            line_number,
+            arguments,
+        }))
+    }
+
+    fn primary(&mut self) -> anyhow::Result<NodeId> {
+        let annotated_token = self.peek();
+        let line_number = annotated_token.line_number;
+        let expr = match &annotated_token.token {
+            Token::False => Expr::LiteralBool {
+                value: false,
+                line_number,
+            },
+            Token::True => Expr::LiteralBool {
+                value: true,
+                line_number,
+            },
+            Token::Nil => Expr::Nil { line_number },
+            Token::Number { number } => Expr::LiteralNumber {
+                value: *number,
+                line_number,
+            },
+            Token::String { quoted_str } => Expr::LiteralString {
+                value: unquote(quoted_str).to_owned(),
+                line_number,
+            },
+            Token::Identifier { identifier } => Expr::Variable {
+                name: Symbol::intern(identifier),
+                line_number,
+            },
             Token::LeftParen => {
                 self.advance();
                 let parens_expr = self.expression()?;
                 if !self.matches(|token| matches!(token, Token::RightParen)) {
-                    anyhow::bail!("Couldn't find closing parens.");
+                    return Err(self.error("Couldn't find closing parens."));
                 }
-                Box::new(expr::Expr::Grouping(parens_expr))
+                Expr::Grouping(parens_expr)
             }
-            _ => {
-                anyhow::bail!("Unexpected token");
+            Token::LeftBracket => {
+                self.advance();
+                Expr::List(self.list_elements()?)
+            }
+            Token::Fun => return self.function(),
+            // Only in an expression: at the start of a statement, a brace is a
+            // block.
+            Token::LeftBrace => {
+                self.advance();
+                Expr::Map(self.map_entries()?)
+            }
+            token => {
+                if let Some(operator) = BinaryOp::from_token(token) {
+                    return self.missing_left_operand(operator);
+                }
+                return Err(self.error("Unexpected token."));
             }
         };
 
         self.advance();
-        Ok(expr)
+        Ok(self.ast.add(expr))
+    }
+
+    // "fun" "(" ( IDENTIFIER ( "," IDENTIFIER )* )? ")" block
+    fn function(&mut self) -> anyhow::Result<NodeId> {
+        let line_number = self.peek().line_number;
+        self.advance();
+        self.consume(
+            |token| matches!(token, Token::LeftParen),
+            "Expect '(' after 'fun'.",
+        )?;
+        let mut params = Vec::new();
+        if !self.matches(|token| matches!(token, Token::RightParen)) {
+            loop {
+                if params.len() == MAX_PARAMETERS {
+                    let error = self.error("Can't have more than 255 parameters.");
+                    self.errors.push(error.to_string());
+                }
+                let (name, _) = self.consume_identifier("Expect parameter name.")?;
+                params.push(name);
+                if !self.matches(|token| matches!(token, Token::Comma)) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.consume(
+            |token| matches!(token, Token::RightParen),
+            "Expect ')' after parameters.",
+        )?;
+        if !self.matches(|token| matches!(token, Token::LeftBrace)) {
+            return Err(self.error("Expect '{' before function body."));
+        }
+
+        // A loop around the function isn't one its body can break out of.
+        let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+        self.function_depth += 1;
+        let body = self.block();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+
+        Ok(self.ast.add(Expr::Function {
+            params,
+            body: body?,
+            line_number,
+        }))
+    }
+
+    // ( assignment ( "," assignment )* )? "]"
+    // NOTE: like arguments, elements skip the comma operator. Leaves the `]`
+    // for `primary` to consume.
+    fn list_elements(&mut self) -> anyhow::Result<Vec<NodeId>> {
+        let mut elements = Vec::new();
+        if !self.matches(|token| matches!(token, Token::RightBracket)) {
+            loop {
+                if elements.len() == MAX_LIST_ELEMENTS {
+                    let error = self.error("Can't have more than 255 elements in a list.");
+                    self.errors.push(error.to_string());
+                }
+                elements.push(self.assignment()?);
+                if !self.matches(|token| matches!(token, Token::Comma)) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        if !self.matches(|token| matches!(token, Token::RightBracket)) {
+            return Err(self.error("Expect ']' after list elements."));
+        }
+        Ok(elements)
+    }
+
+    // ( assignment ":" assignment ( "," assignment ":" assignment )* )? "}"
+    // NOTE: leaves the `}` for `primary` to consume, like `list_elements`.
+    fn map_entries(&mut self) -> anyhow::Result<Vec<(NodeId, NodeId)>> {
+        let mut entries = Vec::new();
+        if !self.matches(|token| matches!(token, Token::RightBrace)) {
+            loop {
+                if entries.len() == MAX_MAP_ENTRIES {
+                    let error = self.error("Can't have more than 255 entries in a map.");
+                    self.errors.push(error.to_string());
+                }
+                let key = self.assignment()?;
+                self.consume(
+                    |token| matches!(token, Token::Colon),
+                    "Expect ':' after map key.",
+                )?;
+                entries.push((key, self.assignment()?));
+                if !self.matches(|token| matches!(token, Token::Comma)) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        if !self.matches(|token| matches!(token, Token::RightBrace)) {
+            return Err(self.error("Expect '}' after map entries."));
+        }
+        Ok(entries)
+    }
+
+    // Error production for a binary operator without a left operand, e.g. `* 3`.
+    // Unary operators never end up here since `unary` already took them.
+    fn missing_left_operand(&mut self, operator: BinaryOp) -> anyhow::Result<NodeId> {
+        let error = self.error("Missing left-hand operand.");
+        self.errors.push(error.to_string());
+        self.advance();
+
+        // Parse the right operand like the operator would have, so that we can
+        // carry on from after it. It's discarded, since parsing fails anyway.
+        let parse_right_operand: fn(&mut Self) -> anyhow::Result<NodeId> = match operator {
+            BinaryOp::Comma => Self::assignment,
+            BinaryOp::Equal | BinaryOp::NotEqual => Self::comparison,
+            BinaryOp::Greater | BinaryOp::GreaterEqual | BinaryOp::Less | BinaryOp::LessEqual => {
+                Self::term
+            }
+            BinaryOp::Add | BinaryOp::Subtract => Self::factor,
+            BinaryOp::Multiply | BinaryOp::Divide => Self::unary,
+        };
+        parse_right_operand(self)
     }
 
     // Helpers
@@ -110,35 +757,127 @@ impl<'a> Parser<'a> {
         pred(&self.peek().token)
     }
 
-    fn peek(&self) -> &AnnotatedToken<'a> {
+    fn peek(&self) -> &'a AnnotatedToken<'a> {
         &self.tokens[self.current_index]
     }
 
+    fn at_end(&self) -> bool {
+        self.peek().token == Token::EOF
+    }
+
+    fn consume(&mut self, pred: fn(&Token) -> bool, message: &str) -> anyhow::Result<()> {
+        if !self.matches(pred) {
+            return Err(self.error(message));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn consume_identifier(&mut self, message: &str) -> anyhow::Result<(Symbol, usize)> {
+        let annotated_token = self.peek();
+        match annotated_token.token {
+            Token::Identifier { identifier } => {
+                self.advance();
+                Ok((Symbol::intern(identifier), annotated_token.line_number))
+            }
+            _ => Err(self.error(message)),
+        }
+    }
+
+    // An error at the current token.
+    fn error(&self, message: &str) -> anyhow::Error {
+        let annotated_token = self.peek();
+        let location = match annotated_token.token {
+            Token::EOF => "end".to_owned(),
+            _ => format!("'{}'", token_to_string(&annotated_token.token)),
+        };
+        anyhow::anyhow!(
+            "[line {}] Error at {}: {}",
+            annotated_token.line_number,
+            location,
+            message
+        )
+    }
+
+    // Skips tokens until what's probably the start of the next statement.
+    fn synchronize(&mut self) {
+        // Always make progress, the current token is what went wrong.
+        if !self.at_end() {
+            self.advance();
+        }
+        while !self.at_end() {
+            if self.tokens[self.current_index - 1].token == Token::Semicolon {
+                return;
+            }
+            match self.peek().token {
+                Token::Class
+                | Token::Fun
+                | Token::Var
+                | Token::For
+                | Token::If
+                | Token::While
+                | Token::Print
+                | Token::Return => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    fn add_stmt(&mut self, start: usize, stmt: Stmt) -> StmtId {
+        let id = self.ast.add_stmt(stmt);
+        self.statement_tokens.insert(id, start..self.current_index);
+        id
+    }
+
+    fn logical_left_associative_helper(
+        &mut self,
+        parse_subrule_fn: fn(&mut Self) -> anyhow::Result<NodeId>,
+        operator: LogicalOp,
+    ) -> anyhow::Result<NodeId> {
+        let mut left_expr = parse_subrule_fn(self)?;
+        while LogicalOp::from_token(&self.peek().token) == Some(operator) {
+            self.advance();
+            let right_expr = parse_subrule_fn(self)?;
+            left_expr = self.ast.add(Expr::Logical {
+                left: left_expr,
+                operator,
+                right: right_expr,
+            });
+        }
+        Ok(left_expr)
+    }
+
     fn subrule_operator_subrule_left_associative_helper(
         &mut self,
-        parse_subrule_fn: fn(&mut Self) -> anyhow::Result<Box<expr::Expr<'a>>>,
-        operator_pred: fn(&Token) -> bool,
-    ) -> anyhow::Result<Box<expr::Expr<'a>>> {
+        parse_subrule_fn: fn(&mut Self) -> anyhow::Result<NodeId>,
+        operator_pred: fn(&BinaryOp) -> bool,
+    ) -> anyhow::Result<NodeId> {
         let mut left_expr = parse_subrule_fn(self)?;
         loop {
-            let possible_operator = self.peek().clone();
-            if operator_pred(&possible_operator.token) {
-                self.advance();
-                let right_expr = parse_subrule_fn(self)?;
-                left_expr = Box::new(Expr::Binary {
-                    left: left_expr,
-                    operator: possible_operator,
-                    right: right_expr,
-                });
-            } else {
-                return Ok(left_expr);
+            let possible_operator = self.peek();
+            match BinaryOp::from_token(&possible_operator.token) {
+                Some(operator) if operator_pred(&operator) => {
+                    self.advance();
+                    let right_expr = parse_subrule_fn(self)?;
+                    left_expr = self.ast.add(Expr::Binary {
+                        left: left_expr,
+                        operator,
+                        line_number: possible_operator.line_number,
+                        right: right_expr,
+                    });
+                }
+                _ => return Ok(left_expr),
             }
         }
     }
+}
 
-    fn at_end(&self) -> bool {
-        self.peek().token == Token::EOF
-    }
+// The scanner keeps the quotation marks around string lexemes.
+fn unquote(quoted_str: &str) -> &str {
+    quoted_str
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(quoted_str)
 }
 
 #[cfg(test)]
@@ -151,9 +890,10 @@ mod tests {
     fn test_with_ast(source: &'static str, expected_ast: &'static str) {
         let mut scanner = scanner::Scanner::new(source);
         let tokens = scanner.scan_tokens();
-        let mut parser = Parser::new(tokens);
+        let mut ast = Ast::new();
+        let mut parser = Parser::new(tokens, &mut ast);
         let expr = parser.parse().unwrap();
-        assert_eq!(ast_print::AstPrinter.visit_expr(&expr), expected_ast);
+        assert_eq!(ast_print::AstPrinter.visit_expr(&ast, expr), expected_ast);
     }
 
     #[test]
@@ -191,8 +931,397 @@ mod tests {
         test_with_ast("(12 + 23) * (3 - 4)", "(* ((+ 12 23)) ((- 3 4)))")
     }
 
+    #[test]
+    fn operator_line_numbers() {
+        let mut scanner = scanner::Scanner::new("1\n+\n-2");
+        let tokens = scanner.scan_tokens();
+        let mut ast = Ast::new();
+        let mut parser = Parser::new(tokens, &mut ast);
+        let expr = parser.parse().unwrap();
+        let Expr::Binary {
+            operator,
+            line_number,
+            right,
+            ..
+        } = ast[expr]
+        else {
+            panic!("expected binary expression");
+        };
+        assert_eq!(operator, BinaryOp::Add);
+        assert_eq!(line_number, 2);
+        assert!(matches!(
+            ast[right],
+            Expr::Unary {
+                operator: UnaryOp::Minus,
+                line_number: 3,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn side_table_by_node_id() {
+        let mut scanner = scanner::Scanner::new("(1 + 2) * -3");
+        let tokens = scanner.scan_tokens();
+        let mut ast = Ast::new();
+        let root = Parser::new(tokens, &mut ast).parse().unwrap();
+        assert_eq!(ast.len(), 7);
+        // The root is the last node allocated, after all of its children.
+        assert_eq!(ast.iter().last().unwrap().0, root);
+
+        // Count the nodes under each node without touching the tree itself.
+        let mut sizes: std::collections::HashMap<NodeId, usize> = Default::default();
+        for (id, expr) in ast.iter() {
+            let children_size = match expr {
+                Expr::Binary { left, right, .. } => sizes[left] + sizes[right],
+                Expr::Grouping(inner) | Expr::Unary { right: inner, .. } => sizes[inner],
+                _ => 0,
+            };
+            sizes.insert(id, children_size + 1);
+        }
+        assert_eq!(sizes[&root], 7);
+    }
+
+    #[test]
+    fn parse_into_shared_ast() {
+        let mut ast = Ast::new();
+        let mut first_scanner = scanner::Scanner::new("1 + 2");
+        let first = Parser::new(first_scanner.scan_tokens(), &mut ast)
+            .parse()
+            .unwrap();
+        let mut second_scanner = scanner::Scanner::new("3");
+        let second = Parser::new(second_scanner.scan_tokens(), &mut ast)
+            .parse()
+            .unwrap();
+        assert_ne!(first, second);
+        assert_eq!(ast_print::AstPrinter.visit_expr(&ast, first), "(+ 1 2)");
+        assert_eq!(ast_print::AstPrinter.visit_expr(&ast, second), "3");
+    }
+
+    #[test]
+    fn ast_outlives_source() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let mut ast = Ast::new();
+        let root = {
+            let source = String::from("\"dropped\" == \"source\"");
+            let mut scanner = scanner::Scanner::new(&source);
+            Parser::new(scanner.scan_tokens(), &mut ast).parse().unwrap()
+        };
+        assert_send(&ast);
+        let printed = std::thread::spawn(move || ast_print::AstPrinter.visit_expr(&ast, root))
+            .join()
+            .unwrap();
+        assert_eq!(printed, "(== \"dropped\" \"source\")");
+    }
+
+    #[test]
+    fn conditional() {
+        test_with_ast("1 == 2 ? 3 : 4", "(?: (== 1 2) 3 4)");
+    }
+
+    #[test]
+    fn conditional_right_associativity() {
+        test_with_ast("1 ? 2 : 3 ? 4 : 5", "(?: 1 2 (?: 3 4 5))");
+        test_with_ast("1 ? 2 ? 3 : 4 : 5", "(?: 1 (?: 2 3 4) 5)");
+    }
+
+    #[test]
+    fn comma() {
+        test_with_ast("1, 2 + 3, 4", "(, (, 1 (+ 2 3)) 4)");
+        test_with_ast("1 ? 2, 3 : 4, 5", "(, (?: 1 (, 2 3) 4) 5)");
+    }
+
+    #[test]
+    fn conditional_missing_colon() {
+        let mut scanner = scanner::Scanner::new("1 ? 2");
+        let tokens = scanner.scan_tokens();
+        let mut ast = Ast::new();
+        assert!(Parser::new(tokens, &mut ast).parse().is_err());
+    }
+
+    fn test_parse_error(source: &'static str, expected_error: &'static str) {
+        let mut scanner = scanner::Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        let mut ast = Ast::new();
+        let error = Parser::new(tokens, &mut ast).parse().unwrap_err();
+        assert_eq!(error.to_string(), expected_error);
+    }
+
+    #[test]
+    fn missing_left_operand() {
+        test_parse_error("* 3", "[line 1] Error at '*': Missing left-hand operand.");
+        test_parse_error("1 +\n== 2", "[line 2] Error at '==': Missing left-hand operand.");
+        test_parse_error(", 2", "[line 1] Error at ',': Missing left-hand operand.");
+    }
+
+    #[test]
+    fn missing_left_operand_keeps_parsing() {
+        // Both errors are reported, so parsing continued after the first one.
+        test_parse_error(
+            "<= 1 + 2 == (/ 3)",
+            "[line 1] Error at '<=': Missing left-hand operand.\n\
+             [line 1] Error at '/': Missing left-hand operand.",
+        );
+        // Errors that can't be recovered from still stop parsing.
+        test_parse_error(
+            "!= 1 +",
+            "[line 1] Error at '!=': Missing left-hand operand.\n\
+             [line 1] Error at end: Unexpected token.",
+        );
+    }
+
     #[test]
     fn strings() {
         test_with_ast("\"str\" + \"otherstr\"", "(+ \"str\" \"otherstr\")")
     }
+
+    fn test_with_program(source: &'static str, expected_statements: &[&str]) {
+        use crate::stmt::Visitor;
+        let mut scanner = scanner::Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        let mut ast = Ast::new();
+        let statements = Parser::new(tokens, &mut ast).parse_program().unwrap();
+        let printed: Vec<_> = statements
+            .iter()
+            .map(|stmt| ast_print::AstPrinter.visit_stmt(&ast, *stmt))
+            .collect();
+        assert_eq!(printed, expected_statements);
+    }
+
+    fn test_program_error(source: &'static str, expected_error: &'static str) {
+        let mut scanner = scanner::Scanner::new(source);
+        let tokens = scanner.scan_tokens();
+        let mut ast = Ast::new();
+        let error = Parser::new(tokens, &mut ast).parse_program().unwrap_err();
+        assert_eq!(error.to_string(), expected_error);
+    }
+
+    #[test]
+    fn assignment_and_logical() {
+        test_with_ast("a = b = c", "(= a (= b c))");
+        test_with_ast("a = 1, b", "(, (= a 1) b)");
+        test_with_ast("a or b and c or d", "(or (or a (and b c)) d)");
+        test_with_ast("a ? b : c or d", "(?: a b (or c d))");
+    }
+
+    #[test]
+    fn statements() {
+        test_with_program(
+            "var a; var b = 1; print a; a;",
+            &["(var a)", "(var b 1)", "(print a)", "(; a)"],
+        );
+        test_with_program(
+            "{ var a = 1; { print a; } }",
+            &["(block (var a 1) (block (print a)))"],
+        );
+        test_with_program(
+            "while (a) if (b) print 1; else if (c) print 2;",
+            &["(while a (if b (print 1) (if c (print 2))))"],
+        );
+        test_with_program(
+            "for (var i = 0; i < 3; i = i + 1) print i; for (;;) {}",
+            &[
+                "(for (var i 0) (< i 3) (= i (+ i 1)) (print i))",
+                "(for _ _ _ (block))",
+            ],
+        );
+    }
+
+    #[test]
+    fn break_and_continue() {
+        test_with_program(
+            "while (a) { if (b) break; continue; } for (;;) break;",
+            &[
+                "(while a (block (if b (break)) (continue)))",
+                "(for _ _ _ (break))",
+            ],
+        );
+        test_program_error(
+            "break;\nif (a) { continue; }",
+            "[line 1] Error at 'break': Can't use 'break' outside of a loop.\n\
+             [line 2] Error at 'continue': Can't use 'continue' outside of a loop.",
+        );
+        test_program_error(
+            "while (a) break",
+            "[line 1] Error at end: Expect ';' after 'break'.",
+        );
+        // The loop is over once its body is, so this is outside of it again.
+        test_program_error(
+            "while (a) {} break;",
+            "[line 1] Error at 'break': Can't use 'break' outside of a loop.",
+        );
+    }
+
+    #[test]
+    fn functions() {
+        test_with_program(
+            "var f = fun (a, b) { return a; }; fun () { return; }();",
+            &[
+                "(var f (fun (a b) (return a)))",
+                "(; (call (fun () (return))))",
+            ],
+        );
+        test_program_error(
+            "return 1;\nfun () { return 2 };",
+            "[line 1] Error at 'return': Can't return from top-level code.\n\
+             [line 2] Error at '}': Expect ';' after return value.\n\
+             [line 2] Error at end: Expect '}' after block.",
+        );
+        // A loop outside of the function doesn't count.
+        test_program_error(
+            "while (a) fun () { break; };",
+            "[line 1] Error at 'break': Can't use 'break' outside of a loop.",
+        );
+        test_program_error(
+            "fun a () {};",
+            "[line 1] Error at 'a': Expect '(' after 'fun'.",
+        );
+        test_program_error(
+            "fun (a b) {};",
+            "[line 1] Error at 'b': Expect ')' after parameters.",
+        );
+        test_program_error(
+            "fun (1) {};",
+            "[line 1] Error at '1': Expect parameter name.",
+        );
+        test_program_error(
+            "fun () return 1;",
+            "[line 1] Error at 'return': Expect '{' before function body.",
+        );
+    }
+
+    #[test]
+    fn too_many_parameters() {
+        let params: Vec<_> = (0..=MAX_PARAMETERS).map(|n| format!("p{}", n)).collect();
+        let source = format!("fun ({}) {{}};", params.join(", "));
+        let mut scanner = scanner::Scanner::new(&source);
+        let mut ast = Ast::new();
+        let error = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse_program()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1] Error at 'p255': Can't have more than 255 parameters."
+        );
+    }
+
+    #[test]
+    fn statement_tokens() {
+        let mut scanner = scanner::Scanner::new("print 1; { 2; }");
+        let tokens = scanner.scan_tokens();
+        let mut ast = Ast::new();
+        let mut parser = Parser::new(tokens, &mut ast);
+        let statements = parser.parse_program().unwrap();
+        let spans = parser.statement_tokens().clone();
+        assert_eq!(spans[&statements[0]], 0..3);
+        assert_eq!(spans[&statements[1]], 3..7);
+        let Stmt::Block(inner) = &ast[statements[1]] else {
+            panic!("expected a block");
+        };
+        assert_eq!(spans[&inner[0]], 4..6);
+    }
+
+    #[test]
+    fn calls() {
+        test_with_ast("f()", "(call f)");
+        test_with_ast("f(1, (2, 3))(a = 4)", "(call (call f 1 ((, 2 3))) (= a 4))");
+        test_with_ast("-Math.sqrt(4).x", "(- (. (call (. Math sqrt) 4) x))");
+        test_program_error(
+            "f(1;",
+            "[line 1] Error at ';': Expect ')' after arguments.",
+        );
+        test_program_error(
+            "a.1;",
+            "[line 1] Error at '1': Expect property name after '.'.",
+        );
+    }
+
+    #[test]
+    fn too_many_arguments() {
+        let source = format!("f({});", vec!["1"; MAX_ARGUMENTS + 1].join(", "));
+        let mut scanner = scanner::Scanner::new(&source);
+        let mut ast = Ast::new();
+        let error = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse_program()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1] Error at '1': Can't have more than 255 arguments."
+        );
+    }
+
+    #[test]
+    fn lists() {
+        test_with_ast("[]", "[]");
+        test_with_ast("[1, 2 + 3, [a]]", "[1 (+ 2 3) [a]]");
+        test_with_ast("xs[0][i, j]", "(index (index xs 0) (, i j))");
+        test_with_ast("f()[0].a", "(. (index (call f) 0) a)");
+        test_with_ast("xs[0] = ys[1] = 2", "(= (index xs 0) (= (index ys 1) 2))");
+        test_program_error(
+            "[1, 2;",
+            "[line 1] Error at ';': Expect ']' after list elements.",
+        );
+        test_program_error("xs[0;", "[line 1] Error at ';': Expect ']' after index.");
+        test_program_error(
+            "[1] = 2;",
+            "[line 1] Error at '=': Invalid assignment target.",
+        );
+    }
+
+    #[test]
+    fn maps() {
+        test_with_ast("{}", "{}");
+        test_with_ast(
+            "{\"a\": 1, 2: b = c}[\"a\"]",
+            "(index {\"a\" 1 2 (= b c)} \"a\")",
+        );
+        // A brace starting a statement is still a block.
+        test_with_program("{ a; }", &["(block (; a))"]);
+        test_with_program("({});", &["(; ({}))"]);
+        test_program_error(
+            "print {1 2};",
+            "[line 1] Error at '2': Expect ':' after map key.",
+        );
+        test_program_error(
+            "print {1: 2;",
+            "[line 1] Error at ';': Expect '}' after map entries.",
+        );
+    }
+
+    #[test]
+    fn too_many_list_elements() {
+        let source = format!("[{}];", vec!["1"; MAX_LIST_ELEMENTS + 1].join(", "));
+        let mut scanner = scanner::Scanner::new(&source);
+        let mut ast = Ast::new();
+        let error = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse_program()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "[line 1] Error at '1': Can't have more than 255 elements in a list."
+        );
+    }
+
+    #[test]
+    fn invalid_assignment_target() {
+        test_program_error(
+            "1 + a = 2;",
+            "[line 1] Error at '=': Invalid assignment target.",
+        );
+    }
+
+    #[test]
+    fn statement_errors_are_all_reported() {
+        test_program_error(
+            "print 1\nvar a = 2;\n{ var = 1; }\nprint 3 4;",
+            "[line 2] Error at 'var': Expect ';' after value.\n\
+             [line 3] Error at '=': Expect variable name.\n\
+             [line 4] Error at '4': Expect ';' after value.",
+        );
+        test_program_error(
+            "{ print 1;",
+            "[line 1] Error at end: Expect '}' after block.",
+        );
+    }
 }
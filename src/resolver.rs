@@ -0,0 +1,274 @@
+use crate::expr;
+use crate::expr::Ast;
+use crate::expr::Expr;
+use crate::expr::NodeId;
+use crate::expr::Visitor as _;
+use crate::stmt;
+use crate::stmt::Stmt;
+use crate::stmt::StmtId;
+use crate::stmt::Visitor as _;
+use crate::symbol::Symbol;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// Works out which scope each variable refers to before the tree-walker runs,
+/// so that a function sees the variables that were in scope where it was
+/// written, not whatever has been declared by the time it's called.
+///
+/// Scopes are the same ones the tree-walker creates environments for: blocks,
+/// `for` loops and function bodies, which share a scope with the parameters.
+/// Top-level variables are globals, and aren't resolved.
+#[derive(Default)]
+pub struct Resolver {
+    // Innermost last.
+    scopes: Vec<HashSet<Symbol>>,
+    locals: HashMap<NodeId, usize>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many scopes out each `Variable` and `Assign` node resolved so far
+    /// finds its variable. Nodes that aren't in here refer to globals.
+    pub fn into_locals(self) -> HashMap<NodeId, usize> {
+        self.locals
+    }
+
+    fn resolve_local(&mut self, expr: NodeId, name: Symbol) {
+        if let Some(distance) = self
+            .scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains(&name))
+        {
+            self.locals.insert(expr, distance);
+        }
+    }
+
+    fn scoped(&mut self, ast: &Ast, declared: &[Symbol], statements: &[StmtId]) {
+        self.scopes.push(declared.iter().copied().collect());
+        for stmt in statements {
+            self.visit_stmt(ast, *stmt);
+        }
+        self.scopes.pop();
+    }
+}
+
+impl expr::Visitor for Resolver {
+    type Result = ();
+
+    fn visit_expr(&mut self, ast: &Ast, expr: NodeId) {
+        match &ast[expr] {
+            Expr::Assign { name, value, .. } => {
+                self.visit_expr(ast, *value);
+                self.resolve_local(expr, *name);
+            }
+            Expr::Variable { name, .. } => self.resolve_local(expr, *name),
+            Expr::Function { params, body, .. } => {
+                // The body's statements are in the parameters' scope, rather
+                // than a block of their own.
+                let Stmt::Block(statements) = &ast[*body] else {
+                    unreachable!("function bodies are blocks");
+                };
+                self.scoped(ast, params, statements);
+            }
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.visit_expr(ast, *left);
+                self.visit_expr(ast, *right);
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.visit_expr(ast, *callee);
+                for argument in arguments {
+                    self.visit_expr(ast, *argument);
+                }
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.visit_expr(ast, *condition);
+                self.visit_expr(ast, *then_branch);
+                self.visit_expr(ast, *else_branch);
+            }
+            Expr::Get { object: inner, .. }
+            | Expr::Grouping(inner)
+            | Expr::Unary { right: inner, .. } => self.visit_expr(ast, *inner),
+            Expr::Index { object, index, .. } => {
+                self.visit_expr(ast, *object);
+                self.visit_expr(ast, *index);
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.visit_expr(ast, *object);
+                self.visit_expr(ast, *index);
+                self.visit_expr(ast, *value);
+            }
+            Expr::List(elements) => {
+                for element in elements {
+                    self.visit_expr(ast, *element);
+                }
+            }
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.visit_expr(ast, *key);
+                    self.visit_expr(ast, *value);
+                }
+            }
+            Expr::Nil { .. }
+            | Expr::LiteralNumber { .. }
+            | Expr::LiteralString { .. }
+            | Expr::LiteralBool { .. } => {}
+        }
+    }
+}
+
+impl stmt::Visitor for Resolver {
+    type Result = ();
+
+    fn visit_stmt(&mut self, ast: &Ast, stmt: StmtId) {
+        match &ast[stmt] {
+            Stmt::Block(statements) => self.scoped(ast, &[], statements),
+            Stmt::Break { .. } | Stmt::Continue { .. } => {}
+            Stmt::Expression { expr, .. } | Stmt::Print { expr, .. } => self.visit_expr(ast, *expr),
+            Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+            } => {
+                // Like a block around the whole loop.
+                self.scopes.push(HashSet::new());
+                if let Some(initializer) = initializer {
+                    self.visit_stmt(ast, *initializer);
+                }
+                for expr in condition.iter().chain(increment) {
+                    self.visit_expr(ast, *expr);
+                }
+                self.visit_stmt(ast, *body);
+                self.scopes.pop();
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.visit_expr(ast, *condition);
+                self.visit_stmt(ast, *then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.visit_stmt(ast, *else_branch);
+                }
+            }
+            Stmt::Return { value, .. } => {
+                if let Some(value) = value {
+                    self.visit_expr(ast, *value);
+                }
+            }
+            Stmt::Var {
+                name, initializer, ..
+            } => {
+                // Declared once the initializer has run, so the initializer
+                // still sees whatever the name referred to before.
+                if let Some(initializer) = initializer {
+                    self.visit_expr(ast, *initializer);
+                }
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(*name);
+                }
+            }
+            Stmt::While {
+                condition, body, ..
+            } => {
+                self.visit_expr(ast, *condition);
+                self.visit_stmt(ast, *body);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    // Each variable's name and distance, in the order they were allocated.
+    fn resolve(source: &str) -> Vec<(String, Option<usize>)> {
+        let mut scanner = Scanner::new(source);
+        let mut ast = Ast::new();
+        let statements = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse_program()
+            .unwrap();
+        let mut resolver = Resolver::new();
+        for stmt in &statements {
+            resolver.visit_stmt(&ast, *stmt);
+        }
+        let locals = resolver.into_locals();
+        ast.iter()
+            .filter_map(|(id, expr)| match expr {
+                Expr::Variable { name, .. } | Expr::Assign { name, .. } => {
+                    Some((name.to_string(), locals.get(&id).copied()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn named(pairs: &[(&str, Option<usize>)]) -> Vec<(String, Option<usize>)> {
+        pairs
+            .iter()
+            .map(|(name, distance)| (name.to_string(), *distance))
+            .collect()
+    }
+
+    #[test]
+    fn globals_are_unresolved() {
+        // The parser reads an assignment's target as a variable first, and
+        // that node is never resolved.
+        assert_eq!(
+            resolve("var a = 1; print a; b = a;"),
+            named(&[("a", None), ("b", None), ("a", None), ("b", None)])
+        );
+        assert_eq!(
+            resolve("{ var b; b = 1; }"),
+            named(&[("b", None), ("b", Some(0))])
+        );
+    }
+
+    #[test]
+    fn scopes() {
+        assert_eq!(
+            resolve("{ var a; { var b; print a; print b; } } for (var i; i;) { i; }"),
+            named(&[
+                ("a", Some(1)),
+                ("b", Some(0)),
+                ("i", Some(0)),
+                ("i", Some(1))
+            ])
+        );
+    }
+
+    #[test]
+    fn initializer_sees_the_outer_variable() {
+        assert_eq!(
+            resolve("{ var a; { var a = a; print a; } }"),
+            named(&[("a", Some(1)), ("a", Some(0))])
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(
+            resolve("{ var a; var f = fun (b) { var c; return a + b + c + d; }; var d; }"),
+            named(&[("a", Some(1)), ("b", Some(0)), ("c", Some(0)), ("d", None)])
+        );
+    }
+}
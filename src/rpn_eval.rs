@@ -0,0 +1,218 @@
+use crate::expr::BinaryOp;
+use crate::expr::LogicalOp;
+use crate::expr::UnaryOp;
+use crate::rpn_print::CONDITIONAL_NAME;
+use crate::value;
+use crate::value::Value;
+
+/// Evaluates `RPNPrinter` output. It shares operator semantics with the
+/// tree-walker, so the two should always agree on what an expression
+/// evaluates to, including which operands of `?:`, `and` and `or` get
+/// evaluated at all.
+///
+/// There's nowhere to look variables up, so expressions using them (or
+/// assigning to them) are rejected.
+pub fn evaluate(rpn: &str) -> anyhow::Result<Value> {
+    // Postfix only says where an operator's operands end once it's reached,
+    // so the words are turned back into a tree first, and only evaluated
+    // once it's known which operands are needed.
+    let mut nodes = Vec::new();
+    let mut stack = Vec::new();
+    for (position, word) in words(rpn)?.into_iter().enumerate() {
+        let operation = if word == CONDITIONAL_NAME {
+            Operation::Conditional
+        } else if let Some(operator) = unary_from_name(word) {
+            Operation::Unary(operator)
+        } else if let Some(operator) = LogicalOp::from_lexeme(word) {
+            Operation::Logical(operator)
+        } else if let Some(operator) = BinaryOp::from_lexeme(word) {
+            Operation::Binary(operator)
+        } else {
+            let value = literal(word)
+                .ok_or_else(|| anyhow::anyhow!("[word {}] Unknown word '{}'.", position, word))?;
+            Operation::Literal(value)
+        };
+        let Some(start) = stack.len().checked_sub(operation.operand_count()) else {
+            anyhow::bail!("[word {}] Stack underflow.", position);
+        };
+        let operands = stack.split_off(start);
+        stack.push(nodes.len());
+        nodes.push(Node {
+            position,
+            operation,
+            operands,
+        });
+    }
+
+    if stack.len() != 1 {
+        anyhow::bail!("Expected one value left on the stack, found {}.", stack.len());
+    }
+    evaluate_node(&nodes, stack[0])
+}
+
+enum Operation {
+    Literal(Value),
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    Logical(LogicalOp),
+    Conditional,
+}
+
+impl Operation {
+    fn operand_count(&self) -> usize {
+        match self {
+            Operation::Literal(_) => 0,
+            Operation::Unary(_) => 1,
+            Operation::Binary(_) | Operation::Logical(_) => 2,
+            Operation::Conditional => 3,
+        }
+    }
+}
+
+struct Node {
+    // Which word it was, for errors.
+    position: usize,
+    operation: Operation,
+    // Indices into the nodes, which always come before this one.
+    operands: Vec<usize>,
+}
+
+fn evaluate_node(nodes: &[Node], index: usize) -> anyhow::Result<Value> {
+    let node = &nodes[index];
+    let operand = |index: usize| evaluate_node(nodes, node.operands[index]);
+    let error = |message| anyhow::anyhow!("[word {}] {}", node.position, message);
+    let value = match &node.operation {
+        Operation::Literal(value) => value.clone(),
+        Operation::Unary(operator) => value::unary(*operator, operand(0)?).map_err(error)?,
+        Operation::Binary(operator) => {
+            value::binary(*operator, operand(0)?, operand(1)?).map_err(error)?
+        }
+        Operation::Logical(operator) => {
+            let left = operand(0)?;
+            match operator {
+                LogicalOp::Or if left.is_truthy() => left,
+                LogicalOp::And if !left.is_truthy() => left,
+                _ => operand(1)?,
+            }
+        }
+        Operation::Conditional => {
+            if operand(0)?.is_truthy() {
+                operand(1)?
+            } else {
+                operand(2)?
+            }
+        }
+    };
+    Ok(value)
+}
+
+fn unary_from_name(word: &str) -> Option<UnaryOp> {
+    [UnaryOp::Plus, UnaryOp::Minus]
+        .into_iter()
+        .find(|operator| crate::rpn_print::unary_name(*operator) == word)
+}
+
+fn literal(word: &str) -> Option<Value> {
+    let value = match word {
+        "nil" => Value::Nil,
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => {
+            if let Some(quoted) = word.strip_prefix('"') {
+                Value::String(quoted.strip_suffix('"')?.into())
+            } else {
+                Value::Number(word.parse().ok()?)
+            }
+        }
+    };
+    Some(value)
+}
+
+// Splits on whitespace, except inside of string literals.
+fn words(rpn: &str) -> anyhow::Result<Vec<&str>> {
+    let mut words = Vec::new();
+    let mut rest = rpn.trim_start();
+    while !rest.is_empty() {
+        let end = if let Some(unquoted) = rest.strip_prefix('"') {
+            match unquoted.find('"') {
+                // Including both quotes.
+                Some(index) => index + 2,
+                None => anyhow::bail!("Unterminated string."),
+            }
+        } else {
+            rest.find(char::is_whitespace).unwrap_or(rest.len())
+        };
+        words.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::Ast;
+    use crate::expr::Visitor;
+    use crate::interpreter::Interpreter;
+    use crate::parser::Parser;
+    use crate::rpn_print::RPNPrinter;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn evaluate_rpn() {
+        assert_eq!(evaluate("1 2 + 4 3 - *").unwrap(), Value::Number(3.0));
+        assert_eq!(evaluate("123 neg").unwrap(), Value::Number(-123.0));
+        assert_eq!(
+            evaluate("\"a b\" \" c\" +").unwrap(),
+            Value::String("a b c".into())
+        );
+        assert_eq!(evaluate("nil false ==").unwrap(), Value::Bool(false));
+    }
+
+    #[test]
+    fn evaluate_errors() {
+        assert!(evaluate("").is_err());
+        assert!(evaluate("1 +").is_err());
+        assert!(evaluate("1 2").is_err());
+        assert!(evaluate("\"open").is_err());
+        assert!(evaluate("true neg").is_err());
+        assert!(evaluate("1 foo").is_err());
+    }
+
+    #[test]
+    fn matches_tree_walker() {
+        for source in [
+            "1 + 2 * 3 - 4 / 5",
+            "-(1 + 2) * -+-3",
+            "(1 < 2) == (3 >= 4)",
+            "\"some \" + \"string\" != \"some string\"",
+            "nil == nil",
+            "1 - (2 - (3 - 4))",
+            "true + 1",
+            "1 == 2 ? \"yes\" : 3 ? 4 : 5",
+            "1, 2, 3",
+            "nil or \"default\"",
+            "1 and false or 2",
+            // The operands that aren't needed aren't evaluated, so their
+            // errors don't happen.
+            "true ? 1 : -nil",
+            "false ? -nil : 2",
+            "false and -nil",
+            "true or -nil",
+            "true and -nil",
+        ] {
+            let mut scanner = Scanner::new(source);
+            let mut ast = Ast::new();
+            let expr = Parser::new(scanner.scan_tokens(), &mut ast)
+                .parse()
+                .unwrap();
+            let tree_walked = Interpreter::new().evaluate(&ast, expr);
+            let rpn = evaluate(&RPNPrinter.visit_expr(&ast, expr));
+            match (tree_walked, rpn) {
+                (Ok(tree_walked), Ok(rpn)) => assert_eq!(tree_walked, rpn, "{}", source),
+                (Err(_), Err(_)) => {}
+                (tree_walked, rpn) => panic!("{}: {:?} vs {:?}", source, tree_walked, rpn),
+            }
+        }
+    }
+}
@@ -1,46 +1,227 @@
-use crate::ast_print::token_to_string;
 use crate::expr;
+use crate::expr::UnaryOp;
 
-/// Prints in reverse-polish notation. 
+/// Prints in reverse-polish notation. Groupings disappear since postfix
+/// doesn't need them, and unary operators get their own names (`neg`, `pos`)
+/// so they can't be mistaken for binary ones.
 pub struct RPNPrinter;
 
 impl expr::Visitor for RPNPrinter {
     type Result = String;
 
-    fn visit_expr(&mut self, expr: &expr::Expr) -> Self::Result {
+    fn visit_expr(&mut self, ast: &expr::Ast, expr: expr::NodeId) -> Self::Result {
         use expr::Expr::*;
-        match expr {
+        match &ast[expr] {
+            Assign { name, value, .. } => {
+                format!("{} {} {}", self.visit_expr(ast, *value), name, ASSIGN_NAME)
+            }
             Binary {
                 left,
                 operator,
                 right,
+                ..
             } => {
                 format!(
                     "{} {} {}",
-                    self.visit_expr(left),
-                    self.visit_expr(right),
-                    token_to_string(operator),
+                    self.visit_expr(ast, *left),
+                    self.visit_expr(ast, *right),
+                    operator,
                 )
             }
-            Grouping(expr) => {
-                format!("({})", self.visit_expr(expr))
+            Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                format!(
+                    "{} {} {} {}",
+                    self.visit_expr(ast, *condition),
+                    self.visit_expr(ast, *then_branch),
+                    self.visit_expr(ast, *else_branch),
+                    CONDITIONAL_NAME,
+                )
             }
-            LiteralNumber(number) => {
-                format!("{}", number)
+            Call {
+                callee, arguments, ..
+            } => {
+                let mut printed = self.visit_expr(ast, *callee);
+                for argument in arguments {
+                    printed.push(' ');
+                    printed.push_str(&self.visit_expr(ast, *argument));
+                }
+                format!("{} {}/{}", printed, CALL_NAME, arguments.len())
+            }
+            Function { params, .. } => {
+                let mut printed = String::new();
+                for param in params {
+                    printed.push_str(param.as_str());
+                    printed.push(' ');
+                }
+                format!("{}{}/{}", printed, FUNCTION_NAME, params.len())
             }
-            LiteralString(ss) => {
-                format!("{}", ss)
+            Get { object, name, .. } => format!("{} .{}", self.visit_expr(ast, *object), name),
+            Grouping(expr) => self.visit_expr(ast, *expr),
+            Index { object, index, .. } => format!(
+                "{} {} {}",
+                self.visit_expr(ast, *object),
+                self.visit_expr(ast, *index),
+                INDEX_NAME
+            ),
+            List(elements) => {
+                let mut printed = String::new();
+                for element in elements {
+                    printed.push_str(&self.visit_expr(ast, *element));
+                    printed.push(' ');
+                }
+                format!("{}{}/{}", printed, LIST_NAME, elements.len())
             }
-            LiteralBool(bb) => {
+            Map(entries) => {
+                let mut printed = String::new();
+                for (key, value) in entries {
+                    printed.push_str(&self.visit_expr(ast, *key));
+                    printed.push(' ');
+                    printed.push_str(&self.visit_expr(ast, *value));
+                    printed.push(' ');
+                }
+                format!("{}{}/{}", printed, MAP_NAME, entries.len())
+            }
+            LiteralNumber { value: number, .. } => {
+                format!("{}", number)
+            }
+            LiteralString { value: ss, .. } => format!("\"{}\"", ss),
+            LiteralBool { value: bb, .. } => {
                 format!("{}", bb)
             }
-            Nil => "nil".to_owned(),
-            // Note: this is tricky... we'd have to represent the unary
-            // differently to distinguish it from a binary operation. We just
-            // use the debug representation.
-            Unary { operator, right } => {
-                format!("{} {:#?}", self.visit_expr(right), operator.token)
+            Logical {
+                left,
+                operator,
+                right,
+            } => {
+                format!(
+                    "{} {} {}",
+                    self.visit_expr(ast, *left),
+                    self.visit_expr(ast, *right),
+                    operator,
+                )
+            }
+            Nil { .. } => "nil".to_owned(),
+            SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => format!(
+                "{} {} {} {}",
+                self.visit_expr(ast, *object),
+                self.visit_expr(ast, *index),
+                self.visit_expr(ast, *value),
+                SET_INDEX_NAME
+            ),
+            Unary {
+                operator, right, ..
+            } => {
+                format!("{} {}", self.visit_expr(ast, *right), unary_name(*operator))
             }
+            Variable { name, .. } => name.to_string(),
         }
     }
 }
+
+/// Pops the else branch, then branch and condition, in that order.
+pub const CONDITIONAL_NAME: &str = "?:";
+
+/// Pops a variable name, then the value to assign to it.
+pub const ASSIGN_NAME: &str = "=";
+
+/// Followed by the number of arguments, e.g. `call/2`, it pops that many
+/// arguments and then the callee. Property access is `.name`, which pops the
+/// object.
+pub const CALL_NAME: &str = "call";
+
+/// Like calls, followed by the number of elements to pop, e.g. `list/3`.
+pub const LIST_NAME: &str = "list";
+
+/// Followed by the number of entries, e.g. `map/2`. Each entry is a key and
+/// then its value, so it pops twice that many values.
+pub const MAP_NAME: &str = "map";
+
+/// Followed by the number of parameters, e.g. `fun/2`, it pops that many
+/// parameter names. Statements have no postfix form, so the body is left out.
+pub const FUNCTION_NAME: &str = "fun";
+
+/// Pops the index, then the list.
+pub const INDEX_NAME: &str = "index";
+
+/// Pops the value, the index and then the list.
+pub const SET_INDEX_NAME: &str = "index=";
+
+pub fn unary_name(operator: UnaryOp) -> &'static str {
+    match operator {
+        UnaryOp::Plus => "pos",
+        UnaryOp::Minus => "neg",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expr::Ast;
+    use crate::expr::Visitor;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn test_with_rpn(source: &str, expected_rpn: &str) {
+        let mut scanner = Scanner::new(source);
+        let mut ast = Ast::new();
+        let expr = Parser::new(scanner.scan_tokens(), &mut ast)
+            .parse()
+            .unwrap();
+        assert_eq!(RPNPrinter.visit_expr(&ast, expr), expected_rpn);
+    }
+
+    #[test]
+    fn groupings_are_dropped() {
+        test_with_rpn("(1 + 2) * (4 - 3)", "1 2 + 4 3 - *");
+    }
+
+    #[test]
+    fn unary_operators() {
+        test_with_rpn("-123 * (45.67)", "123 neg 45.67 *");
+        test_with_rpn("1 - -+2", "1 2 pos neg -");
+    }
+
+    #[test]
+    fn conditional_and_comma() {
+        test_with_rpn("1 ? 2 : 3, 4", "1 2 3 ?: 4 ,");
+    }
+
+    #[test]
+    fn literals() {
+        test_with_rpn("\"a b\" == nil != true", "\"a b\" nil == true !=");
+    }
+
+    #[test]
+    fn variables_and_logical_operators() {
+        test_with_rpn("a = b or c and 1", "b c 1 and or a =");
+    }
+
+    #[test]
+    fn calls() {
+        test_with_rpn("f(1, 2 + 3)()", "f 1 2 3 + call/2 call/0");
+        test_with_rpn("Math.abs(-1)", "Math .abs 1 neg call/1");
+        test_with_rpn("fun (a, b) { return a; }(1, 2)", "a b fun/2 1 2 call/2");
+    }
+
+    #[test]
+    fn lists() {
+        test_with_rpn("[]", "list/0");
+        test_with_rpn("[1, [2]][0]", "1 2 list/1 list/2 0 index");
+        test_with_rpn("xs[-1] = 2", "xs 1 neg 2 index=");
+    }
+
+    #[test]
+    fn maps() {
+        test_with_rpn("{}", "map/0");
+        test_with_rpn("{\"a\": 1, 2: b}[c]", "\"a\" 1 2 b map/2 c index");
+    }
+}
@@ -1,6 +1,6 @@
+use serde::Serialize;
 use std::iter::Iterator;
 use std::iter::Peekable;
-use std::string::ToString;
 use unicode_segmentation::UnicodeSegmentation;
 
 pub struct Scanner<'a> {
@@ -12,9 +12,10 @@ pub struct Scanner<'a> {
 
     line_number: usize,
     tokens: Vec<AnnotatedToken<'a>>,
-    // TODO: this has_error stuff is duplicated in Lox. Maybe have a reference
-    // to Lox somehow?
-    has_error: bool,
+    comments: Vec<Comment<'a>>,
+    // Kept rather than printed, so that whoever runs the scanner decides where
+    // they go.
+    errors: Vec<String>,
 }
 
 impl<'a> Scanner<'a> {
@@ -28,7 +29,8 @@ impl<'a> Scanner<'a> {
 
             line_number: 1,
             tokens: Vec::new(),
-            has_error: false,
+            comments: Vec::new(),
+            errors: Vec::new(),
         }
     }
 
@@ -40,7 +42,19 @@ impl<'a> Scanner<'a> {
     }
 
     pub fn has_error(&self) -> bool {
-        self.has_error
+        !self.errors.is_empty()
+    }
+
+    /// What went wrong, one message per error, like
+    /// `[line 1] Error (TODO where): Unexpected character.`
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    /// Comments skipped while scanning, in source order. Each one is leading
+    /// trivia of the token at `Comment::token_index`.
+    pub fn comments(&self) -> &Vec<Comment<'a>> {
+        &self.comments
     }
 
     // TODO: return nothing, just update state?
@@ -54,12 +68,16 @@ impl<'a> Scanner<'a> {
             ")" => self.add_token(Token::RightParen),
             "{" => self.add_token(Token::LeftBrace),
             "}" => self.add_token(Token::RightBrace),
+            "[" => self.add_token(Token::LeftBracket),
+            "]" => self.add_token(Token::RightBracket),
             "," => self.add_token(Token::Comma),
             "." => self.add_token(Token::Dot),
             "-" => self.add_token(Token::Minus),
             "+" => self.add_token(Token::Plus),
             ";" => self.add_token(Token::Semicolon),
             "*" => self.add_token(Token::Star),
+            "?" => self.add_token(Token::Question),
+            ":" => self.add_token(Token::Colon),
             "!" => {
                 if self.advance_if_next_matches("=") {
                     self.add_token(Token::BangEqual);
@@ -102,6 +120,7 @@ impl<'a> Scanner<'a> {
                         // grapheme; // uncommenting this line causes a
                         // double &mut borrow error.
                     }
+                    self.add_comment();
                 } else {
                     // Division
                     self.add_token(Token::Slash);
@@ -276,9 +295,20 @@ impl<'a> Scanner<'a> {
         });
     }
 
+    // See `add_number_token`
+    fn add_comment(&mut self) {
+        let text = &self.source[self.token_start_byte_offset..self.current_byte_offset];
+        self.comments.push(Comment {
+            text: text.trim_end(),
+            line_number: self.line_number,
+            // Comments are attached to whichever token comes next.
+            token_index: self.tokens.len(),
+        });
+    }
+
     fn report_error(&mut self, line: usize, message: &str) {
-        eprintln!("[line {}] Error (TODO where): {}", line, message);
-        self.has_error = true;
+        self.errors
+            .push(format!("[line {}] Error (TODO where): {}", line, message));
     }
 }
 
@@ -289,18 +319,22 @@ impl<'a> std::fmt::Debug for Scanner<'a> {
             .field("current_byte_offset", &self.current_byte_offset)
             .field("line_number", &self.line_number)
             .field("tokens", &self.tokens)
-            .field("has_error", &self.has_error)
+            .field("comments", &self.comments)
+            .field("errors", &self.errors)
             .finish()
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(tag = "type")]
 pub enum Token<'a> {
     // Single-character tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -308,6 +342,8 @@ pub enum Token<'a> {
     Semicolon,
     Slash,
     Star,
+    Question,
+    Colon,
 
     // One or two character tokens.
     Bang,
@@ -321,7 +357,9 @@ pub enum Token<'a> {
 
     // Keywords.
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
@@ -345,18 +383,29 @@ pub enum Token<'a> {
     EOF,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct AnnotatedToken<'a> {
     pub token: Token<'a>,
     pub line_number: usize,
 }
 
-impl<'a> ToString for AnnotatedToken<'a> {
-    fn to_string(&self) -> String {
-        format!("{:?}", self)
+impl<'a> std::fmt::Display for AnnotatedToken<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
     }
 }
 
+/// A `//` comment, kept as trivia so that tools like the formatter can put it
+/// back.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Comment<'a> {
+    /// The comment including its leading `//`.
+    pub text: &'a str,
+    pub line_number: usize,
+    /// Index of the token the comment precedes; might be the `EOF` token.
+    pub token_index: usize,
+}
+
 fn is_newline(grapheme: &str) -> bool {
     // Note: can add support for other types of newlines later.
     grapheme == "\n"
@@ -375,7 +424,7 @@ fn is_digit(grapheme: &str) -> bool {
         && grapheme
             .chars()
             .next()
-            .map_or(false, |ch| char::is_digit(ch, 10))
+            .is_some_and(|ch| char::is_ascii_digit(&ch))
 }
 
 // Lox accepts alphabetic (unicode) and underscore as the first grapheme of an
@@ -395,10 +444,12 @@ fn is_identifier_tail(grapheme: &str) -> bool {
         .all(|ch| char::is_alphanumeric(ch) || ch == '_')
 }
 
-fn token_from_identifier(identifier: &str) -> Token {
+fn token_from_identifier(identifier: &str) -> Token<'_> {
     match identifier {
         "and" => Token::And,
+        "break" => Token::Break,
         "class" => Token::Class,
+        "continue" => Token::Continue,
         "else" => Token::Else,
         "false" => Token::False,
         "for" => Token::For,
@@ -421,6 +472,26 @@ fn token_from_identifier(identifier: &str) -> Token {
 mod tests {
     use super::*;
 
+    #[test]
+    fn brackets() {
+        let mut scanner = Scanner::new("a[0]");
+        let tokens: Vec<_> = scanner
+            .scan_tokens()
+            .iter()
+            .map(|token| token.token.clone())
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Identifier { identifier: "a" },
+                Token::LeftBracket,
+                Token::Number { number: 0.0 },
+                Token::RightBracket,
+                Token::EOF,
+            ]
+        );
+    }
+
     #[test]
     fn basic_scan() {
         let source = r#"
@@ -595,6 +666,73 @@ var var_is_a_keyword and true;
             ]
         );
 
-        assert!(scanner.has_error());
+        assert_eq!(
+            scanner.errors(),
+            ["[line 20] Error (TODO where): Unterminated string"]
+        );
+    }
+
+    #[test]
+    fn comments_attach_to_the_next_token() {
+        let mut scanner = Scanner::new("\n// this is a comment\n(( )){} // grouping stuff\n!");
+        scanner.scan_tokens();
+        assert_eq!(
+            *scanner.comments(),
+            [
+                Comment {
+                    text: "// this is a comment",
+                    line_number: 2,
+                    token_index: 0,
+                },
+                Comment {
+                    text: "// grouping stuff",
+                    line_number: 3,
+                    token_index: 6,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn conditional_tokens() {
+        let mut scanner = Scanner::new("a ? b : c");
+        let tokens: Vec<_> = scanner
+            .scan_tokens()
+            .iter()
+            .map(|annotated| annotated.token.clone())
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Identifier { identifier: "a" },
+                Token::Question,
+                Token::Identifier { identifier: "b" },
+                Token::Colon,
+                Token::Identifier { identifier: "c" },
+                Token::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn trailing_comment_attaches_to_eof() {
+        let mut scanner = Scanner::new("1 // one\n// end");
+        let token_count = scanner.scan_tokens().len();
+        assert_eq!(token_count, 2);
+        assert_eq!(
+            *scanner.comments(),
+            [
+                Comment {
+                    text: "// one",
+                    line_number: 1,
+                    token_index: 1,
+                },
+                Comment {
+                    text: "// end",
+                    line_number: 2,
+                    token_index: 1,
+                },
+            ]
+        );
     }
 }
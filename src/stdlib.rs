@@ -0,0 +1,373 @@
+//! The natives every program gets: `clock`, `str`, `num`, `len`, `type`,
+//! `input`, `exit`, `readFile`, `writeFile`, `getenv`, and the `Math`
+//! namespace.
+
+use crate::native::Namespace;
+use crate::native::NativeRegistry;
+use crate::symbol::InternedString;
+use crate::value::Value;
+use std::io::BufRead;
+use std::io::Write;
+
+/// What the standard library may reach outside of the program, for running
+/// untrusted scripts.
+///
+/// Natives that need a capability that's off are still defined, but fail
+/// with a "Capability denied" error when called, rather than looking like a
+/// typo.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Capabilities {
+    /// `readFile` and `writeFile`.
+    pub filesystem: bool,
+    /// `getenv`.
+    pub env: bool,
+    /// `input`.
+    pub stdin: bool,
+    /// `exit`.
+    pub process: bool,
+    /// `clock`.
+    pub time: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Capabilities {
+            filesystem: true,
+            env: true,
+            stdin: true,
+            process: true,
+            time: true,
+        }
+    }
+
+    /// What `--sandbox` allows: nothing.
+    pub fn none() -> Self {
+        Capabilities {
+            filesystem: false,
+            env: false,
+            stdin: false,
+            process: false,
+            time: false,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// A registry with the whole standard library in it.
+pub fn core() -> NativeRegistry {
+    with_capabilities(Capabilities::all())
+}
+
+/// The standard library, with only the given capabilities.
+pub fn with_capabilities(capabilities: Capabilities) -> NativeRegistry {
+    let mut natives = NativeRegistry::new();
+    // Formats anything the way `print` would.
+    natives.register("str", 1, |arguments| Ok(string(&arguments[0].to_string())));
+    natives.register("num", 1, |arguments| match &arguments[0] {
+        Value::Number(number) => Ok(Value::Number(*number)),
+        Value::String(ss) => match ss.trim().parse() {
+            Ok(number) => Ok(Value::Number(number)),
+            Err(_) => anyhow::bail!("Can't convert '{}' to a number.", ss),
+        },
+        other => anyhow::bail!("Can't convert a {} to a number.", other.type_name()),
+    });
+    natives.register("len", 1, |arguments| {
+        let len = match &arguments[0] {
+            Value::List(list) => list.len(),
+            Value::Map(map) => map.len(),
+            other => text("len", other)?.chars().count(),
+        };
+        Ok(Value::Number(len as f64))
+    });
+    natives.register("type", 1, |arguments| Ok(string(arguments[0].type_name())));
+    natives.register_namespace(math());
+
+    // The rest reach outside of the program, so need a capability.
+    // Seconds since the Unix epoch, for timing things.
+    guarded(&mut natives, "time", capabilities.time, "clock", 0, |_| {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        Ok(Value::Number(now.as_secs_f64()))
+    });
+    // A line from stdin without its line ending, or nil at the end of input.
+    guarded(
+        &mut natives,
+        "stdin",
+        capabilities.stdin,
+        "input",
+        0,
+        |_| {
+            let mut line = String::new();
+            if std::io::stdin().lock().read_line(&mut line)? == 0 {
+                return Ok(Value::Nil);
+            }
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            Ok(string(line.strip_suffix('\r').unwrap_or(line)))
+        },
+    );
+    guarded(
+        &mut natives,
+        "process",
+        capabilities.process,
+        "exit",
+        1,
+        |arguments| {
+            let code = number("exit", &arguments[0])?;
+            // `process::exit` skips destructors, so nothing else would flush it.
+            std::io::stdout().flush()?;
+            std::process::exit(code as i32);
+        },
+    );
+    guarded(
+        &mut natives,
+        "filesystem",
+        capabilities.filesystem,
+        "readFile",
+        1,
+        |arguments| {
+            let path = text("readFile", &arguments[0])?;
+            match std::fs::read_to_string(&*path) {
+                Ok(contents) => Ok(string(&contents)),
+                Err(err) => anyhow::bail!("Can't read '{}': {}.", path, err),
+            }
+        },
+    );
+    // Replaces the whole file.
+    guarded(
+        &mut natives,
+        "filesystem",
+        capabilities.filesystem,
+        "writeFile",
+        2,
+        |arguments| {
+            let path = text("writeFile", &arguments[0])?;
+            let contents = text("writeFile", &arguments[1])?;
+            match std::fs::write(&*path, &*contents) {
+                Ok(()) => Ok(Value::Nil),
+                Err(err) => anyhow::bail!("Can't write '{}': {}.", path, err),
+            }
+        },
+    );
+    // An environment variable, or nil if it isn't set.
+    guarded(
+        &mut natives,
+        "env",
+        capabilities.env,
+        "getenv",
+        1,
+        |arguments| {
+            let name = text("getenv", &arguments[0])?;
+            Ok(std::env::var(&*name).map_or(Value::Nil, |value| string(&value)))
+        },
+    );
+    natives
+}
+
+fn math() -> Namespace {
+    let mut math = Namespace::new("Math");
+    math.constant("pi", Value::Number(std::f64::consts::PI))
+        .constant("e", Value::Number(std::f64::consts::E));
+
+    // The first function's cast gives the array its type.
+    let unary = [
+        ("abs", f64::abs as fn(f64) -> f64),
+        ("ceil", f64::ceil),
+        ("floor", f64::floor),
+        ("round", f64::round),
+        ("sqrt", f64::sqrt),
+        ("sin", f64::sin),
+        ("cos", f64::cos),
+        ("tan", f64::tan),
+        ("log", f64::ln),
+        ("exp", f64::exp),
+    ];
+    for (name, function) in unary {
+        let full_name = format!("Math.{}", name);
+        math.function(name, 1, move |arguments| {
+            Ok(Value::Number(function(number(&full_name, &arguments[0])?)))
+        });
+    }
+
+    let binary = [
+        ("pow", f64::powf as fn(f64, f64) -> f64),
+        ("min", f64::min),
+        ("max", f64::max),
+    ];
+    for (name, function) in binary {
+        let full_name = format!("Math.{}", name);
+        math.function(name, 2, move |arguments| {
+            let left = number(&full_name, &arguments[0])?;
+            let right = number(&full_name, &arguments[1])?;
+            Ok(Value::Number(function(left, right)))
+        });
+    }
+    math
+}
+
+fn string(ss: &str) -> Value {
+    Value::String(ss.into())
+}
+
+// Registers a native that needs `capability`, or if that isn't `allowed`,
+// one that says so.
+fn guarded(
+    natives: &mut NativeRegistry,
+    capability: &str,
+    allowed: bool,
+    name: &str,
+    arity: u8,
+    function: impl Fn(&[Value]) -> anyhow::Result<Value> + 'static,
+) {
+    if allowed {
+        natives.register(name, arity, function);
+    } else {
+        let message = format!(
+            "Capability denied: {}() needs the '{}' capability.",
+            name, capability
+        );
+        natives.register(name, arity, move |_| anyhow::bail!("{}", message));
+    }
+}
+
+fn text(function: &str, value: &Value) -> anyhow::Result<InternedString> {
+    match value {
+        Value::String(ss) => Ok(ss.clone()),
+        other => anyhow::bail!(
+            "{}() expects a string but got a {}.",
+            function,
+            other.type_name()
+        ),
+    }
+}
+
+fn number(function: &str, value: &Value) -> anyhow::Result<f64> {
+    match value {
+        Value::Number(number) => Ok(*number),
+        other => anyhow::bail!(
+            "{}() expects a number but got a {}.",
+            function,
+            other.type_name()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::Symbol;
+
+    fn call(name: &str, arguments: &[Value]) -> anyhow::Result<Value> {
+        call_in(&core(), name, arguments)
+    }
+
+    fn call_in(natives: &NativeRegistry, name: &str, arguments: &[Value]) -> anyhow::Result<Value> {
+        let (global, member) = match name.split_once('.') {
+            Some((global, member)) => (global, Some(member)),
+            None => (name, None),
+        };
+        let mut callee = natives.get(Symbol::intern(global)).unwrap();
+        if let Some(member) = member {
+            callee = crate::value::get_property(&callee, Symbol::intern(member))?;
+        }
+        crate::value::call(&callee, arguments)
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(
+            call("str", &[Value::Number(1.5)]).unwrap(),
+            Value::String("1.5".into())
+        );
+        assert_eq!(
+            call("num", &[Value::String(" 12 ".into())]).unwrap(),
+            Value::Number(12.0)
+        );
+        assert_eq!(
+            call("num", &[Value::String("twelve".into())])
+                .unwrap_err()
+                .to_string(),
+            "Can't convert 'twelve' to a number."
+        );
+        assert_eq!(
+            call("len", &[Value::String("héllo".into())]).unwrap(),
+            Value::Number(5.0)
+        );
+        assert_eq!(
+            call("len", &[vec![1, 2].into()]).unwrap(),
+            Value::Number(2.0)
+        );
+        assert_eq!(
+            call("type", &[Value::Nil]).unwrap(),
+            Value::String("nil".into())
+        );
+    }
+
+    #[test]
+    fn capabilities() {
+        let sandbox = with_capabilities(Capabilities::none());
+        assert_eq!(
+            call_in(&sandbox, "clock", &[]).unwrap_err().to_string(),
+            "Capability denied: clock() needs the 'time' capability."
+        );
+        assert_eq!(
+            call_in(&sandbox, "readFile", &[string("/etc/passwd")])
+                .unwrap_err()
+                .to_string(),
+            "Capability denied: readFile() needs the 'filesystem' capability."
+        );
+        assert_eq!(
+            call_in(&sandbox, "str", &[Value::Nil]).unwrap(),
+            string("nil")
+        );
+
+        let path = std::env::temp_dir().join(format!("stdlib-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        call("writeFile", &[string(path), string("contents")]).unwrap();
+        assert_eq!(
+            call("readFile", &[string(path)]).unwrap(),
+            string("contents")
+        );
+        std::fs::remove_file(path).unwrap();
+        assert!(call("readFile", &[string(path)])
+            .unwrap_err()
+            .to_string()
+            .starts_with("Can't read"));
+        assert_eq!(
+            call("getenv", &[string("STDLIB_TEST_UNSET_VARIABLE")]).unwrap(),
+            Value::Nil
+        );
+    }
+
+    #[test]
+    fn math() {
+        assert_eq!(
+            call("Math.sqrt", &[Value::Number(9.0)]).unwrap(),
+            Value::Number(3.0)
+        );
+        assert_eq!(
+            call("Math.max", &[Value::Number(1.0), Value::Number(2.0)]).unwrap(),
+            Value::Number(2.0)
+        );
+        assert_eq!(
+            call("Math.abs", &[Value::Bool(true)])
+                .unwrap_err()
+                .to_string(),
+            "Math.abs() expects a number but got a bool."
+        );
+        let math = core().get(Symbol::intern("Math")).unwrap();
+        assert_eq!(
+            crate::value::get_property(&math, Symbol::intern("pi")).unwrap(),
+            Value::Number(std::f64::consts::PI)
+        );
+        assert_eq!(
+            crate::value::get_property(&math, Symbol::intern("tau"))
+                .unwrap_err()
+                .to_string(),
+            "Undefined property 'tau'."
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use crate::expr::Ast;
+use crate::expr::NodeId;
+use crate::symbol::Symbol;
+use serde::Deserialize;
+use serde::Serialize;
+
+pub trait Visitor {
+    type Result;
+
+    fn visit_stmt(&mut self, ast: &Ast, stmt: StmtId) -> Self::Result;
+}
+
+/// Identifies a statement inside of an `Ast`, like `NodeId` does for
+/// expressions.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct StmtId(u32);
+
+impl StmtId {
+    pub(crate) fn from_index(index: usize) -> Self {
+        StmtId(u32::try_from(index).expect("too many statements for a single Ast"))
+    }
+
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value")]
+pub enum Stmt {
+    Block(Vec<StmtId>),
+    /// Only ever inside of a loop, which the parser checks.
+    Break {
+        line_number: usize,
+    },
+    /// Like `Break`. In a `for` loop, the increment still runs.
+    Continue {
+        line_number: usize,
+    },
+    Expression {
+        expr: NodeId,
+        line_number: usize,
+    },
+    /// Kept as written rather than desugared into a `while`, so that tools
+    /// like the formatter can print it back.
+    For {
+        initializer: Option<StmtId>,
+        condition: Option<NodeId>,
+        increment: Option<NodeId>,
+        body: StmtId,
+    },
+    If {
+        condition: NodeId,
+        then_branch: StmtId,
+        else_branch: Option<StmtId>,
+    },
+    Print {
+        expr: NodeId,
+        line_number: usize,
+    },
+    /// Only ever inside of a function, which the parser checks.
+    Return {
+        value: Option<NodeId>,
+        line_number: usize,
+    },
+    Var {
+        name: Symbol,
+        line_number: usize,
+        initializer: Option<NodeId>,
+    },
+    /// The line is the `while` keyword's, where each iteration starts.
+    While {
+        condition: NodeId,
+        body: StmtId,
+        line_number: usize,
+    },
+}
+
+impl Stmt {
+    /// Where the statement came from, for those that record it.
+    pub fn line_number(&self) -> Option<usize> {
+        match self {
+            Stmt::Break { line_number }
+            | Stmt::Continue { line_number }
+            | Stmt::Expression { line_number, .. }
+            | Stmt::Print { line_number, .. }
+            | Stmt::Return { line_number, .. }
+            | Stmt::Var { line_number, .. }
+            | Stmt::While { line_number, .. } => Some(*line_number),
+            Stmt::Block(_) | Stmt::For { .. } | Stmt::If { .. } => None,
+        }
+    }
+}
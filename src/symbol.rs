@@ -0,0 +1,235 @@
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::rc::Rc;
+use std::rc::Weak;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+
+/// An interned string, for identifiers. Equal strings always get the same
+/// `Symbol`, so comparing and hashing them doesn't look at the characters.
+///
+/// NOTE: symbols live until the process exits, so string values are
+/// `InternedString`s instead, since a loop building strings would otherwise
+/// grow the interner forever.
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+impl Symbol {
+    pub fn intern(string: &str) -> Symbol {
+        let mut interner = interner().lock().unwrap();
+        if let Some(symbol) = interner.symbols.get(string) {
+            return *symbol;
+        }
+
+        // Leaked so that `as_str` can hand out references without holding the
+        // lock.
+        let string: &'static str = Box::leak(string.into());
+        let symbol = Symbol(u32::try_from(interner.strings.len()).expect("too many symbols"));
+        interner.strings.push(string);
+        interner.symbols.insert(string, symbol);
+        symbol
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        interner().lock().unwrap().strings[self.0 as usize]
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(string: &str) -> Self {
+        Symbol::intern(string)
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Shows the string rather than the number, which means nothing outside this
+// process.
+impl std::fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+// Serialized as the string itself, for the same reason.
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = std::borrow::Cow::<str>::deserialize(deserializer)?;
+        Ok(Symbol::intern(&string))
+    }
+}
+
+/// An interned string value. Like `Symbol`, equal strings are the same
+/// string, so comparing and hashing one doesn't look at the characters.
+/// Unlike a `Symbol`, it's freed once the last copy is dropped, and the
+/// interner forgets it.
+///
+/// Interning is per thread, like the `Rc` inside.
+#[derive(Clone)]
+pub struct InternedString(Rc<str>);
+
+thread_local! {
+    // By the hash of their contents. Only weak, so that this doesn't keep
+    // them alive, and each string removes itself when it's freed.
+    static STRINGS: RefCell<HashMap<u64, Vec<Weak<str>>>> = RefCell::default();
+}
+
+fn content_hash(string: &str) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    string.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl InternedString {
+    pub fn new(string: &str) -> Self {
+        STRINGS.with(|strings| {
+            let mut strings = strings.borrow_mut();
+            let bucket = strings.entry(content_hash(string)).or_default();
+            let existing = bucket
+                .iter()
+                .filter_map(Weak::upgrade)
+                .find(|existing| **existing == *string);
+            let string = existing.unwrap_or_else(|| {
+                let string: Rc<str> = string.into();
+                bucket.push(Rc::downgrade(&string));
+                string
+            });
+            InternedString(string)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for InternedString {
+    fn drop(&mut self) {
+        if Rc::strong_count(&self.0) > 1 {
+            return;
+        }
+        // The table is already gone if the thread is exiting.
+        let _ = STRINGS.try_with(|strings| {
+            let mut strings = strings.borrow_mut();
+            let hash = content_hash(&self.0);
+            if let Some(bucket) = strings.get_mut(&hash) {
+                bucket.retain(|string| !std::ptr::eq(string.as_ptr(), Rc::as_ptr(&self.0)));
+                if bucket.is_empty() {
+                    strings.remove(&hash);
+                }
+            }
+        });
+    }
+}
+
+impl std::ops::Deref for InternedString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for InternedString {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for InternedString {}
+
+impl Hash for InternedString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).hash(state)
+    }
+}
+
+impl From<&str> for InternedString {
+    fn from(string: &str) -> Self {
+        InternedString::new(string)
+    }
+}
+
+impl From<String> for InternedString {
+    fn from(string: String) -> Self {
+        InternedString::new(&string)
+    }
+}
+
+impl std::fmt::Display for InternedString {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self)
+    }
+}
+
+impl std::fmt::Debug for InternedString {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interning() {
+        let a = Symbol::intern("interning_a");
+        assert_eq!(a, Symbol::intern(&String::from("interning_a")));
+        assert_ne!(a, Symbol::intern("interning_b"));
+        assert_eq!(a.as_str(), "interning_a");
+        assert_eq!(format!("{} {:?}", a, a), "interning_a \"interning_a\"");
+    }
+
+    #[test]
+    fn interned_strings() {
+        let a = InternedString::new("interned_a");
+        let b = InternedString::from(String::from("interned_a"));
+        assert_eq!(a, b);
+        assert!(std::ptr::eq(a.as_str(), b.as_str()));
+        assert_ne!(a, InternedString::new("interned_b"));
+        assert_eq!(format!("{} {:?}", a, a), "interned_a \"interned_a\"");
+
+        // Forgotten once the last one is dropped.
+        let hash = content_hash("interned_a");
+        drop(a);
+        assert!(STRINGS.with(|strings| strings.borrow().contains_key(&hash)));
+        drop(b);
+        assert!(!STRINGS.with(|strings| strings.borrow().contains_key(&hash)));
+    }
+
+    #[test]
+    fn serde() {
+        let symbol = Symbol::intern("serde");
+        let json = serde_json::to_string(&symbol).unwrap();
+        assert_eq!(json, "\"serde\"");
+        assert_eq!(serde_json::from_str::<Symbol>(&json).unwrap(), symbol);
+    }
+}
@@ -0,0 +1,400 @@
+use crate::expr::BinaryOp;
+use crate::expr::UnaryOp;
+use crate::interpreter::Function;
+use crate::list::List;
+use crate::map::Map;
+use crate::native::Namespace;
+use crate::native::NativeClass;
+use crate::native::NativeFunction;
+use crate::native::NativeInstance;
+use crate::symbol::InternedString;
+use crate::symbol::Symbol;
+use crate::vm::Closure;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::rc::Rc;
+
+/// A runtime Lox value.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    /// Interned, so that `==` doesn't compare the characters, but unlike
+    /// identifiers, freed once they're no longer used.
+    String(InternedString),
+    List(Rc<List>),
+    Map(Rc<Map>),
+    /// A Lox function made by the tree-walker.
+    Function(Rc<Function>),
+    /// A Lox function made by the VM. Each backend can only call its own.
+    Closure(Rc<Closure>),
+    Native(Rc<NativeFunction>),
+    Namespace(Rc<Namespace>),
+    NativeClass(Rc<NativeClass>),
+    NativeInstance(Rc<NativeInstance>),
+}
+
+impl Value {
+    /// `nil` and `false` are falsey, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// What the `type` native returns.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Function(_) | Value::Closure(_) | Value::Native(_) => "function",
+            Value::Namespace(_) => "namespace",
+            Value::NativeClass(_) => "class",
+            Value::NativeInstance(_) => "instance",
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(bb) => write!(f, "{}", bb),
+            Value::Number(number) => write!(f, "{}", number),
+            Value::String(ss) => write!(f, "{}", ss),
+            Value::List(list) => write!(f, "{}", list),
+            Value::Map(map) => write!(f, "{}", map),
+            Value::Function(_) | Value::Closure(_) => write!(f, "<fn>"),
+            Value::Native(native) => write!(f, "{:?}", native),
+            Value::Namespace(namespace) => write!(f, "{:?}", namespace),
+            Value::NativeClass(class) => write!(f, "{:?}", class),
+            Value::NativeInstance(instance) => write!(f, "{:?}", instance),
+        }
+    }
+}
+
+// Lists and maps print what's in them, which could include themselves. `open`
+// has those that are already being printed further out.
+pub(crate) fn write_nested(
+    f: &mut std::fmt::Formatter,
+    value: &Value,
+    open: &mut Vec<*const ()>,
+) -> std::fmt::Result {
+    match value {
+        Value::List(list) => list.write(f, open),
+        Value::Map(map) => map.write(f, open),
+        _ => write!(f, "{}", value),
+    }
+}
+
+// Empties the lists and maps among `copies` that nothing else refers to.
+// They're copies of the VM's, made for code that works with `Value`s, like
+// natives, and reference counting would never free the ones that refer to each
+// other. Copies something else kept, and whatever they refer to, are left
+// alone.
+pub(crate) fn break_cycles(copies: impl IntoIterator<Item = Value>) {
+    let copies: Vec<Value> = copies
+        .into_iter()
+        .filter(|copy| address(copy).is_some())
+        .collect();
+    // How many references to each copy come from the copies themselves.
+    let mut internal: HashMap<*const (), usize> = copies
+        .iter()
+        .filter_map(address)
+        .map(|copy| (copy, 0))
+        .collect();
+    for copy in &copies {
+        for child in children(copy).iter().filter_map(address) {
+            if let Some(count) = internal.get_mut(&child) {
+                *count += 1;
+            }
+        }
+    }
+
+    // Anything with more references than that is held from outside, along
+    // with `copies` itself.
+    let mut live = HashSet::new();
+    let mut gray: Vec<Value> = copies
+        .iter()
+        .filter(|copy| strong_count(copy) > internal[&address(copy).unwrap()] + 1)
+        .cloned()
+        .collect();
+    while let Some(copy) = gray.pop() {
+        let copy_address = address(&copy).unwrap();
+        if internal.contains_key(&copy_address) && live.insert(copy_address) {
+            gray.extend(
+                children(&copy)
+                    .into_iter()
+                    .filter(|child| address(child).is_some()),
+            );
+        }
+    }
+
+    for copy in &copies {
+        if !live.contains(&address(copy).unwrap()) {
+            match copy {
+                Value::List(list) => drop(list.replace(Vec::new())),
+                Value::Map(map) => map.clear(),
+                _ => {}
+            }
+        }
+    }
+}
+
+// Identifies a list or map, the only values that can refer to others.
+fn address(value: &Value) -> Option<*const ()> {
+    match value {
+        Value::List(list) => Some(Rc::as_ptr(list) as *const ()),
+        Value::Map(map) => Some(Rc::as_ptr(map) as *const ()),
+        _ => None,
+    }
+}
+
+fn strong_count(value: &Value) -> usize {
+    match value {
+        Value::List(list) => Rc::strong_count(list),
+        Value::Map(map) => Rc::strong_count(map),
+        _ => 0,
+    }
+}
+
+fn children(value: &Value) -> Vec<Value> {
+    match value {
+        Value::List(list) => list.elements().clone(),
+        Value::Map(map) => map
+            .entries()
+            .into_iter()
+            .flat_map(|(key, value)| [key, value])
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// A value that can be hashed, for map keys. Two keys are equal exactly when
+/// their values are `==`: numbers and strings by value (so `0` and `-0` are
+/// the same key), and everything else by identity.
+///
+/// `NaN` isn't `==` to itself, so it can't be a key.
+#[derive(Debug, Clone)]
+pub struct HashKey(Value);
+
+impl HashKey {
+    pub fn new(value: Value) -> anyhow::Result<Self> {
+        if matches!(value, Value::Number(number) if number.is_nan()) {
+            anyhow::bail!("Map keys can't be NaN.");
+        }
+        Ok(HashKey(value))
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.0
+    }
+}
+
+impl PartialEq for HashKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+// `new` keeps out NaN, the only value that isn't equal to itself.
+impl Eq for HashKey {}
+
+impl Hash for HashKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(&self.0).hash(state);
+        match &self.0 {
+            Value::Nil => {}
+            Value::Bool(bb) => bb.hash(state),
+            // `-0 == 0`, so both hash like `0`.
+            Value::Number(number) if *number == 0.0 => 0u64.hash(state),
+            Value::Number(number) => number.to_bits().hash(state),
+            Value::String(ss) => ss.hash(state),
+            Value::List(list) => Rc::as_ptr(list).hash(state),
+            Value::Map(map) => Rc::as_ptr(map).hash(state),
+            Value::Function(function) => Rc::as_ptr(function).hash(state),
+            Value::Closure(closure) => Rc::as_ptr(closure).hash(state),
+            Value::Native(native) => Rc::as_ptr(native).hash(state),
+            Value::Namespace(namespace) => Rc::as_ptr(namespace).hash(state),
+            Value::NativeClass(class) => Rc::as_ptr(class).hash(state),
+            Value::NativeInstance(instance) => Rc::as_ptr(instance).hash(state),
+        }
+    }
+}
+
+// Conversions for hosts embedding Lox, see `embed`.
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Nil
+    }
+}
+
+impl From<bool> for Value {
+    fn from(bb: bool) -> Self {
+        Value::Bool(bb)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(number: f64) -> Self {
+        Value::Number(number)
+    }
+}
+
+// Lox only has doubles, so this is lossless, unlike `i64`.
+impl From<i32> for Value {
+    fn from(number: i32) -> Self {
+        Value::Number(number.into())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(ss: &str) -> Self {
+        Value::String(ss.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(ss: String) -> Self {
+        Value::String(ss.into())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(elements: Vec<T>) -> Self {
+        let elements = elements.into_iter().map(Into::into).collect();
+        Value::List(Rc::new(List::new(elements)))
+    }
+}
+
+/// `None` is `nil`.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(option: Option<T>) -> Self {
+        option.map_or(Value::Nil, Into::into)
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> anyhow::Result<Self> {
+        match value {
+            Value::Bool(bb) => Ok(bb),
+            _ => Err(type_mismatch("bool", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> anyhow::Result<Self> {
+        match value {
+            Value::Number(number) => Ok(number),
+            _ => Err(type_mismatch("number", &value)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> anyhow::Result<Self> {
+        match value {
+            Value::String(ss) => Ok(ss.to_string()),
+            _ => Err(type_mismatch("string", &value)),
+        }
+    }
+}
+
+fn type_mismatch(expected: &str, value: &Value) -> anyhow::Error {
+    anyhow::anyhow!("Expected a {} but got a {}.", expected, value.type_name())
+}
+
+// Operator semantics live here so that every backend agrees on them. Errors
+// are bare messages, since only the caller knows where the operator was.
+
+pub fn unary(operator: UnaryOp, right: Value) -> Result<Value, &'static str> {
+    match (operator, right) {
+        (UnaryOp::Plus, Value::Number(number)) => Ok(Value::Number(number)),
+        (UnaryOp::Minus, Value::Number(number)) => Ok(Value::Number(-number)),
+        _ => Err("Operand must be a number."),
+    }
+}
+
+pub fn binary(operator: BinaryOp, left: Value, right: Value) -> Result<Value, &'static str> {
+    use BinaryOp::*;
+    use Value::*;
+    let value = match (operator, left, right) {
+        (Comma, _, right) => right,
+
+        (Equal, left, right) => Bool(left == right),
+        (NotEqual, left, right) => Bool(left != right),
+
+        (Add, Number(left), Number(right)) => Number(left + right),
+        (Add, String(left), String(right)) => String((left.to_string() + &right).into()),
+        (Add, _, _) => return Err("Operands must be two numbers or two strings."),
+
+        (Subtract, Number(left), Number(right)) => Number(left - right),
+        (Multiply, Number(left), Number(right)) => Number(left * right),
+        (Divide, Number(left), Number(right)) => Number(left / right),
+        (Greater, Number(left), Number(right)) => Bool(left > right),
+        (GreaterEqual, Number(left), Number(right)) => Bool(left >= right),
+        (Less, Number(left), Number(right)) => Bool(left < right),
+        (LessEqual, Number(left), Number(right)) => Bool(left <= right),
+        _ => return Err("Operands must be numbers."),
+    };
+    Ok(value)
+}
+
+/// Calls `callee` with already evaluated arguments. Lox functions run Lox
+/// code, so they're called by the backend that made them instead.
+pub fn call(callee: &Value, arguments: &[Value]) -> anyhow::Result<Value> {
+    match callee {
+        Value::Function(_) | Value::Closure(_) => {
+            anyhow::bail!("Can't call a function made by another interpreter.")
+        }
+        Value::Native(native) => native.call(arguments),
+        Value::NativeClass(class) => {
+            Ok(Value::NativeInstance(Rc::new(class.construct(arguments)?)))
+        }
+        _ => anyhow::bail!("Can only call functions and classes."),
+    }
+}
+
+/// Reads `object.name`.
+pub fn get_property(object: &Value, name: Symbol) -> anyhow::Result<Value> {
+    match object {
+        Value::Namespace(namespace) => namespace
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Undefined property '{}'.", name)),
+        Value::NativeInstance(instance) => instance.get(name),
+        Value::List(list) => list.method(name),
+        Value::Map(map) => map.method(name),
+        _ => anyhow::bail!("Only instances, lists, maps and namespaces have properties."),
+    }
+}
+
+/// Reads `object[index]`.
+pub fn get_index(object: &Value, index: &Value) -> anyhow::Result<Value> {
+    match object {
+        Value::List(list) => list.get(index),
+        Value::Map(map) => map.get(index),
+        _ => anyhow::bail!("Only lists and maps can be indexed."),
+    }
+}
+
+/// Runs `object[index] = value`.
+pub fn set_index(object: &Value, index: &Value, value: Value) -> anyhow::Result<()> {
+    match object {
+        Value::List(list) => list.set(index, value),
+        Value::Map(map) => map.insert(index.clone(), value),
+        _ => anyhow::bail!("Only lists and maps can be indexed."),
+    }
+}
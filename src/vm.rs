@@ -0,0 +1,1425 @@
+use crate::chunk::Chunk;
+use crate::chunk::OpCode;
+use crate::chunk::Prototype;
+use crate::chunk::SCRIPT_NAME;
+use crate::disassembler;
+use crate::expr::BinaryOp;
+use crate::expr::UnaryOp;
+use crate::heap::GcStats;
+use crate::heap::Heap;
+use crate::heap::ObjRef;
+use crate::heap::Object;
+use crate::limits::LimitExceeded;
+use crate::limits::LimitKind;
+use crate::limits::Limits;
+use crate::list;
+use crate::list::List;
+use crate::map;
+use crate::map::Map;
+use crate::map::OrderedMap;
+use crate::native::NativeRegistry;
+use crate::stdlib;
+use crate::symbol::InternedString;
+use crate::symbol::Symbol;
+use crate::value;
+use crate::value::Value;
+use crate::vm_value::Unpacked;
+use crate::vm_value::VmKey;
+use crate::vm_value::VmValue;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Stack-based bytecode interpreter. Like `Interpreter`, globals are kept
+/// between calls to `run`.
+///
+/// Strings, lists, maps and closures are allocated on a garbage collected
+/// `Heap`. The roots are the stack, the globals, the constants of the running
+/// chunks and the open upvalues.
+///
+/// Natives are looked up in a `NativeRegistry` when a global isn't defined,
+/// and called with copies of the arguments as `value::Value`s, so they behave
+/// the same as in the tree-walker.
+pub struct Vm {
+    stack: Vec<VmValue>,
+    globals: HashMap<Symbol, VmValue>,
+    natives: NativeRegistry,
+    // The constants of every chunk that's running, allocated on the heap when
+    // it's called so that `Constant` doesn't have to. Each frame knows where
+    // its own start.
+    constants: Vec<VmValue>,
+    // The names among `constants`, as `Symbol`s for looking up globals, made
+    // the first time they're used.
+    names: Vec<Option<Symbol>>,
+    // Upvalues that still point at the stack, so that closures made in the
+    // same scope share them.
+    // NOTE: searched linearly, but there are rarely more than a few.
+    open_upvalues: Vec<ObjRef>,
+    heap: Heap,
+    limits: Limits,
+    call_depth: usize,
+    trace: bool,
+    // Where `print` and tracing write.
+    stdout: Box<dyn Write>,
+    stderr: Box<dyn Write>,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::with_natives(stdlib::core())
+    }
+
+    pub fn with_natives(natives: NativeRegistry) -> Self {
+        Vm {
+            stack: Vec::new(),
+            globals: HashMap::new(),
+            natives,
+            constants: Vec::new(),
+            names: Vec::new(),
+            open_upvalues: Vec::new(),
+            heap: Heap::new(),
+            limits: Limits::default(),
+            call_depth: 0,
+            trace: false,
+            stdout: Box::new(std::io::stdout()),
+            stderr: Box::new(std::io::stderr()),
+        }
+    }
+
+    /// Sends the output of `print` somewhere other than stdout.
+    pub fn set_stdout(&mut self, stdout: impl Write + 'static) {
+        self.stdout = Box::new(stdout);
+    }
+
+    /// Sends tracing somewhere other than stderr.
+    pub fn set_stderr(&mut self, stderr: impl Write + 'static) {
+        self.stderr = Box::new(stderr);
+    }
+
+    /// A global the program defined, or else a native.
+    pub fn get_global(&self, name: Symbol) -> Option<Value> {
+        match self.globals.get(&name) {
+            Some(value) => Some(self.to_value(*value)),
+            None => self.natives.get(name),
+        }
+    }
+
+    /// Defines a global, or replaces it if it already exists.
+    pub fn set_global(&mut self, name: Symbol, value: Value) {
+        let value = self.from_value(value);
+        self.globals.insert(name, value);
+    }
+
+    /// When tracing, the value stack and each instruction are printed to
+    /// stderr before the instruction runs.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Collect garbage on every allocation, see `Heap::set_stress`.
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.heap.set_stress(stress);
+    }
+
+    pub fn gc_stats(&self) -> &GcStats {
+        self.heap.stats()
+    }
+
+    /// Runs a chunk from the start, stopping at the first runtime error. Each
+    /// call gets a fresh step budget.
+    pub fn run(&mut self, chunk: &Chunk) -> anyhow::Result<()> {
+        self.evaluate(chunk).map(|_| ())
+    }
+
+    /// Like `run`, but returns what's left on top of the stack when the chunk
+    /// returns, or `nil` if nothing is. See `compiler::compile_expression`.
+    pub fn evaluate(&mut self, chunk: &Chunk) -> anyhow::Result<Value> {
+        let script = Rc::new(Closure {
+            prototype: Rc::new(Prototype {
+                name: SCRIPT_NAME.to_string(),
+                arity: 0,
+                upvalues: Vec::new(),
+                chunk: chunk.clone(),
+            }),
+            upvalues: Vec::new(),
+        });
+        // Unlike a function's, the script's locals start at slot 0.
+        let base = self.stack.len();
+        let constants = self.push_constants(chunk);
+        self.run_frame(CallFrame {
+            closure: script,
+            ip: 0,
+            base,
+            constants,
+        })
+    }
+
+    /// Calls a function, e.g. one the program stored in a global.
+    pub fn call(&mut self, callee: &Value, arguments: &[Value]) -> anyhow::Result<Value> {
+        let Value::Closure(closure) = callee else {
+            return value::call(callee, arguments);
+        };
+        let object = self.heap.find_shared(closure);
+        let Some(object) =
+            object.filter(|object| matches!(self.heap.get(*object), Object::Closure(_)))
+        else {
+            anyhow::bail!("Can't call a function made by another interpreter.");
+        };
+        let prototype = &closure.prototype;
+        if arguments.len() != prototype.arity as usize {
+            anyhow::bail!(
+                "Expected {} arguments but got {}.",
+                prototype.arity,
+                arguments.len()
+            );
+        }
+        if self
+            .limits
+            .max_call_depth
+            .is_some_and(|max| self.call_depth >= max)
+        {
+            return Err(LimitExceeded {
+                kind: LimitKind::CallDepth,
+                line_number: prototype.chunk.line_number(0),
+            }
+            .into());
+        }
+
+        let base = self.stack.len();
+        self.stack.push(VmValue::object(object));
+        for argument in arguments {
+            let argument = self.from_value(argument.clone());
+            self.stack.push(argument);
+        }
+        let constants = self.push_constants(&prototype.chunk);
+        self.call_depth += 1;
+        let result = self.run_frame(CallFrame {
+            closure: closure.clone(),
+            ip: 0,
+            base,
+            constants,
+        });
+        self.call_depth -= 1;
+        result
+    }
+
+    // Returns where the chunk's constants start.
+    fn push_constants(&mut self, chunk: &Chunk) -> usize {
+        let start = self.constants.len();
+        for constant in &chunk.constants {
+            let constant = self.from_value(constant.clone());
+            self.constants.push(constant);
+        }
+        self.names.resize(self.constants.len(), None);
+        start
+    }
+
+    // Runs until `frame` returns, then cleans up after it.
+    fn run_frame(&mut self, frame: CallFrame) -> anyhow::Result<Value> {
+        let call_depth = self.call_depth;
+        let result = self.execute(frame);
+        // Whatever was left over belongs to the failed code.
+        self.close_upvalues(0);
+        self.stack.clear();
+        self.constants.clear();
+        self.names.clear();
+        self.call_depth = call_depth;
+        result
+    }
+
+    /// Copies a value off the heap. Strings share their contents with the
+    /// heap's, so this doesn't copy them, but lists and maps are copied along
+    /// with everything in them, keeping any sharing between them.
+    pub fn to_value(&self, value: VmValue) -> Value {
+        self.copy(value, &mut HashMap::new())
+    }
+
+    // `copies` has the lists and maps copied so far, by the object they were
+    // copied from.
+    fn copy(&self, value: VmValue, copies: &mut HashMap<ObjRef, Value>) -> Value {
+        let object = match value.unpack() {
+            Unpacked::Nil => return Value::Nil,
+            Unpacked::Bool(bb) => return Value::Bool(bb),
+            Unpacked::Number(number) => return Value::Number(number),
+            Unpacked::Object(object) => object,
+        };
+        if let Some(copy) = copies.get(&object) {
+            return copy.clone();
+        }
+        match self.heap.get(object) {
+            Object::String(ss) => Value::String(ss.clone()),
+            Object::Native(native) => Value::Native(native.clone()),
+            Object::Namespace(namespace) => Value::Namespace(namespace.clone()),
+            Object::NativeClass(class) => Value::NativeClass(class.clone()),
+            Object::NativeInstance(instance) => Value::NativeInstance(instance.clone()),
+            // Recorded before what's in them is copied, in case that refers
+            // back to them.
+            Object::List(elements) => {
+                let list = Rc::new(List::new(Vec::new()));
+                copies.insert(object, Value::List(list.clone()));
+                let elements = elements
+                    .iter()
+                    .map(|element| self.copy(*element, copies))
+                    .collect();
+                list.replace(elements);
+                Value::List(list)
+            }
+            Object::Map(entries) => {
+                let map = Rc::new(Map::new());
+                copies.insert(object, Value::Map(map.clone()));
+                for (key, value) in entries.iter() {
+                    let key = self.copy(key.value(), copies);
+                    let value = self.copy(*value, copies);
+                    map.insert(key, value).expect("keys on the heap aren't NaN");
+                }
+                Value::Map(map)
+            }
+            Object::BoundMethod { receiver, name } => {
+                let receiver = self.copy(VmValue::object(*receiver), copies);
+                value::get_property(&receiver, *name).expect("only methods that exist are bound")
+            }
+            Object::Closure(closure) | Object::ForeignClosure(closure) => {
+                Value::Closure(closure.clone())
+            }
+            Object::Function(function) => Value::Function(function.clone()),
+            Object::Upvalue(_) => unreachable!("upvalues aren't values"),
+        }
+    }
+
+    // Runs `f` on copies of `values`, for code that works with `Value`s.
+    // Copies of lists and maps are broken up afterwards, unless `f` kept
+    // them, since reference counting would never free the ones that contain
+    // each other.
+    fn with_copies<R>(&self, values: &[VmValue], f: impl FnOnce(&[Value]) -> R) -> R {
+        let mut copies = HashMap::new();
+        let values: Vec<_> = values
+            .iter()
+            .map(|value| self.copy(*value, &mut copies))
+            .collect();
+        let result = f(&values);
+        drop(values);
+        value::break_cycles(copies.into_values());
+        result
+    }
+
+    /// Copies a value onto the heap, if it needs to be there. Like
+    /// `to_value`, lists and maps are copied along with everything in them.
+    /// Might collect garbage first, so anything the caller still needs must
+    /// be rooted.
+    pub fn from_value(&mut self, value: Value) -> VmValue {
+        // What's copied isn't rooted until it's all done, so this is the only
+        // chance to collect.
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.move_value(value, &mut HashMap::new())
+    }
+
+    // Like `copy`, the other way around. Never collects.
+    fn move_value(&mut self, value: Value, moved: &mut HashMap<*const (), ObjRef>) -> VmValue {
+        let object = match value {
+            Value::Nil => return VmValue::nil(),
+            Value::Bool(bb) => return VmValue::bool(bb),
+            Value::Number(number) => return VmValue::number(number),
+            Value::String(ss) => self.heap.intern(ss),
+            Value::Native(native) => self.heap.alloc(Object::Native(native)),
+            Value::Namespace(namespace) => self.heap.alloc(Object::Namespace(namespace)),
+            Value::NativeClass(class) => self.heap.alloc(Object::NativeClass(class)),
+            Value::NativeInstance(instance) => self.heap.alloc(Object::NativeInstance(instance)),
+            Value::List(list) => {
+                if let Some(object) = moved.get(&(Rc::as_ptr(&list) as *const ())) {
+                    return VmValue::object(*object);
+                }
+                let object = self.heap.alloc(Object::List(Vec::new()));
+                moved.insert(Rc::as_ptr(&list) as *const (), object);
+                let elements = list
+                    .elements()
+                    .iter()
+                    .map(|element| self.move_value(element.clone(), moved))
+                    .collect();
+                *self.list_mut(object) = elements;
+                self.heap.resize(object);
+                object
+            }
+            Value::Map(map) => {
+                if let Some(object) = moved.get(&(Rc::as_ptr(&map) as *const ())) {
+                    return VmValue::object(*object);
+                }
+                let object = self.heap.alloc(Object::Map(OrderedMap::default()));
+                moved.insert(Rc::as_ptr(&map) as *const (), object);
+                for (key, value) in map.entries() {
+                    let key = VmKey::new(self.move_value(key, moved)).expect("keys aren't NaN");
+                    let value = self.move_value(value, moved);
+                    self.map_mut(object).insert(key, value);
+                }
+                self.heap.resize(object);
+                object
+            }
+            // Only closures this VM made are on its heap already.
+            Value::Closure(closure) => match self.heap.find_shared(&closure) {
+                Some(object) => object,
+                None => self.heap.alloc(Object::ForeignClosure(closure)),
+            },
+            Value::Function(function) => self.heap.alloc(Object::Function(function)),
+        };
+        VmValue::object(object)
+    }
+
+    // Strings are interned on the heap, so that `==` can compare `ObjRef`s.
+    fn intern_string(&mut self, string: InternedString) -> ObjRef {
+        if let Some(object) = self.heap.find_string(&string) {
+            return object;
+        }
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc_string(string)
+    }
+
+    fn alloc(&mut self, object: Object) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        self.heap.alloc(object)
+    }
+
+    // How `print` shows a value.
+    fn display(&self, value: VmValue) -> String {
+        match value.as_object().map(|object| self.heap.get(object)) {
+            Some(Object::String(ss)) => ss.to_string(),
+            _ => self.with_copies(&[value], |values| values[0].to_string()),
+        }
+    }
+
+    fn collect_garbage(&mut self) {
+        for value in &self.stack {
+            self.heap.mark_value(*value);
+        }
+        for value in self.globals.values() {
+            self.heap.mark_value(*value);
+        }
+        for value in &self.constants {
+            self.heap.mark_value(*value);
+        }
+        // Closures refer to them, but a closure might not have been stored
+        // anywhere yet.
+        for upvalue in &self.open_upvalues {
+            self.heap.mark_object(*upvalue);
+        }
+        self.heap.collect();
+    }
+
+    fn read_name(&mut self, chunk: &Chunk, frame: &mut CallFrame) -> Symbol {
+        let constant = chunk.code[frame.ip] as usize;
+        frame.ip += 1;
+        let slot = &mut self.names[frame.constants + constant];
+        *slot.get_or_insert_with(|| match &chunk.constants[constant] {
+            Value::String(name) => Symbol::intern(name),
+            other => panic!("variable name should be a string, found {:?}", other),
+        })
+    }
+
+    fn execute(&mut self, mut frame: CallFrame) -> anyhow::Result<Value> {
+        // The frames that called `frame`, innermost last.
+        let mut callers: Vec<CallFrame> = Vec::new();
+        let mut steps: u64 = 0;
+        let max_steps = self.limits.max_steps.unwrap_or(u64::MAX);
+        let max_heap_bytes = self.limits.max_heap_bytes.unwrap_or(usize::MAX);
+        loop {
+            // A handle of our own, since a call or return replaces `frame`.
+            let closure = frame.closure.clone();
+            let chunk = &closure.prototype.chunk;
+            if self.trace {
+                let stack = self.with_copies(&self.stack, disassembler::stack_to_string);
+                let stack = format!("          {}", stack);
+                writeln!(self.stderr, "{}", stack.trim_end())?;
+                writeln!(
+                    self.stderr,
+                    "{}",
+                    disassembler::disassemble_instruction(chunk, frame.ip).0
+                )?;
+            }
+
+            let op_offset = frame.ip;
+            let op = OpCode::try_from(chunk.code[frame.ip])?;
+            frame.ip += 1;
+            let runtime_error = |message: &str| {
+                anyhow::anyhow!("[line {}] {}", chunk.line_number(op_offset), message)
+            };
+            let limit_exceeded = |kind| {
+                anyhow::Error::from(LimitExceeded {
+                    kind,
+                    line_number: chunk.line_number(op_offset),
+                })
+            };
+
+            steps += 1;
+            if steps > max_steps {
+                return Err(limit_exceeded(LimitKind::Steps));
+            }
+
+            match op {
+                OpCode::Constant => {
+                    let constant = chunk.code[frame.ip];
+                    frame.ip += 1;
+                    self.stack
+                        .push(self.constants[frame.constants + constant as usize]);
+                }
+                OpCode::Nil => self.stack.push(VmValue::nil()),
+                OpCode::True => self.stack.push(VmValue::bool(true)),
+                OpCode::False => self.stack.push(VmValue::bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.local_slot(chunk, &mut frame.ip, frame.base)?;
+                    self.stack.push(self.stack[slot]);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.local_slot(chunk, &mut frame.ip, frame.base)?;
+                    self.stack[slot] = self.peek();
+                }
+                OpCode::GetUpvalue => {
+                    let index = chunk.code[frame.ip] as usize;
+                    frame.ip += 1;
+                    let value = match self.upvalue(frame.closure.upvalues[index]) {
+                        Upvalue::Open(slot) => self.stack[*slot],
+                        Upvalue::Closed(value) => *value,
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = chunk.code[frame.ip] as usize;
+                    frame.ip += 1;
+                    let value = self.peek();
+                    let upvalue = frame.closure.upvalues[index];
+                    match self.upvalue_mut(upvalue) {
+                        Upvalue::Open(slot) => {
+                            let slot = *slot;
+                            self.stack[slot] = value;
+                        }
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::Closure => {
+                    let index = chunk.code[frame.ip] as usize;
+                    frame.ip += 1;
+                    let prototype = chunk.functions[index].clone();
+                    let mut upvalues = Vec::with_capacity(prototype.upvalues.len());
+                    for capture in &prototype.upvalues {
+                        let upvalue = if capture.is_local {
+                            let slot = frame.base + capture.index as usize;
+                            if slot >= self.stack.len() {
+                                anyhow::bail!(
+                                    "Invalid bytecode: no local in slot {}.",
+                                    capture.index
+                                );
+                            }
+                            self.capture_upvalue(slot)
+                        } else {
+                            frame.closure.upvalues[capture.index as usize]
+                        };
+                        upvalues.push(upvalue);
+                    }
+                    let closure = Closure {
+                        prototype,
+                        upvalues,
+                    };
+                    // The upvalues are all reachable until the closure is:
+                    // they're open, or the running closure's.
+                    let closure = self.alloc(Object::Closure(Rc::new(closure)));
+                    self.stack.push(VmValue::object(closure));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_name(chunk, &mut frame);
+                    let value = match self.globals.get(&name) {
+                        Some(value) => *value,
+                        None => {
+                            let native = self.natives.get(name).ok_or_else(|| {
+                                runtime_error(&format!("Undefined variable '{}'.", name))
+                            })?;
+                            self.from_value(native)
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_name(chunk, &mut frame);
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_name(chunk, &mut frame);
+                    let value = self.peek();
+                    match self.globals.get_mut(&name) {
+                        Some(slot) => *slot = value,
+                        None => {
+                            return Err(runtime_error(&format!("Undefined variable '{}'.", name)))
+                        }
+                    }
+                }
+                OpCode::Equal
+                | OpCode::NotEqual
+                | OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let result = self.binary(op, left, right).map_err(runtime_error)?;
+                    self.stack.push(result);
+                }
+                OpCode::Negate | OpCode::Positive => {
+                    let operator = if op == OpCode::Negate {
+                        UnaryOp::Minus
+                    } else {
+                        UnaryOp::Plus
+                    };
+                    let right = self.pop();
+                    let result = self
+                        .with_copies(&[right], |values| value::unary(operator, values[0].clone()))
+                        .map_err(runtime_error)?;
+                    let result = self.from_value(result);
+                    self.stack.push(result);
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    let string = self.display(value);
+                    writeln!(self.stdout, "{}", string)?;
+                }
+                OpCode::Jump => {
+                    let offset = read_u16(chunk, &mut frame.ip);
+                    frame.ip += offset as usize;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = read_u16(chunk, &mut frame.ip);
+                    if !self.peek().is_truthy() {
+                        frame.ip += offset as usize;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = read_u16(chunk, &mut frame.ip);
+                    frame.ip -= offset as usize;
+                }
+                OpCode::Return => {
+                    // The script might not leave anything, but a function
+                    // always does.
+                    let result = self.stack.pop().unwrap_or(VmValue::nil());
+                    let Some(caller) = callers.pop() else {
+                        return Ok(self.to_value(result));
+                    };
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    self.constants.truncate(frame.constants);
+                    self.names.truncate(frame.constants);
+                    self.call_depth -= 1;
+                    self.stack.push(result);
+                    frame = caller;
+                }
+                OpCode::Call => {
+                    let count = chunk.code[frame.ip] as usize;
+                    frame.ip += 1;
+                    let Some(callee_slot) = self.stack.len().checked_sub(count + 1) else {
+                        anyhow::bail!("Invalid bytecode: call with {} arguments.", count);
+                    };
+                    if self
+                        .limits
+                        .max_call_depth
+                        .is_some_and(|max| self.call_depth >= max)
+                    {
+                        return Err(limit_exceeded(LimitKind::CallDepth));
+                    }
+                    if let Some(Object::Closure(closure)) = self.stack[callee_slot]
+                        .as_object()
+                        .map(|callee| self.heap.get(callee))
+                    {
+                        let closure = closure.clone();
+                        let arity = closure.prototype.arity as usize;
+                        if count != arity {
+                            return Err(runtime_error(&format!(
+                                "Expected {} arguments but got {}.",
+                                arity, count
+                            )));
+                        }
+                        let constants = self.push_constants(&closure.prototype.chunk);
+                        self.call_depth += 1;
+                        let callee = CallFrame {
+                            closure,
+                            ip: 0,
+                            base: callee_slot,
+                            constants,
+                        };
+                        callers.push(std::mem::replace(&mut frame, callee));
+                        continue;
+                    }
+
+                    let arguments = self.stack[callee_slot + 1..].to_vec();
+                    let result = match self.stack[callee_slot]
+                        .as_object()
+                        .map(|callee| self.heap.get(callee))
+                    {
+                        Some(Object::BoundMethod { receiver, name }) => {
+                            self.call_method(*receiver, *name, &arguments)
+                        }
+                        _ => self.call_native(self.stack[callee_slot], &arguments),
+                    };
+                    let result = result.map_err(|err| runtime_error(&err.to_string()))?;
+                    self.stack.truncate(callee_slot);
+                    self.stack.push(result);
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_name(chunk, &mut frame);
+                    let property = self
+                        .get_property(self.peek(), name)
+                        .map_err(|err| runtime_error(&err.to_string()))?;
+                    self.pop();
+                    self.stack.push(property);
+                }
+                OpCode::BuildList => {
+                    let count = chunk.code[frame.ip] as usize;
+                    frame.ip += 1;
+                    let Some(start) = self.stack.len().checked_sub(count) else {
+                        anyhow::bail!("Invalid bytecode: list with {} elements.", count);
+                    };
+                    // The elements stay on the stack until the list is
+                    // allocated.
+                    let elements = self.stack[start..].to_vec();
+                    let list = self.alloc(Object::List(elements));
+                    self.stack.truncate(start);
+                    self.stack.push(VmValue::object(list));
+                }
+                OpCode::BuildMap => {
+                    let count = chunk.code[frame.ip] as usize;
+                    frame.ip += 1;
+                    let Some(start) = self.stack.len().checked_sub(count * 2) else {
+                        anyhow::bail!("Invalid bytecode: map with {} entries.", count);
+                    };
+                    let mut map = OrderedMap::default();
+                    for entry in self.stack[start..].chunks(2) {
+                        let key =
+                            VmKey::new(entry[0]).map_err(|err| runtime_error(&err.to_string()))?;
+                        map.insert(key, entry[1]);
+                    }
+                    let map = self.alloc(Object::Map(map));
+                    self.stack.truncate(start);
+                    self.stack.push(VmValue::object(map));
+                }
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let element = self
+                        .get_index(object, index)
+                        .map_err(|err| runtime_error(&err.to_string()))?;
+                    self.stack.push(element);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    self.set_index(object, index, value)
+                        .map_err(|err| runtime_error(&err.to_string()))?;
+                    self.stack.push(value);
+                }
+            }
+
+            // Only whatever's still reachable counts against the limit.
+            if self.heap.live_bytes() > max_heap_bytes {
+                self.collect_garbage();
+                if self.heap.live_bytes() > max_heap_bytes {
+                    return Err(limit_exceeded(LimitKind::HeapBytes));
+                }
+            }
+        }
+    }
+
+    fn binary(
+        &mut self,
+        op: OpCode,
+        left: VmValue,
+        right: VmValue,
+    ) -> Result<VmValue, &'static str> {
+        match (op, left.as_object(), right.as_object()) {
+            // Objects are only equal to themselves, and equal strings are
+            // the same object.
+            (OpCode::Equal, _, _) => Ok(VmValue::bool(left == right)),
+            (OpCode::NotEqual, _, _) => Ok(VmValue::bool(left != right)),
+            (OpCode::Add, Some(left_object), Some(right_object)) => {
+                let (Object::String(left), Object::String(right)) =
+                    (self.heap.get(left_object), self.heap.get(right_object))
+                else {
+                    return Err("Operands must be two numbers or two strings.");
+                };
+                // The operands are already off the stack, but this copy
+                // doesn't need them to survive a collection.
+                let string = format!("{}{}", left, right);
+                Ok(VmValue::object(self.intern_string(string.into())))
+            }
+            // Only numbers are left, or the error for whatever isn't.
+            _ => {
+                let result = self.with_copies(&[left, right], |values| {
+                    value::binary(binary_operator(op), values[0].clone(), values[1].clone())
+                })?;
+                Ok(self.from_value(result))
+            }
+        }
+    }
+
+    // Natives are called with copies of the arguments, so they behave the
+    // same as in the tree-walker.
+    // NOTE: that makes passing a list or map to a native linear in its size,
+    // e.g. `len(xs)`, unlike the `xs.len()` method.
+    fn call_native(&mut self, callee: VmValue, arguments: &[VmValue]) -> anyhow::Result<VmValue> {
+        let mut copies = HashMap::new();
+        let callee = self.copy(callee, &mut copies);
+        let arguments: Vec<_> = arguments
+            .iter()
+            .map(|argument| self.copy(*argument, &mut copies))
+            .collect();
+        self.call_depth += 1;
+        let result = value::call(&callee, &arguments);
+        self.call_depth -= 1;
+        drop((callee, arguments));
+        // The arguments stay on the stack until the result is on the heap,
+        // and the copies stay intact until it's been copied, since it might
+        // refer to them.
+        let result = result.map(|result| self.from_value(result));
+        value::break_cycles(copies.into_values());
+        result
+    }
+
+    // `object.name`. A list or map method is bound here, and called by
+    // `call_method`.
+    fn get_property(&mut self, object: VmValue, name: Symbol) -> anyhow::Result<VmValue> {
+        let method = match object.as_object().map(|object| self.heap.get(object)) {
+            Some(Object::List(_)) => list_method(name).map(|_| ()),
+            Some(Object::Map(_)) => map_method(name).map(|_| ()),
+            _ => {
+                let object = self.to_value(object);
+                let property = value::get_property(&object, name)?;
+                return Ok(self.from_value(property));
+            }
+        };
+        if method.is_none() {
+            anyhow::bail!("Undefined property '{}'.", name);
+        }
+        let receiver = object.as_object().unwrap();
+        let method = self.alloc(Object::BoundMethod { receiver, name });
+        Ok(VmValue::object(method))
+    }
+
+    fn call_method(
+        &mut self,
+        receiver: ObjRef,
+        name: Symbol,
+        arguments: &[VmValue],
+    ) -> anyhow::Result<VmValue> {
+        let (arity, method) = match self.heap.get(receiver) {
+            Object::List(_) => list_method(name),
+            _ => map_method(name),
+        }
+        .expect("only methods that exist are bound");
+        if arguments.len() != arity as usize {
+            anyhow::bail!("Expected {} arguments but got {}.", arity, arguments.len());
+        }
+        let result = method(self, receiver, arguments)?;
+        self.heap.resize(receiver);
+        Ok(result)
+    }
+
+    // `object[index]`, like `value::get_index`.
+    fn get_index(&self, object: VmValue, index: VmValue) -> anyhow::Result<VmValue> {
+        match object.as_object().map(|object| self.heap.get(object)) {
+            Some(Object::List(elements)) => {
+                Ok(elements[list::element_index(index.as_number(), elements.len())?])
+            }
+            Some(Object::Map(map)) => VmKey::new(index)
+                .ok()
+                .and_then(|key| map.get(&key).copied())
+                .ok_or_else(|| map::undefined_key(&self.display(index))),
+            _ => anyhow::bail!("Only lists and maps can be indexed."),
+        }
+    }
+
+    // `object[index] = value`, like `value::set_index`.
+    fn set_index(&mut self, object: VmValue, index: VmValue, value: VmValue) -> anyhow::Result<()> {
+        let Some(object) = object.as_object() else {
+            anyhow::bail!("Only lists and maps can be indexed.");
+        };
+        match self.heap.get_mut(object) {
+            Object::List(elements) => {
+                let index = list::element_index(index.as_number(), elements.len())?;
+                elements[index] = value;
+            }
+            Object::Map(map) => map.insert(VmKey::new(index)?, value),
+            _ => anyhow::bail!("Only lists and maps can be indexed."),
+        }
+        self.heap.resize(object);
+        Ok(())
+    }
+
+    fn elements(&self, list: ObjRef) -> &Vec<VmValue> {
+        match self.heap.get(list) {
+            Object::List(elements) => elements,
+            other => panic!("expected a list, found {:?}", other),
+        }
+    }
+
+    fn list_mut(&mut self, list: ObjRef) -> &mut Vec<VmValue> {
+        match self.heap.get_mut(list) {
+            Object::List(elements) => elements,
+            other => panic!("expected a list, found {:?}", other),
+        }
+    }
+
+    fn entries(&self, map: ObjRef) -> &OrderedMap<VmKey, VmValue> {
+        match self.heap.get(map) {
+            Object::Map(map) => map,
+            other => panic!("expected a map, found {:?}", other),
+        }
+    }
+
+    fn map_mut(&mut self, map: ObjRef) -> &mut OrderedMap<VmKey, VmValue> {
+        match self.heap.get_mut(map) {
+            Object::Map(map) => map,
+            other => panic!("expected a map, found {:?}", other),
+        }
+    }
+
+    // Compiled code always has the slot, but bytecode loaded from a file might
+    // not. Slots are relative to the frame's `base`.
+    fn local_slot(&self, chunk: &Chunk, ip: &mut usize, base: usize) -> anyhow::Result<usize> {
+        let slot = chunk.code[*ip] as usize;
+        *ip += 1;
+        if base + slot >= self.stack.len() {
+            anyhow::bail!("Invalid bytecode: no local in slot {}.", slot);
+        }
+        Ok(base + slot)
+    }
+
+    // The upvalue for a stack slot, shared with any other closure that
+    // captured it.
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let existing = self.open_upvalues.iter().find(
+            |upvalue| matches!(self.upvalue(**upvalue), Upvalue::Open(open) if *open == slot),
+        );
+        if let Some(upvalue) = existing {
+            return *upvalue;
+        }
+        let upvalue = self.alloc(Object::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    // Moves the values of slots `from` and above off the stack and into their
+    // upvalues, before the slots are popped.
+    fn close_upvalues(&mut self, from: usize) {
+        let mut open_upvalues = std::mem::take(&mut self.open_upvalues);
+        open_upvalues.retain(|upvalue| match *self.upvalue(*upvalue) {
+            Upvalue::Open(slot) if slot >= from => {
+                *self.upvalue_mut(*upvalue) = Upvalue::Closed(self.stack[slot]);
+                false
+            }
+            _ => true,
+        });
+        self.open_upvalues = open_upvalues;
+    }
+
+    fn upvalue(&self, upvalue: ObjRef) -> &Upvalue {
+        match self.heap.get(upvalue) {
+            Object::Upvalue(upvalue) => upvalue,
+            other => panic!("expected an upvalue, found {:?}", other),
+        }
+    }
+
+    fn upvalue_mut(&mut self, upvalue: ObjRef) -> &mut Upvalue {
+        match self.heap.get_mut(upvalue) {
+            Object::Upvalue(upvalue) => upvalue,
+            other => panic!("expected an upvalue, found {:?}", other),
+        }
+    }
+
+    // The compiler only emits balanced code, so popping an empty stack is a
+    // bug rather than a Lox error.
+    fn pop(&mut self) -> VmValue {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn peek(&self) -> VmValue {
+        *self.stack.last().expect("stack underflow")
+    }
+}
+
+// A list or map method. It's given the receiver, and arguments whose number
+// has already been checked. `call_method` resizes the receiver afterwards.
+type Method = fn(&mut Vm, ObjRef, &[VmValue]) -> anyhow::Result<VmValue>;
+
+// The same methods as `List::method`, for the VM's lists.
+fn list_method(name: Symbol) -> Option<(u8, Method)> {
+    let method: (u8, Method) = match name.as_str() {
+        "push" => (1, |vm, list, arguments| {
+            vm.list_mut(list).push(arguments[0]);
+            Ok(VmValue::nil())
+        }),
+        "pop" => (0, |vm, list, _| {
+            vm.list_mut(list)
+                .pop()
+                .ok_or_else(|| anyhow::anyhow!("Can't pop from an empty list."))
+        }),
+        "len" => (0, |vm, list, _| {
+            Ok(VmValue::number(vm.elements(list).len() as f64))
+        }),
+        "insert" => (2, |vm, list, arguments| {
+            let elements = vm.list_mut(list);
+            let index = list::element_index(arguments[0].as_number(), elements.len() + 1)?;
+            elements.insert(index, arguments[1]);
+            Ok(VmValue::nil())
+        }),
+        "remove" => (1, |vm, list, arguments| {
+            let elements = vm.list_mut(list);
+            let index = list::element_index(arguments[0].as_number(), elements.len())?;
+            Ok(elements.remove(index))
+        }),
+        "slice" => (2, |vm, list, arguments| {
+            let elements = vm.list_mut(list);
+            let start = list::slice_bound(arguments[0].as_number(), elements.len())?;
+            let end = list::slice_bound(arguments[1].as_number(), elements.len())?.max(start);
+            // The receiver is still on the stack, keeping the elements alive.
+            let slice = elements[start..end].to_vec();
+            Ok(VmValue::object(vm.alloc(Object::List(slice))))
+        }),
+        _ => return None,
+    };
+    Some(method)
+}
+
+// The same methods as `Map::method`, for the VM's maps.
+fn map_method(name: Symbol) -> Option<(u8, Method)> {
+    let method: (u8, Method) = match name.as_str() {
+        "keys" => (0, |vm, map, _| {
+            let keys = vm.entries(map).iter().map(|(key, _)| key.value()).collect();
+            Ok(VmValue::object(vm.alloc(Object::List(keys))))
+        }),
+        "values" => (0, |vm, map, _| {
+            let values = vm.entries(map).iter().map(|(_, value)| *value).collect();
+            Ok(VmValue::object(vm.alloc(Object::List(values))))
+        }),
+        "has" => (1, |vm, map, arguments| {
+            let has = VmKey::new(arguments[0]).is_ok_and(|key| vm.entries(map).contains_key(&key));
+            Ok(VmValue::bool(has))
+        }),
+        "remove" => (1, |vm, map, arguments| {
+            let removed = VmKey::new(arguments[0])
+                .ok()
+                .and_then(|key| vm.map_mut(map).remove(&key));
+            removed.ok_or_else(|| map::undefined_key(&vm.display(arguments[0])))
+        }),
+        "len" => (0, |vm, map, _| {
+            Ok(VmValue::number(vm.entries(map).len() as f64))
+        }),
+        _ => return None,
+    };
+    Some(method)
+}
+
+/// A function made by the VM, along with the variables it captured.
+pub struct Closure {
+    prototype: Rc<Prototype>,
+    // `Object::Upvalue`s on the heap of the VM that made the closure.
+    upvalues: Vec<ObjRef>,
+}
+
+impl Closure {
+    pub(crate) fn upvalues(&self) -> &[ObjRef] {
+        &self.upvalues
+    }
+}
+
+/// A captured variable. It stays on the stack while the scope that declared
+/// it is running, and moves into the upvalue once that scope ends.
+#[derive(Debug, PartialEq)]
+pub enum Upvalue {
+    /// The stack slot it's in.
+    Open(usize),
+    Closed(VmValue),
+}
+
+// Closures are only equal to themselves, like other objects.
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::fmt::Debug for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<fn>")
+    }
+}
+
+// A running function.
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // The stack slot of its slot 0, which holds the closure itself.
+    base: usize,
+    // Where its constants start in `Vm::constants`.
+    constants: usize,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read_u16(chunk: &Chunk, ip: &mut usize) -> u16 {
+    let value = u16::from_be_bytes([chunk.code[*ip], chunk.code[*ip + 1]]);
+    *ip += 2;
+    value
+}
+
+fn binary_operator(op: OpCode) -> BinaryOp {
+    match op {
+        OpCode::Equal => BinaryOp::Equal,
+        OpCode::NotEqual => BinaryOp::NotEqual,
+        OpCode::Greater => BinaryOp::Greater,
+        OpCode::GreaterEqual => BinaryOp::GreaterEqual,
+        OpCode::Less => BinaryOp::Less,
+        OpCode::LessEqual => BinaryOp::LessEqual,
+        OpCode::Add => BinaryOp::Add,
+        OpCode::Subtract => BinaryOp::Subtract,
+        OpCode::Multiply => BinaryOp::Multiply,
+        OpCode::Divide => BinaryOp::Divide,
+        _ => unreachable!("{:?} isn't a binary operator", op),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+    use crate::expr::Ast;
+    use crate::native::NativeFunction;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use std::cell::RefCell;
+
+    // Runs a program, then returns the value of global `result`.
+    fn run(source: &str) -> anyhow::Result<Value> {
+        run_with(Vm::new(), source).map(|(value, _)| value)
+    }
+
+    // Like `run`, but with a configured VM, which is also returned.
+    fn run_with(mut vm: Vm, source: &str) -> anyhow::Result<(Value, Vm)> {
+        let mut scanner = Scanner::new(source);
+        let mut ast = Ast::new();
+        let statements = Parser::new(scanner.scan_tokens(), &mut ast).parse_program()?;
+        let chunk = compiler::compile(&ast, &statements)?;
+        vm.run(&chunk)?;
+        let result = vm
+            .globals
+            .get(&Symbol::intern("result"))
+            .copied()
+            .unwrap_or(VmValue::nil());
+        Ok((vm.to_value(result), vm))
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(
+            run("var result = (1 + 2) * -3 == -9;").unwrap(),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            run("var result = nil ? 1 : (2, \"a\" + \"b\");").unwrap(),
+            Value::String("ab".into())
+        );
+        assert_eq!(
+            run("var result = nil or false and -nil;").unwrap(),
+            Value::Bool(false)
+        );
+    }
+
+    #[test]
+    fn locals() {
+        assert_eq!(
+            run("var result; { var a = 1; { var a = a + 1; result = a; } }").unwrap(),
+            Value::Number(2.0)
+        );
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(
+            run("var result = 0; for (var i = 0; i < 5; i = i + 1) result = result + i;").unwrap(),
+            Value::Number(10.0)
+        );
+        assert_eq!(
+            run("var result = 1; while (result < 100) result = result * 2;").unwrap(),
+            Value::Number(128.0)
+        );
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert_eq!(
+            run("var a = 1;\nprint a + nil;").unwrap_err().to_string(),
+            "[line 2] Operands must be two numbers or two strings."
+        );
+        assert_eq!(
+            run("undefined = 1;").unwrap_err().to_string(),
+            "[line 1] Undefined variable 'undefined'."
+        );
+    }
+
+    #[test]
+    fn natives() {
+        assert_eq!(
+            run("var result = Math.max(len(\"ab\" + \"c\"), num(\"2\"));").unwrap(),
+            Value::Number(3.0)
+        );
+        assert_eq!(
+            run("var result = Math.sqrt == Math.sqrt and clock != str;").unwrap(),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            run("var str = 1; var result = str;").unwrap(),
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            run("var a = 1;\nvar result = Math.pow(a,\n2,\n3);")
+                .unwrap_err()
+                .to_string(),
+            "[line 4] Expected 2 arguments but got 3."
+        );
+        assert_eq!(
+            run("clock = 1;").unwrap_err().to_string(),
+            "[line 1] Undefined variable 'clock'."
+        );
+        assert_eq!(
+            run("print 1 .x;").unwrap_err().to_string(),
+            "[line 1] Only instances, lists, maps and namespaces have properties."
+        );
+    }
+
+    #[test]
+    fn closures() {
+        assert_eq!(
+            run("var make = fun () { var n = 0; return fun () { n = n + 1; return n; }; }; var counter = make(); counter(); var result = counter();").unwrap(),
+            Value::Number(2.0)
+        );
+        // Both closures share the variable, even once it's off the stack.
+        assert_eq!(
+            run("var get; var set; { var a = 1; get = fun () { return a; }; set = fun (value) { a = value; }; } set(5); var result = get();").unwrap(),
+            Value::Number(5.0)
+        );
+        // Through a function in between that doesn't use it itself.
+        assert_eq!(
+            run("var result = fun (a) { return fun () { return fun () { return a; }; }; }(3)()();")
+                .unwrap(),
+            Value::Number(3.0)
+        );
+        assert_eq!(
+            run("var f = fun (a) {\n  return a + nil;\n};\nf(1);")
+                .unwrap_err()
+                .to_string(),
+            "[line 2] Operands must be two numbers or two strings."
+        );
+        assert_eq!(
+            run("var f = fun (a) {};\nf();").unwrap_err().to_string(),
+            "[line 2] Expected 1 arguments but got 0."
+        );
+        let err = run("var f = fun () { return f(); };\nf();").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LimitExceeded>().map(|err| err.kind),
+            Some(LimitKind::CallDepth)
+        );
+    }
+
+    #[test]
+    fn failed_runs_are_cleaned_up() {
+        let compile = |source: &str| {
+            let mut scanner = Scanner::new(source);
+            let mut ast = Ast::new();
+            let statements = Parser::new(scanner.scan_tokens(), &mut ast)
+                .parse_program()
+                .unwrap();
+            compiler::compile(&ast, &statements).unwrap()
+        };
+        let mut vm = Vm::new();
+        let failing = "var f; { var a = 1; f = fun () { return a; }; a = 2; fun () { nil(); }(); }";
+        assert!(vm.run(&compile(failing)).is_err());
+        assert_eq!(vm.call_depth, 0);
+        assert!(vm.open_upvalues.is_empty());
+        // The upvalue was closed with the value it had when the error happened.
+        let f = vm.get_global(Symbol::intern("f")).unwrap();
+        assert_eq!(vm.call(&f, &[]).unwrap(), Value::Number(2.0));
+    }
+
+    #[test]
+    fn gc_stress() {
+        let mut vm = Vm::new();
+        vm.set_gc_stress(true);
+        let (result, vm) = run_with(
+            vm,
+            "var result = \"\"; { var s = \"x\"; for (var i = 0; i < 4; i = i + 1) result = result + s; }",
+        )
+        .unwrap();
+        assert_eq!(result, Value::String("xxxx".into()));
+
+        let stats = vm.gc_stats();
+        assert!(stats.collections >= stats.objects_allocated);
+        // Each string `result` held before is garbage by now.
+        assert!(stats.objects_freed > 0);
+        assert_eq!(vm.heap.len(), stats.objects_allocated - stats.objects_freed);
+    }
+
+    #[test]
+    fn unreachable_strings_are_freed() {
+        let (_, mut vm) = run_with(Vm::new(), "var result = \"a\" + \"b\"; result = nil;").unwrap();
+        // The constants `"result"`, `"a"` and `"b"`, and then `"ab"`.
+        assert_eq!(vm.heap.len(), 4);
+        vm.collect_garbage();
+        assert!(vm.heap.is_empty());
+    }
+
+    #[test]
+    fn closures_that_capture_themselves_are_freed() {
+        let (result, mut vm) = run_with(
+            Vm::new(),
+            "for (var i = 0; i < 100; i = i + 1) { var f; f = fun () { return f; }; }
+            var get; { var xs = [1]; get = fun () { return xs; }; }
+            var result = get() == get();",
+        )
+        .unwrap();
+        // Reading a closed upvalue gives the object that's in it.
+        assert_eq!(result, Value::Bool(true));
+        vm.globals.clear();
+        vm.collect_garbage();
+        assert!(vm.heap.is_empty());
+    }
+
+    #[test]
+    fn cyclic_garbage_is_collected() {
+        // Each list holds a native of its own, which keeps `alive`'s count up
+        // until the list is freed.
+        let alive = Rc::new(());
+        let mut natives = stdlib::core();
+        let tracker = alive.clone();
+        natives.register("token", 0, move |_| {
+            let token = tracker.clone();
+            let native = NativeFunction::new("token", 0, move |_| {
+                let _ = &token;
+                Ok(Value::Nil)
+            });
+            Ok(Value::Native(Rc::new(native)))
+        });
+        let (_, mut vm) = run_with(
+            Vm::with_natives(natives),
+            "for (var i = 0; i < 20000; i = i + 1) { var xs = [token(), 2, 3, 4, 5, 6, 7, 8]; xs.push(xs); }",
+        )
+        .unwrap();
+        let stats = vm.gc_stats();
+        assert!(stats.peak_bytes < 2 * 1024 * 1024, "{:?}", stats);
+        vm.collect_garbage();
+        // Just `alive` and the registry's `token`.
+        assert_eq!(Rc::strong_count(&alive), 2);
+    }
+
+    #[test]
+    fn lists_that_contain_themselves_are_freed() {
+        let (result, mut vm) = run_with(
+            Vm::new(),
+            "var xs = [1]; xs.push(xs); var result = str(xs) + \" \" + str(xs[1].len());",
+        )
+        .unwrap();
+        assert_eq!(result, Value::String("[1, [...]] 2".into()));
+        vm.globals.clear();
+        vm.collect_garbage();
+        assert!(vm.heap.is_empty());
+    }
+
+    #[test]
+    fn maps_that_contain_themselves_are_freed() {
+        let (result, mut vm) = run_with(
+            Vm::new(),
+            "var m = {}; m[m] = m; m[\"list\"] = [m];
+            var result = str(m) + \" \" + str(m[m][\"list\"][0].len());",
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            Value::String("{{...}: {...}, list: [{...}]} 2".into())
+        );
+        vm.globals.clear();
+        vm.collect_garbage();
+        assert!(vm.heap.is_empty());
+    }
+
+    #[test]
+    fn natives_get_copies() {
+        // Natives that keep a copy keep it intact, and the others' copies are
+        // freed even when they contain themselves.
+        let kept = Rc::new(RefCell::new(Vec::new()));
+        let weak = Rc::new(RefCell::new(Vec::new()));
+        let mut natives = stdlib::core();
+        let keep = kept.clone();
+        natives.register("keep", 1, move |arguments| {
+            keep.borrow_mut().push(arguments[0].clone());
+            Ok(Value::Nil)
+        });
+        let watch = weak.clone();
+        natives.register("watch", 1, move |arguments| {
+            if let Value::List(list) = &arguments[0] {
+                watch.borrow_mut().push(Rc::downgrade(list));
+            }
+            Ok(Value::Nil)
+        });
+        let (result, _vm) = run_with(
+            Vm::with_natives(natives),
+            "var xs = [1]; xs.push(xs); keep(xs); watch(xs); xs.push(2); var result = xs;",
+        )
+        .unwrap();
+        assert_eq!(result.to_string(), "[1, [...], 2]");
+        assert_eq!(kept.borrow()[0].to_string(), "[1, [...]]");
+        assert!(weak.borrow()[0].upgrade().is_none());
+    }
+
+    #[test]
+    fn closures_from_another_vm_cant_be_called() {
+        let (_, mut other) = run_with(Vm::new(), "var f = fun () { return 1; };").unwrap();
+        let f = other.get_global(Symbol::intern("f")).unwrap();
+        let mut vm = Vm::new();
+        vm.set_global(Symbol::intern("f"), f.clone());
+        assert_eq!(
+            vm.call(&f, &[]).unwrap_err().to_string(),
+            "Can't call a function made by another interpreter."
+        );
+        assert_eq!(other.call(&f, &[]).unwrap(), Value::Number(1.0));
+    }
+
+    #[test]
+    fn strings_off_the_heap_share_their_contents() {
+        let mut vm = Vm::new();
+        let value = vm.from_value(Value::String("shared".into()));
+        let Value::String(off_heap) = vm.to_value(value) else {
+            panic!("expected a string");
+        };
+        let Object::String(on_heap) = vm.heap.get(value.as_object().unwrap()) else {
+            panic!("expected a string object");
+        };
+        assert!(std::ptr::eq(off_heap.as_str(), on_heap.as_str()));
+    }
+}
@@ -0,0 +1,265 @@
+use crate::heap::ObjRef;
+use std::hash::Hash;
+use std::hash::Hasher;
+
+/// A value on the VM's stack or in its globals. Unlike `value::Value` it's
+/// `Copy`: strings (and anything else that isn't a number, bool or nil) live
+/// on the VM's `Heap` and are referred to by handle.
+///
+/// By default this wraps `Unpacked`. With the `nan-boxing` feature it's a
+/// single `u64` instead, see below. Either way, build one with the
+/// constructors and take it apart with `unpack`.
+#[derive(Clone, Copy)]
+pub struct VmValue(Repr);
+
+/// What a `VmValue` holds, for matching on.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Unpacked {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Object(ObjRef),
+}
+
+impl VmValue {
+    pub fn nil() -> Self {
+        Self::pack(Unpacked::Nil)
+    }
+
+    pub fn bool(bb: bool) -> Self {
+        Self::pack(Unpacked::Bool(bb))
+    }
+
+    pub fn number(number: f64) -> Self {
+        Self::pack(Unpacked::Number(number))
+    }
+
+    pub fn object(object: ObjRef) -> Self {
+        Self::pack(Unpacked::Object(object))
+    }
+
+    pub fn as_object(self) -> Option<ObjRef> {
+        match self.unpack() {
+            Unpacked::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(self) -> Option<f64> {
+        match self.unpack() {
+            Unpacked::Number(number) => Some(number),
+            _ => None,
+        }
+    }
+
+    /// `nil` and `false` are falsey, everything else is truthy.
+    pub fn is_truthy(self) -> bool {
+        !matches!(self.unpack(), Unpacked::Nil | Unpacked::Bool(false))
+    }
+}
+
+/// A `VmValue` that can be hashed, for the VM's map keys. Like
+/// `value::HashKey`, two keys are equal exactly when their values are `==`,
+/// and NaN can't be one.
+///
+/// Objects are keys by `ObjRef`, which is by identity: equal strings are the
+/// same object, and the heap never has two objects for anything else.
+#[derive(Debug, Clone, Copy)]
+pub struct VmKey(VmValue);
+
+impl VmKey {
+    pub fn new(value: VmValue) -> anyhow::Result<Self> {
+        if value.as_number().is_some_and(f64::is_nan) {
+            anyhow::bail!("Map keys can't be NaN.");
+        }
+        Ok(VmKey(value))
+    }
+
+    pub fn value(self) -> VmValue {
+        self.0
+    }
+}
+
+impl PartialEq for VmKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+// `new` keeps out NaN, the only value that isn't equal to itself.
+impl Eq for VmKey {}
+
+impl Hash for VmKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.0.unpack() {
+            Unpacked::Nil => 0u8.hash(state),
+            Unpacked::Bool(bb) => (1u8, bb).hash(state),
+            // Matches `-0` too, which is `== 0`, so both hash alike.
+            Unpacked::Number(0.0) => (2u8, 0u64).hash(state),
+            Unpacked::Number(number) => (2u8, number.to_bits()).hash(state),
+            Unpacked::Object(object) => (3u8, object.index()).hash(state),
+        }
+    }
+}
+
+impl std::fmt::Debug for VmValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.unpack().fmt(f)
+    }
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+type Repr = Unpacked;
+
+#[cfg(not(feature = "nan-boxing"))]
+impl VmValue {
+    #[inline]
+    fn pack(unpacked: Unpacked) -> Self {
+        VmValue(unpacked)
+    }
+
+    #[inline]
+    pub fn unpack(self) -> Unpacked {
+        self.0
+    }
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+impl PartialEq for VmValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+// NaN-boxing: a double whose exponent bits are all set, along with the top
+// bit of the mantissa (`QNAN`), is a quiet NaN, and the other 51 bits are free.
+// Arithmetic never produces such a NaN with any of them set, so they can hold
+// everything that isn't a number instead:
+//
+// - `nil` and the bools are `QNAN` plus a small tag.
+// - Objects also have the sign bit set, with their `ObjRef` index in the low
+//   32 bits.
+//
+// Anything without all of `QNAN` set is a number.
+#[cfg(feature = "nan-boxing")]
+type Repr = u64;
+
+#[cfg(feature = "nan-boxing")]
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+#[cfg(feature = "nan-boxing")]
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+#[cfg(feature = "nan-boxing")]
+const NIL: u64 = QNAN | 1;
+#[cfg(feature = "nan-boxing")]
+const FALSE: u64 = QNAN | 2;
+#[cfg(feature = "nan-boxing")]
+const TRUE: u64 = QNAN | 3;
+
+#[cfg(feature = "nan-boxing")]
+impl VmValue {
+    #[inline]
+    fn pack(unpacked: Unpacked) -> Self {
+        VmValue(match unpacked {
+            Unpacked::Nil => NIL,
+            Unpacked::Bool(false) => FALSE,
+            Unpacked::Bool(true) => TRUE,
+            // Some NaNs would look like a tagged value, so all of them are
+            // stored as the canonical one.
+            Unpacked::Number(number) if number.is_nan() => f64::NAN.to_bits(),
+            Unpacked::Number(number) => number.to_bits(),
+            Unpacked::Object(object) => SIGN_BIT | QNAN | object.index() as u64,
+        })
+    }
+
+    #[inline]
+    pub fn unpack(self) -> Unpacked {
+        let bits = self.0;
+        if bits & QNAN != QNAN {
+            Unpacked::Number(f64::from_bits(bits))
+        } else if bits & SIGN_BIT != 0 {
+            Unpacked::Object(ObjRef::from_index((bits & u32::MAX as u64) as usize))
+        } else {
+            match bits {
+                NIL => Unpacked::Nil,
+                FALSE => Unpacked::Bool(false),
+                TRUE => Unpacked::Bool(true),
+                _ => unreachable!("not a NaN-boxed value: {:#x}", bits),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+impl PartialEq for VmValue {
+    // Everything but numbers is equal exactly when the bits are, and numbers
+    // follow IEEE (so `NaN != NaN`), like the enum.
+    fn eq(&self, other: &Self) -> bool {
+        match (self.unpack(), other.unpack()) {
+            (Unpacked::Number(left), Unpacked::Number(right)) => left == right,
+            _ => self.0 == other.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let values = [
+            Unpacked::Nil,
+            Unpacked::Bool(false),
+            Unpacked::Bool(true),
+            Unpacked::Number(0.0),
+            Unpacked::Number(-1.5),
+            Unpacked::Number(f64::INFINITY),
+            Unpacked::Number(f64::MIN_POSITIVE),
+            Unpacked::Object(ObjRef::from_index(0)),
+            Unpacked::Object(ObjRef::from_index(u32::MAX as usize)),
+        ];
+        for value in values {
+            assert_eq!(VmValue::pack(value).unpack(), value);
+        }
+    }
+
+    #[test]
+    fn equality() {
+        assert_eq!(VmValue::number(1.0), VmValue::number(1.0));
+        assert_ne!(VmValue::number(f64::NAN), VmValue::number(f64::NAN));
+        assert_ne!(VmValue::nil(), VmValue::bool(false));
+        assert_ne!(VmValue::number(0.0), VmValue::object(ObjRef::from_index(0)));
+        // Whatever the NaN, it's still a number.
+        assert!(matches!(
+            VmValue::number(-f64::NAN).unpack(),
+            Unpacked::Number(number) if number.is_nan()
+        ));
+    }
+
+    #[test]
+    fn keys() {
+        let hash = |value| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            VmKey::new(value).unwrap().hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(VmValue::number(0.0)), hash(VmValue::number(-0.0)));
+        assert_eq!(
+            VmKey::new(VmValue::number(0.0)).unwrap(),
+            VmKey::new(VmValue::number(-0.0)).unwrap()
+        );
+        assert_ne!(hash(VmValue::nil()), hash(VmValue::bool(false)));
+        assert_eq!(
+            VmKey::new(VmValue::number(f64::NAN))
+                .unwrap_err()
+                .to_string(),
+            "Map keys can't be NaN."
+        );
+    }
+
+    #[test]
+    fn size() {
+        let expected = if cfg!(feature = "nan-boxing") { 8 } else { 16 };
+        assert_eq!(std::mem::size_of::<VmValue>(), expected);
+    }
+}
@@ -0,0 +1,216 @@
+//! Runs every program in `tests/programs` with each backend, checking its
+//! output against the `// expect: <line>` comments in it. A program can also
+//! end with a `// expect runtime error: <message>` comment.
+
+use std::path::Path;
+use std::process::Command;
+
+// `loxc` compiles the program to a file first, and runs that. `gc-stress` is
+// the VM, collecting garbage on every allocation.
+const BACKENDS: [&str; 4] = ["tree-walk", "vm", "loxc", "gc-stress"];
+
+struct Expectations {
+    output: Vec<String>,
+    runtime_error: Option<String>,
+}
+
+fn expectations(source: &str) -> Expectations {
+    let mut output = Vec::new();
+    let mut runtime_error = None;
+    for line in source.lines() {
+        if let Some((_, expected)) = line.split_once("// expect: ") {
+            output.push(expected.to_owned());
+        } else if let Some((_, message)) = line.split_once("// expect runtime error: ") {
+            runtime_error = Some(message.to_owned());
+        }
+    }
+    Expectations {
+        output,
+        runtime_error,
+    }
+}
+
+fn run_program(path: &Path, backend: &str) {
+    let source = std::fs::read_to_string(path).unwrap();
+    let expected = expectations(&source);
+    let output = match backend {
+        "loxc" => {
+            let compiled = std::env::temp_dir().join(format!(
+                "{}-{}.loxc",
+                path.file_stem().unwrap().to_str().unwrap(),
+                std::process::id()
+            ));
+            let status = Command::new(env!("CARGO_BIN_EXE_main"))
+                .arg("compile")
+                .arg(path)
+                .arg("-o")
+                .arg(&compiled)
+                .status()
+                .unwrap();
+            assert!(status.success(), "compiling {}", path.display());
+            let output = Command::new(env!("CARGO_BIN_EXE_main"))
+                .arg(&compiled)
+                .output()
+                .unwrap();
+            std::fs::remove_file(&compiled).unwrap();
+            output
+        }
+        "gc-stress" => Command::new(env!("CARGO_BIN_EXE_main"))
+            .args(["--backend", "vm", "--gc-stress"])
+            .arg(path)
+            .output()
+            .unwrap(),
+        _ => Command::new(env!("CARGO_BIN_EXE_main"))
+            .arg("--backend")
+            .arg(backend)
+            .arg(path)
+            .output()
+            .unwrap(),
+    };
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let context = format!("{} ({})\nstderr:\n{}", path.display(), backend, stderr);
+
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        expected.output,
+        "{}",
+        context
+    );
+    match expected.runtime_error {
+        Some(message) => {
+            assert!(!output.status.success(), "{}", context);
+            assert!(
+                stderr.contains(&format!("Runtime error: {}", message)),
+                "{}",
+                context
+            );
+        }
+        None => assert!(output.status.success(), "{}", context),
+    }
+}
+
+#[test]
+fn programs() {
+    let mut paths: Vec<_> =
+        std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "lox"))
+            .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    for path in &paths {
+        for backend in BACKENDS {
+            run_program(path, backend);
+        }
+    }
+}
+
+#[test]
+fn vm_trace() {
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/programs/expressions.lox"
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_main"))
+        .args(["--backend", "vm", "--trace", path])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // `print 1 + 2 * 3;`, right before multiplying.
+    assert!(stderr.contains("          [ 1 ][ 2 ][ 3 ]\n0006    | MULTIPLY         *\n"));
+}
+
+#[test]
+fn max_steps() {
+    let path = std::env::temp_dir().join(format!("max_steps-{}.lox", std::process::id()));
+    std::fs::write(&path, "print 1;\nwhile (true) {}\n").unwrap();
+    for backend in ["tree-walk", "vm"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_main"))
+            .args(["--backend", backend, "--max-steps", "100"])
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            stderr.contains("Runtime error: [line 2] Step limit exceeded."),
+            "{} ({})",
+            stderr,
+            backend
+        );
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn max_heap_bytes() {
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/programs/expressions.lox"
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_main"))
+        .args(["--max-heap-bytes", "100000", path])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("--max-heap-bytes only works with --backend vm."));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_main"))
+        .args(["--backend", "vm", "--max-heap-bytes", "100000", path])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+}
+
+#[test]
+fn sandbox() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/natives.lox");
+    let output = Command::new(env!("CARGO_BIN_EXE_main"))
+        .args(["--sandbox", path])
+        .output()
+        .unwrap();
+    // Natives that don't need a capability still work.
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("2\n4\n"));
+
+    let path = std::env::temp_dir().join(format!("sandbox-{}.lox", std::process::id()));
+    std::fs::write(&path, "print 1;\nprint readFile(\"Cargo.toml\");\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_main"))
+        .args(["--sandbox"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "Runtime error: [line 2] Capability denied: readFile() needs the 'filesystem' capability."
+        ),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn gc_stats() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs/strings.lox");
+    let output = Command::new(env!("CARGO_BIN_EXE_main"))
+        .args(["--backend", "vm", "--gc-stress", "--gc-stats", path])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("collections:"), "{}", stderr);
+    assert!(!stderr.contains("objects freed:     0\n"), "{}", stderr);
+}